@@ -0,0 +1,18 @@
+//! Helpers shared by the benchmarks.
+
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 10;
+
+/// Runs `f` a number of times, printing the average time it takes and the
+/// number of results it returns, labelled with `unit`.
+pub fn bench<F>(name: &str, unit: &str, mut f: F) where F: FnMut() -> usize {
+    let mut total = Duration::from_secs(0);
+    let mut results = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        results = f();
+        total += start.elapsed();
+    }
+    println!("{:<32} {:>8} {} {:>12.3?}", name, results, unit, total / RUNS);
+}
@@ -0,0 +1,69 @@
+//! Compares the maps that a state can keep its bindings in, for states with
+//! 10, 1k and 100k variables.  Run with `cargo bench --bench maps`.
+//!
+//! Keys are added both in a shuffled order and in order, as a state's
+//! variables are bound in order of their indices.
+
+extern crate rslogic;
+
+mod common;
+
+use common::bench;
+use rslogic::btmap::BtMap;
+use rslogic::goal::{Goal, unify_val};
+use rslogic::radix::RadixMap;
+use rslogic::state::{Backing, IndexMap, State};
+
+const SIZES: [usize; 3] = [10, 1_000, 100_000];
+
+/// The keys `0..n` in a shuffled order, the same for every run.
+fn shuffled(n: usize) -> Vec<usize> {
+    let mut keys: Vec<usize> = (0..n).collect();
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    for i in (1..n).rev() {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        keys.swap(i, (seed % (i as u64 + 1)) as usize);
+    }
+    keys
+}
+
+fn build<M>(keys: &[usize]) -> M where M: IndexMap<usize> {
+    keys.iter().fold(M::empty(), |map, &key| map.replace(key, key))
+}
+
+/// Measures binding, looking up and rebinding each of `n` keys in a map.
+fn bench_map<M>(name: &str, n: usize) where M: IndexMap<usize> {
+    let keys = shuffled(n);
+    bench(&format!("{} bind {}", name, n), "items", || build::<M>(&keys).len());
+    let ordered: Vec<usize> = (0..n).collect();
+    bench(&format!("{} bind in order {}", name, n), "items", || build::<M>(&ordered).len());
+
+    let map: M = build(&keys);
+    bench(&format!("{} lookup {}", name, n), "items", || keys.iter().filter(|key| map.get(key).is_some()).count());
+    bench(&format!("{} rebind {}", name, n), "items", || keys.iter().filter(|&&key| !map.replace(key, 0).is_empty()).count());
+}
+
+/// Measures binding each of `n` variables of a state to a value.
+fn bench_state(name: &str, backing: Backing, n: usize) {
+    let (vars, state) = (0..n).fold((Vec::new(), State::<usize>::empty_in(backing)), |(mut vars, s), _| {
+        let (v, s) = s.make_var();
+        vars.push(v);
+        (vars, s)
+    });
+    bench(&format!("{} state bind {}", name, n), "items", || {
+        let s = vars.iter().enumerate().fold(state.clone(), |s, (i, v)| unify_val(v, i).eval(&s).pop().unwrap());
+        vars.iter().filter(|v| s.get(v).is_some()).count()
+    });
+}
+
+fn main() {
+    for &n in SIZES.iter() {
+        bench_map::<BtMap<usize, usize>>("btmap", n);
+        bench_map::<RadixMap<usize>>("radix", n);
+        bench_state("btmap", Backing::Tree, n);
+        bench_state("radix", Backing::Radix, n);
+        println!();
+    }
+}
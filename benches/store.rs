@@ -0,0 +1,67 @@
+//! Compares evaluating goals with persistent states to solving them in the
+//! mutable stores.  Run with `cargo bench --bench store`.
+
+extern crate rslogic;
+
+mod common;
+
+use common::bench;
+use rslogic::goal::{Goal, conj, disj, unify_val, unify_vars};
+use rslogic::state::{State, Var};
+use rslogic::store::{Store, Trail, Persistent};
+use rslogic::term::Term;
+
+/// Counts the solutions of the goal in the store, without taking snapshots of them.
+fn count<T, G>(goal: &G, store: &mut dyn Store<T>) -> usize where T: PartialEq + rslogic::state::Unif<T>, G: Goal<T> {
+    let mut count = 0;
+    goal.solve(store, &mut |_: &mut dyn Store<T>| {
+        count += 1;
+        false
+    });
+    count
+}
+
+/// A deep chain of choices: each variable is 0 or 1, and is unified with the next.
+fn choices(depth: usize) -> (Box<dyn Goal<i32>>, State<i32>) {
+    let mut s = State::empty();
+    let mut vars: Vec<Var> = Vec::new();
+    for _ in 0..depth * 2 {
+        let (v, next) = s.make_var();
+        vars.push(v);
+        s = next;
+    }
+    let mut goal: Box<dyn Goal<i32>> = Box::new(unify_val(&vars[0], 0));
+    for pair in vars.chunks(2).skip(1) {
+        let step = conj(disj(unify_val(&pair[0], 0), unify_val(&pair[0], 1)), unify_vars(&pair[0], &pair[1]));
+        goal = Box::new(conj(goal, step));
+    }
+    (goal, s)
+}
+
+/// Unifies a long list of fresh variables with a list of integers.
+fn lists(length: usize) -> (Box<dyn Goal<Term>>, State<Term>) {
+    let mut s = State::empty();
+    let mut items = Vec::new();
+    for _ in 0..length {
+        let (v, next) = s.make_var();
+        items.push(Term::Var(v));
+        s = next;
+    }
+    let (x, s) = s.make_var();
+    let ints = (0..length as i64).map(Term::Int).collect();
+    let goal = conj(unify_val(&x, Term::list(items, None)), unify_val(&x, Term::list(ints, None)));
+    (Box::new(goal), s)
+}
+
+
+fn main() {
+    let (goal, s) = choices(14);
+    bench("choices: eval (persistent)", "answers", || goal.eval(&s).len());
+    bench("choices: solve (persistent)", "answers", || count(&goal, &mut Persistent::new(&s)));
+    bench("choices: solve (trail)", "answers", || count(&goal, &mut Trail::from_state(&s)));
+
+    let (goal, s) = lists(2000);
+    bench("lists: eval (persistent)", "answers", || goal.eval(&s).len());
+    bench("lists: solve (persistent)", "answers", || count(&goal, &mut Persistent::new(&s)));
+    bench("lists: solve (trail)", "answers", || count(&goal, &mut Trail::from_state(&s)));
+}
@@ -0,0 +1,377 @@
+//! # Aggregation
+//!
+//! Aggregation goals run a sub-goal to completion, collect the values that a
+//! template variable takes in its solutions, and unify an aggregate of them
+//! (a list, a count, a sum, and so on) with a result variable.
+//!
+//! ```
+//! use rslogic::state::State;
+//! use rslogic::goal::{Goal, disj, unify_val};
+//! use rslogic::aggregate::{count, sum};
+//!
+//! let s = State::<i64>::empty();
+//! let (x, s) = s.make_var();
+//! let (n, s) = s.make_var();
+//! let (total, s) = s.make_var();
+//! let g = || disj(unify_val(&x, 3), disj(unify_val(&x, 4), unify_val(&x, 5)));
+//!
+//! let results = count(g(), &n).eval(&s);
+//! assert_eq!(results[0].get(&n), Some(&3));
+//! let results = sum(&x, g(), &total).eval(&s);
+//! assert_eq!(results[0].get(&total), Some(&12));
+//! ```
+//!
+//! Lists can only be built from values that can represent them, such as
+//! `term::Term`; see `Collect`.
+
+use error::Error;
+use goal::{Goal, check_vars};
+use search::Search;
+use state::{Unif, Reify, Var, State, PossibleStates, expect};
+use std::cmp::Ordering;
+use term::Term;
+
+/// Values that can represent a list of values.
+pub trait Collect: Sized {
+    /// Creates a list of the items.
+    fn collect(items: Vec<Self>) -> Self;
+}
+
+impl Collect for Term {
+    fn collect(items: Vec<Term>) -> Term {
+        Term::list(items, None)
+    }
+}
+
+/// Values that can be counted, added and compared.
+pub trait Numeric: Sized {
+    /// Converts a count to a value, or returns `None` if it is out of range.
+    fn from_count(n: usize) -> Option<Self>;
+
+    /// Adds two values, or returns `None` if they are not numbers or the sum
+    /// is out of range.
+    fn add(&self, other: &Self) -> Option<Self>;
+
+    /// Compares two values as numbers, or returns `None` if they cannot be compared.
+    fn compare(&self, other: &Self) -> Option<Ordering>;
+}
+
+macro_rules! numeric_int {
+    ( $t:ty ) => {
+        impl Numeric for $t {
+            fn from_count(n: usize) -> Option<$t> {
+                <$t as ::std::convert::TryFrom<usize>>::try_from(n).ok()
+            }
+
+            fn add(&self, other: &$t) -> Option<$t> {
+                self.checked_add(*other)
+            }
+
+            fn compare(&self, other: &$t) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+    }
+}
+
+macro_rules! numeric_float {
+    ( $t:ty ) => {
+        impl Numeric for $t {
+            fn from_count(n: usize) -> Option<$t> {
+                Some(n as $t)
+            }
+
+            fn add(&self, other: &$t) -> Option<$t> {
+                Some(self + other)
+            }
+
+            fn compare(&self, other: &$t) -> Option<Ordering> {
+                self.partial_cmp(other)
+            }
+        }
+    }
+}
+
+numeric_int!(i8);
+numeric_int!(i16);
+numeric_int!(i32);
+numeric_int!(i64);
+numeric_int!(isize);
+numeric_int!(u8);
+numeric_int!(u16);
+numeric_int!(u32);
+numeric_int!(u64);
+numeric_int!(usize);
+numeric_float!(f32);
+numeric_float!(f64);
+
+impl Numeric for Term {
+    fn from_count(n: usize) -> Option<Term> {
+        i64::from_count(n).map(Term::Int)
+    }
+
+    fn add(&self, other: &Term) -> Option<Term> {
+        match (self, other) {
+            (Term::Int(a), Term::Int(b)) => a.checked_add(*b).map(Term::Int),
+            (Term::Int(a), Term::Float(b)) => Some(Term::Float(*a as f64 + b)),
+            (Term::Float(a), Term::Int(b)) => Some(Term::Float(a + *b as f64)),
+            (Term::Float(a), Term::Float(b)) => Some(Term::Float(a + b)),
+            _ => None,
+        }
+    }
+
+    fn compare(&self, other: &Term) -> Option<Ordering> {
+        match (self, other) {
+            (Term::Int(a), Term::Int(b)) => Some(a.cmp(b)),
+            (Term::Int(a), Term::Float(b)) => (*a as f64).partial_cmp(b),
+            (Term::Float(a), Term::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Term::Float(a), Term::Float(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+
+/// Computes an aggregate from the number of solutions and the items collected
+/// from them, or returns `None` if there is none.
+type Fold<T> = Box<dyn Fn(usize, Vec<T>) -> Option<T>>;
+
+/// Evaluating an `Aggregate` goal finds all the solutions of its sub-goal,
+/// and unifies the result variable with an aggregate of the values of the
+/// template variable in them.
+///
+/// If the goal groups its solutions by some variables, there is one resulting
+/// state for each distinct combination of their values, in the order in which
+/// they were first found, in which the grouping variables are bound to those
+/// values and the result to the aggregate of the solutions in the group.
+///
+/// A solution in which the template or a grouping variable is unbound has no
+/// value to collect, and is left out.
+pub struct Aggregate<T, G> where T: PartialEq + Unif<T> {
+    template: Option<Var>,
+    group: Vec<Var>,
+    goal: G,
+    result: Var,
+    fold: Fold<T>,
+    fail_if_empty: bool,
+}
+
+impl<T, G> Aggregate<T, G> where T: PartialEq + Unif<T> + Reify<T> + Clone {
+    /// Groups the solutions of the sub-goal found from the state, and unifies
+    /// the result with the aggregate of each group.
+    fn aggregate<I>(&self, state: &State<T>, answers: I) -> Result<PossibleStates<T>, Error> where I: IntoIterator<Item = State<T>> {
+        // each group has the values of the grouping variables, the number of solutions, and their items
+        let mut groups: Vec<(Vec<T>, usize, Vec<T>)> = Vec::new();
+        let mut vars = state.clone();
+        for answer in answers {
+            let key = match self.group.iter().map(|var| reify_var(var, &answer)).collect::<Result<Option<Vec<T>>, Error>>()? {
+                Some(key) => key,
+                None => continue,
+            };
+            let item = match self.template {
+                Some(ref template) => match reify_var(template, &answer)? {
+                    Some(item) => Some(item),
+                    None => continue,
+                },
+                None => None,
+            };
+            // the items may refer to variables created by the search
+            vars = vars.with_vars_of(&answer);
+            let index = match groups.iter().position(|group| group.0 == key) {
+                Some(index) => index,
+                None => {
+                    groups.push((key, 0, Vec::new()));
+                    groups.len() - 1
+                },
+            };
+            groups[index].1 += 1;
+            groups[index].2.extend(item);
+        }
+        if groups.is_empty() && !self.fail_if_empty && self.group.is_empty() {
+            groups.push((Vec::new(), 0, Vec::new()));
+        }
+
+        let mut results = PossibleStates::new();
+        for (key, solutions, items) in groups {
+            let value = match (self.fold)(solutions, items) {
+                Some(value) => value,
+                None => continue,
+            };
+            let mut states = vec![vars.clone()];
+            for (var, val) in self.group.iter().zip(key) {
+                states = try_each(&states, |s| s.try_unify_val(var, val.clone()))?;
+            }
+            results.append(&mut try_each(&states, |s| s.try_unify_val(&self.result, value.clone()))?);
+        }
+        Ok(results)
+    }
+}
+
+impl<T, G> Goal<T> for Aggregate<T, G> where T: PartialEq + Unif<T> + Reify<T> + Clone, G: Goal<T> {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        expect(self.aggregate(state, Search::new(&self.goal, state)))
+    }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        for var in self.template.iter().chain(&self.group).chain(Some(&self.result)) {
+            state.try_get(var)?;
+        }
+        check_vars(&self.goal, state)?;
+        self.aggregate(state, Search::new(&self.goal, state))
+    }
+}
+
+/// Returns the value of the variable in the state, with any variables it
+/// contains replaced by their values, or `None` if it is unbound.
+fn reify_var<T>(var: &Var, state: &State<T>) -> Result<Option<T>, Error> where T: PartialEq + Unif<T> + Reify<T> {
+    Ok(state.try_get(var)?.map(|value| value.reify(state)))
+}
+
+/// Returns all the states that result from applying `f` to each of the states.
+fn try_each<T, F>(states: &[State<T>], f: F) -> Result<PossibleStates<T>, Error>
+    where T: PartialEq + Unif<T>, F: Fn(&State<T>) -> Result<PossibleStates<T>, Error>
+{
+    let mut results = PossibleStates::new();
+    for state in states {
+        results.append(&mut f(state)?);
+    }
+    Ok(results)
+}
+
+/// Creates an `Aggregate` goal that unifies `result` with a list of the values
+/// of `template` in all the solutions of `goal`, in order.  If there are no
+/// solutions, the list is empty.
+pub fn findall<T, G>(template: &Var, goal: G, result: &Var) -> Aggregate<T, G> where T: PartialEq + Unif<T> + Reify<T> + Collect, G: Goal<T> {
+    Aggregate { template: Some(*template), group: Vec::new(), goal, result: *result, fold: Box::new(|_, items| Some(T::collect(items))), fail_if_empty: false }
+}
+
+/// Creates an `Aggregate` goal that, for each distinct combination of values
+/// of the `group` variables, unifies `result` with a list of the values of
+/// `template` in the solutions of `goal` with those values.  It fails if
+/// there are no solutions.
+pub fn bagof<T, G>(template: &Var, group: &[Var], goal: G, result: &Var) -> Aggregate<T, G> where T: PartialEq + Unif<T> + Reify<T> + Collect, G: Goal<T> {
+    Aggregate { template: Some(*template), group: group.to_vec(), goal, result: *result, fold: Box::new(|_, items| Some(T::collect(items))), fail_if_empty: true }
+}
+
+/// Like `bagof`, but the lists are sorted, without duplicates.
+pub fn setof<T, G>(template: &Var, group: &[Var], goal: G, result: &Var) -> Aggregate<T, G> where T: PartialEq + Unif<T> + Reify<T> + Collect + Ord, G: Goal<T> {
+    let fold = |_, mut items: Vec<T>| {
+        items.sort();
+        items.dedup();
+        Some(T::collect(items))
+    };
+    Aggregate { template: Some(*template), group: group.to_vec(), goal, result: *result, fold: Box::new(fold), fail_if_empty: true }
+}
+
+/// Creates an `Aggregate` goal that unifies `result` with the number of solutions of `goal`.
+pub fn count<T, G>(goal: G, result: &Var) -> Aggregate<T, G> where T: PartialEq + Unif<T> + Reify<T> + Numeric, G: Goal<T> {
+    Aggregate { template: None, group: Vec::new(), goal, result: *result, fold: Box::new(|solutions, _| T::from_count(solutions)), fail_if_empty: false }
+}
+
+/// Creates an `Aggregate` goal that unifies `result` with the sum of the
+/// values of `template` in all the solutions of `goal`.  It fails if any of
+/// the values is not a number, or the sum is out of range.
+pub fn sum<T, G>(template: &Var, goal: G, result: &Var) -> Aggregate<T, G> where T: PartialEq + Unif<T> + Reify<T> + Numeric, G: Goal<T> {
+    let fold = |_, items: Vec<T>| items.into_iter().try_fold(T::from_count(0)?, |total, item| total.add(&item));
+    Aggregate { template: Some(*template), group: Vec::new(), goal, result: *result, fold: Box::new(fold), fail_if_empty: false }
+}
+
+/// Creates an `Aggregate` goal that unifies `result` with the least of the
+/// values of `template` in the solutions of `goal`.  It fails if there are no
+/// solutions, or the values cannot be compared.
+pub fn min<T, G>(template: &Var, goal: G, result: &Var) -> Aggregate<T, G> where T: PartialEq + Unif<T> + Reify<T> + Numeric, G: Goal<T> {
+    Aggregate { template: Some(*template), group: Vec::new(), goal, result: *result, fold: Box::new(|_, items| extreme(items, Ordering::Less)), fail_if_empty: true }
+}
+
+/// Creates an `Aggregate` goal that unifies `result` with the greatest of the
+/// values of `template` in the solutions of `goal`.  It fails if there are no
+/// solutions, or the values cannot be compared.
+pub fn max<T, G>(template: &Var, goal: G, result: &Var) -> Aggregate<T, G> where T: PartialEq + Unif<T> + Reify<T> + Numeric, G: Goal<T> {
+    Aggregate { template: Some(*template), group: Vec::new(), goal, result: *result, fold: Box::new(|_, items| extreme(items, Ordering::Greater)), fail_if_empty: true }
+}
+
+/// Returns the item that compares as `which` to all the others.
+fn extreme<T>(items: Vec<T>, which: Ordering) -> Option<T> where T: Numeric {
+    let mut items = items.into_iter();
+    let mut best = items.next()?;
+    for item in items {
+        if item.compare(&best)? == which {
+            best = item;
+        }
+    }
+    Some(best)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use goal::{Goal, conj, disj, fail, unify_val};
+    use state::State;
+    use term::Term;
+    use super::{findall, bagof, setof, count, sum, min, max};
+
+    #[test]
+    fn test_numeric() {
+        let s = State::<i32>::empty();
+        let (x, s) = s.make_var();
+        let (r, s) = s.make_var();
+        let g = || disj(unify_val(&x, 7), disj(unify_val(&x, -2), unify_val(&x, 7)));
+
+        let value = |goal: &dyn Goal<i32>| goal.eval(&s).first().and_then(|s| s.get(&r).cloned());
+        assert_eq!(value(&count(g(), &r)), Some(3));
+        assert_eq!(value(&sum(&x, g(), &r)), Some(12));
+        assert_eq!(value(&min(&x, g(), &r)), Some(-2));
+        assert_eq!(value(&max(&x, g(), &r)), Some(7));
+
+        assert_eq!(value(&count(fail(), &r)), Some(0));
+        assert_eq!(value(&sum(&x, fail(), &r)), Some(0));
+        assert_eq!(value(&max(&x, fail(), &r)), None);
+
+        // the result is unified, not just bound
+        let s2 = unify_val(&r, 4).eval(&s).pop().unwrap();
+        assert!(count(g(), &r).eval(&s2).is_empty());
+    }
+
+    #[test]
+    fn test_lists() {
+        let s = State::<Term>::empty();
+        let (x, s) = s.make_var();
+        let (k, s) = s.make_var();
+        let (r, s) = s.make_var();
+        let pair = |key: &str, n: i64| conj(unify_val(&k, Term::atom(key)), unify_val(&x, Term::Int(n)));
+        let g = || disj(disj(pair("b", 3), pair("a", 1)), disj(pair("b", 2), pair("b", 3)));
+
+        let results = findall(&x, g(), &r).eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(Term::Var(r).reify(&results[0]).to_string(), "[3, 1, 2, 3]");
+        let results = findall(&x, fail(), &r).eval(&s);
+        assert_eq!(Term::Var(r).reify(&results[0]).to_string(), "[]");
+
+        let describe = |results: Vec<State<Term>>| -> Vec<String> {
+            results.iter().map(|s| format!("{}: {}", Term::Var(k).reify(s), Term::Var(r).reify(s))).collect()
+        };
+        assert_eq!(describe(bagof(&x, &[k], g(), &r).eval(&s)), vec!["b: [3, 2, 3]", "a: [1]"]);
+        assert_eq!(describe(setof(&x, &[k], g(), &r).eval(&s)), vec!["b: [2, 3]", "a: [1]"]);
+        assert!(bagof(&x, &[k], fail(), &r).eval(&s).is_empty());
+
+        // `try_eval` finds the solutions in the same order as `eval`
+        let results = findall(&x, g(), &r).try_eval(&s).unwrap();
+        assert_eq!(Term::Var(r).reify(&results[0]).to_string(), "[3, 1, 2, 3]");
+        assert_eq!(describe(bagof(&x, &[k], g(), &r).try_eval(&s).unwrap()), vec!["b: [3, 2, 3]", "a: [1]"]);
+    }
+
+    #[test]
+    fn test_try_eval() {
+        let s = State::<i32>::empty();
+        let (x, s) = s.make_var();
+        let (n, s) = s.make_var();
+        let (foreign, _) = State::<i32>::empty().make_var();
+        let g = || disj(unify_val(&x, 3), unify_val(&x, 4));
+        assert_eq!(sum(&x, disj(unify_val(&x, 3), unify_val(&foreign, 4)), &n).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(sum(&foreign, g(), &n).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(count(fail(), &foreign).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        let results = sum(&x, g(), &n).try_eval(&s).unwrap();
+        assert_eq!(results[0].get(&n), Some(&7));
+    }
+}
@@ -0,0 +1,243 @@
+//! # Attributed Variables
+//!
+//! Constraint solvers can be written outside the crate by attaching
+//! attributes to variables, in the style of SWI-Prolog.  An attribute is any
+//! type that implements `Attribute`; a variable has at most one attribute of
+//! each type, which is attached with `State::put_attr` and read back with
+//! `State::get_attr`.  Attributes belong to the variable's binding, so they
+//! are shared by the variables that have been unified with it.
+//!
+//! Whenever a variable with attributes is bound to a value, each of its
+//! attributes' `verify_attributes` hooks is called with the value.  A hook can
+//! make the unification fail, or return goals that must be proven for it to
+//! succeed.  When an unbound variable with attributes is unified with another
+//! unbound variable, their attributes are merged and each one's
+//! `verify_unified` hook is called instead.
+//!
+//! ```
+//! use rslogic::attr::{Attribute, Goals};
+//! use rslogic::goal::{Goal, disj, unify_val};
+//! use rslogic::state::{State, Var};
+//!
+//! /// The variable must be bound to a value in the range.
+//! struct Range(i32, i32);
+//!
+//! impl Attribute<i32> for Range {
+//!     fn verify_attributes(&self, _: &Var, value: &i32, _: &State<i32>) -> Option<Goals<i32>> {
+//!         if self.0 <= *value && *value <= self.1 { Some(Vec::new()) } else { None }
+//!     }
+//! }
+//!
+//! let s = State::<i32>::empty();
+//! let (a, s) = s.make_var();
+//! let s = s.put_attr(&a, Range(1, 5));
+//!
+//! let g = disj(unify_val(&a, 3), unify_val(&a, 7));
+//! let results = g.eval(&s);
+//! assert_eq!(results.len(), 1);
+//! assert_eq!(results[0].get(&a), Some(&3));
+//! ```
+
+use goal::Goal;
+use state::{Unif, Var, State};
+use std::any::{Any, TypeId};
+use std::sync::Arc;
+
+/// Goals produced by verifying an attribute, which must be proven for the
+/// unification to succeed.
+pub type Goals<T> = Vec<Box<dyn Goal<T>>>;
+
+/// An attribute of a variable, which is verified whenever the variable is bound.
+pub trait Attribute<T>: Any + Send + Sync where T: PartialEq + Unif<T> {
+    /// Called when `var`, which has this attribute, is bound to `value` by a
+    /// unification.  `state` is the state in which the variable has just been
+    /// bound.  Returns `None` to make the unification fail, or the goals that
+    /// must be proven in `state` for it to succeed.
+    ///
+    /// The hook is called as soon as the variable is bound, so while a
+    /// compound value is being unified, variables that it contains may not be
+    /// bound yet.
+    fn verify_attributes(&self, var: &Var, value: &T, state: &State<T>) -> Option<Goals<T>>;
+
+    /// Called when `var`, which has this attribute, has been unified with
+    /// another unbound variable, so that they share their attributes.  Like
+    /// `verify_attributes`, returns `None` to make the unification fail, or
+    /// the goals that must be proven in `state` for it to succeed.  By
+    /// default the unification succeeds.
+    fn verify_unified(&self, var: &Var, state: &State<T>) -> Option<Goals<T>> {
+        let _ = (var, state);
+        Some(Vec::new())
+    }
+}
+
+/// The attributes of a variable.
+pub(crate) type Attrs<T> = Vec<Attr<T>>;
+
+/// Calls the hook of an attribute of a particular type.
+type Verifier<T> = fn(&dyn Any, &Var, &T, &State<T>) -> Option<Goals<T>>;
+
+/// Calls the hook of an attribute of a particular type for a unification with
+/// an unbound variable.
+type UnifiedVerifier<T> = fn(&dyn Any, &Var, &State<T>) -> Option<Goals<T>>;
+
+/// An attribute stored in a state, along with the hook that verifies it.
+pub(crate) struct Attr<T> where T: PartialEq + Unif<T> {
+    type_id: TypeId,
+    value: Arc<dyn Any + Send + Sync>,
+    verify: Verifier<T>,
+    verify_unified: UnifiedVerifier<T>,
+}
+
+impl<T> Attr<T> where T: PartialEq + Unif<T> {
+    pub(crate) fn new<A>(attr: A) -> Attr<T> where A: Attribute<T> {
+        Attr { type_id: TypeId::of::<A>(), value: Arc::new(attr), verify: verify::<T, A>, verify_unified: verify_unified::<T, A> }
+    }
+
+    /// Returns `true` if the attribute is of type `A`.
+    pub(crate) fn is<A>(&self) -> bool where A: Attribute<T> {
+        self.type_id == TypeId::of::<A>()
+    }
+
+    pub(crate) fn downcast<A>(&self) -> Option<&A> where A: Attribute<T> {
+        self.value.downcast_ref::<A>()
+    }
+
+    pub(crate) fn verify(&self, var: &Var, value: &T, state: &State<T>) -> Option<Goals<T>> {
+        (self.verify)(&*self.value, var, value, state)
+    }
+
+    pub(crate) fn verify_unified(&self, var: &Var, state: &State<T>) -> Option<Goals<T>> {
+        (self.verify_unified)(&*self.value, var, state)
+    }
+}
+
+impl<T> Clone for Attr<T> where T: PartialEq + Unif<T> {
+    fn clone(&self) -> Attr<T> {
+        Attr { type_id: self.type_id, value: self.value.clone(), verify: self.verify, verify_unified: self.verify_unified }
+    }
+}
+
+fn verify<T, A>(attr: &dyn Any, var: &Var, value: &T, state: &State<T>) -> Option<Goals<T>> where T: PartialEq + Unif<T>, A: Attribute<T> {
+    attr.downcast_ref::<A>()
+        .expect("attributes are stored with the hook for their type")
+        .verify_attributes(var, value, state)
+}
+
+fn verify_unified<T, A>(attr: &dyn Any, var: &Var, state: &State<T>) -> Option<Goals<T>> where T: PartialEq + Unif<T>, A: Attribute<T> {
+    attr.downcast_ref::<A>()
+        .expect("attributes are stored with the hook for their type")
+        .verify_unified(var, state)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use goal::{Goal, conj, disj, unify_val, unify_vars};
+    use search::Search;
+    use state::{State, Var};
+    use store::{Trail, solve_all};
+    use super::{Attribute, Goals};
+
+    /// The variable must be bound to one of the values.
+    struct Domain(Vec<i32>);
+
+    impl Attribute<i32> for Domain {
+        fn verify_attributes(&self, _: &Var, value: &i32, _: &State<i32>) -> Option<Goals<i32>> {
+            if self.0.contains(value) { Some(Vec::new()) } else { None }
+        }
+    }
+
+    /// The other variable must be bound to the same value as the variable.
+    struct Mirror(Var);
+
+    impl Attribute<i32> for Mirror {
+        fn verify_attributes(&self, _: &Var, value: &i32, _: &State<i32>) -> Option<Goals<i32>> {
+            Some(vec![Box::new(unify_val(&self.0, *value))])
+        }
+    }
+
+    #[test]
+    fn test_attributes() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let (c, s) = s.make_var();
+        let s = s.put_attr(&a, Domain(vec![1, 2])).put_attr(&a, Mirror(c));
+
+        assert_eq!(s.get_attr::<Domain>(&a).map(|d| d.0.clone()), Some(vec![1, 2]));
+        assert!(s.get_attr::<Domain>(&b).is_none());
+        let s = s.put_attr(&a, Domain(vec![2, 3]));
+        assert_eq!(s.get_attr::<Domain>(&a).map(|d| d.0.clone()), Some(vec![2, 3]));
+
+        // attributes are shared by the variables unified with `a`
+        let g = conj(unify_vars(&b, &a), disj(unify_val(&b, 1), disj(unify_val(&b, 2), unify_val(&b, 3))));
+        let results = g.eval(&s);
+        let values: Vec<(i32, i32)> = results.iter().map(|s| (*s.get(&a).unwrap(), *s.get(&c).unwrap())).collect();
+        assert_eq!(values, vec![(2, 2), (3, 3)]);
+        assert_eq!(Search::new(&g, &s).count(), 2);
+
+        // the goals a hook produces must be proven
+        let g = conj(unify_val(&c, 3), disj(unify_val(&a, 2), unify_val(&a, 3)));
+        assert_eq!(g.eval(&s).len(), 1);
+
+        let s = s.del_attr::<Mirror>(&a);
+        assert!(s.get_attr::<Mirror>(&a).is_none());
+        assert_eq!(g.eval(&s).len(), 2);
+    }
+
+    /// The variable must not be unified with another unbound variable.
+    struct Alone;
+
+    impl Attribute<i32> for Alone {
+        fn verify_attributes(&self, _: &Var, _: &i32, _: &State<i32>) -> Option<Goals<i32>> {
+            Some(Vec::new())
+        }
+
+        fn verify_unified(&self, _: &Var, _: &State<i32>) -> Option<Goals<i32>> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_unified_attributes() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let s = s.put_attr(&a, Domain(vec![1, 2])).put_attr(&b, Mirror(a));
+
+        // unifying attributed variables keeps the attributes of both
+        let g = conj(unify_vars(&a, &b), disj(unify_val(&b, 2), unify_val(&b, 3)));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get(&a), Some(&2));
+
+        // the attributes of an unbound variable are verified when it is unified with a bound one
+        let g = conj(unify_val(&a, 1), unify_vars(&a, &b));
+        assert_eq!(g.eval(&s).len(), 1);
+        let g = conj(unify_val(&b, 3), unify_vars(&a, &b));
+        assert_eq!(g.eval(&s).len(), 0);
+
+        // unifying unbound variables calls the hooks of either one's attributes
+        let (c, s) = s.make_var();
+        let s = s.put_attr(&c, Alone);
+        for g in [unify_vars(&a, &c), unify_vars(&c, &a)] {
+            assert_eq!(g.eval(&s).len(), 0);
+            assert_eq!(solve_all(&g, &mut Trail::from_state(&s)).len(), 0);
+        }
+        assert_eq!(conj(unify_val(&a, 1), unify_vars(&a, &c)).eval(&s).len(), 1);
+    }
+
+    #[test]
+    fn test_trail() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let s = s.put_attr(&b, Domain(vec![4]));
+
+        let g = conj(unify_vars(&a, &b), disj(unify_val(&a, 3), unify_val(&a, 4)));
+        let results = solve_all(&g, &mut Trail::from_state(&s));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get(&a), Some(&4));
+        assert!(results[0].get_attr::<Domain>(&a).is_some());
+    }
+}
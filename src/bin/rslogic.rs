@@ -0,0 +1,325 @@
+//! # rslogic
+//!
+//! An interactive interpreter for the text syntax.
+//!
+//! ```text
+//! rslogic [FILE]...
+//! ```
+//!
+//! Loads the given program files, then reads queries from standard input.
+//! When standard input is a terminal, answers are printed one at a time;
+//! type `;` to ask for the next answer, or just press return to stop.
+//! Otherwise, all the answers to each query are printed, so that a script of
+//! queries can be piped in.
+
+extern crate rslogic;
+
+use rslogic::goal::{AnswerFilter, PrettyTracer, Tracer};
+use rslogic::search::{Search, SearchLimits, SearchStrategy, Outcome};
+use rslogic::search::{DepthFirst, Interleaving, BreadthFirst, IterativeDeepening};
+use rslogic::state::{State, Unif, Var};
+use rslogic::stats::Stats;
+use rslogic::syntax::{Program, Query, parse_query};
+use rslogic::term::Term;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const HELP: &str = "\
+Enter a query such as `member(X, [1, 2, 3])`, or one of the commands:
+  :load FILE    add the clauses in FILE to the program
+  :trace        turn tracing of goals' ports on or off
+  :stats        turn printing of search statistics on or off
+  :limit [N]    print at most N answers to each query (no limit if N is omitted)
+  :steps [N]    stop a search for an answer after N steps (no limit if N is omitted)
+  :timeout [MS] stop a query after MS milliseconds (no limit if MS is omitted)
+  :compact [N]  forget unreachable bindings every N steps (never if N is omitted)
+  :distinct M   print every answer (off), only answers not printed before (on),
+                or only those not subsumed by answers printed before (subsume)
+  :strategy S   search depth-first (dfs), interleaving (interleave),
+                breadth-first (bfs) or by iterative deepening (iddfs)
+  :help         show this message
+  :quit         exit";
+
+/// The number of steps between compactions of a search's bindings, by default.
+const COMPACT_INTERVAL: u64 = 1000;
+
+struct Repl {
+    program: Program,
+    interactive: bool,
+    trace: bool,
+    stats: bool,
+    limit: Option<usize>,
+    steps: Option<u64>,
+    timeout: Option<Duration>,
+    compact: Option<u64>,
+    distinct: Option<bool>, // `Some(true)` if subsumed answers are dropped too
+    strategy: String,
+}
+
+impl Repl {
+    /// Loads a program file, printing the answers to any queries it contains.
+    fn load(&mut self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let queries = self.program.consult(&text).map_err(|err| format!("{}: {}", path, err))?;
+        for query in queries {
+            self.answer(&query, None);
+        }
+        Ok(())
+    }
+
+    /// Handles a command, returning `false` if the interpreter should exit.
+    fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(":quit"), None) | (Some(":q"), None) => return false,
+            (Some(":help"), None) => println!("{}", HELP),
+            (Some(":load"), Some(path)) => {
+                if let Err(err) = self.load(path) {
+                    eprintln!("error: {}", err);
+                }
+            },
+            (Some(":trace"), None) => {
+                self.trace = !self.trace;
+                let tracer: Option<Rc<dyn Tracer<Term>>> = if self.trace { Some(Rc::new(PrettyTracer::stderr())) } else { None };
+                self.program.set_tracer(tracer);
+                println!("tracing is {}", if self.trace { "on" } else { "off" });
+            },
+            (Some(":stats"), None) => {
+                self.stats = !self.stats;
+                println!("statistics are {}", if self.stats { "on" } else { "off" });
+            },
+            (Some(":limit"), None) => {
+                self.limit = None;
+                println!("no answer limit");
+            },
+            (Some(":limit"), Some(n)) => match n.parse() {
+                Ok(n) if n > 0 => {
+                    self.limit = Some(n);
+                    println!("at most {} answers", n);
+                },
+                _ => eprintln!("error: invalid limit `{}`", n),
+            },
+            (Some(":steps"), None) => {
+                self.steps = None;
+                println!("no step limit");
+            },
+            (Some(":steps"), Some(n)) => match n.parse() {
+                Ok(n) => {
+                    self.steps = Some(n);
+                    println!("at most {} steps per answer", n);
+                },
+                Err(_) => eprintln!("error: invalid step limit `{}`", n),
+            },
+            (Some(":timeout"), None) => {
+                self.timeout = None;
+                println!("no timeout");
+            },
+            (Some(":timeout"), Some(ms)) => match ms.parse() {
+                Ok(ms) => {
+                    self.timeout = Some(Duration::from_millis(ms));
+                    println!("queries time out after {} ms", ms);
+                },
+                Err(_) => eprintln!("error: invalid timeout `{}`", ms),
+            },
+            (Some(":compact"), None) => {
+                self.compact = None;
+                println!("no compaction");
+            },
+            (Some(":compact"), Some(n)) => match n.parse() {
+                Ok(n) => {
+                    self.compact = Some(n);
+                    println!("compacting every {} steps", n);
+                },
+                Err(_) => eprintln!("error: invalid compaction interval `{}`", n),
+            },
+            (Some(":strategy"), Some(name)) => match strategy::<Term>(name) {
+                Some(_) => {
+                    self.strategy = name.to_string();
+                    println!("using {} search", name);
+                },
+                None => eprintln!("error: unknown strategy `{}`", name),
+            },
+            (Some(":distinct"), Some(mode)) => {
+                let (distinct, message) = match mode {
+                    "off" => (None, "printing every answer"),
+                    "on" => (Some(false), "printing distinct answers"),
+                    "subsume" => (Some(true), "printing answers not subsumed by earlier ones"),
+                    _ => {
+                        eprintln!("error: unknown mode `{}`", mode);
+                        return true;
+                    },
+                };
+                self.distinct = distinct;
+                println!("{}", message);
+            },
+            _ => eprintln!("error: unknown command `{}`; try :help", line),
+        }
+        true
+    }
+
+    /// Prints the answers to a query.  If there is an `input`, each answer after
+    /// the first is only printed if a `;` is read from it.
+    fn answer(&self, query: &Query, input: Option<&mut dyn BufRead>) {
+        let stats = Arc::new(Stats::new());
+        let start = if self.stats { State::empty().with_stats(stats.clone()) } else { State::empty() };
+        self.search(query, &start, input);
+        if self.stats {
+            eprintln!("% {}", stats);
+        }
+    }
+
+    fn search(&self, query: &Query, start: &State<Term>, mut input: Option<&mut dyn BufRead>) {
+        let (goal, vars, state) = query.instantiate(start);
+        let goal = self.program.goal(goal);
+
+        let mut limits = SearchLimits::new().answers(1);
+        if let Some(steps) = self.steps {
+            limits = limits.steps(steps);
+        }
+        if let Some(timeout) = self.timeout {
+            limits = limits.deadline(Instant::now() + timeout);
+        }
+
+        let strategy = strategy(&self.strategy).expect("the strategy name was checked when it was set");
+        let mut search = Search::with_strategy(&goal, &state, strategy);
+        let roots: Vec<Var> = vars.iter().map(|&(_, var)| var).collect();
+        if let Some(interval) = self.compact {
+            search = search.compact_every(interval, &roots);
+        }
+        let mut filter = self.distinct.map(|subsume| {
+            let filter = AnswerFilter::new(&roots);
+            if subsume { filter.subsuming() } else { filter }
+        });
+        let mut count = 0;
+        loop {
+            let mut result = search.run(&limits);
+            let state = match result.answers.pop() {
+                Some(state) => state,
+                None => {
+                    match result.outcome {
+                        Outcome::Exhausted => println!("false."),
+                        outcome => println!("% search stopped: {}", outcome),
+                    }
+                    return;
+                },
+            };
+            if filter.as_mut().is_some_and(|filter| !filter.admit(&state)) {
+                continue;
+            }
+            count += 1;
+            print!("{}", describe(&vars, &state));
+            if self.limit == Some(count) {
+                println!(" .");
+                return;
+            }
+
+            let input = match input {
+                Some(ref mut input) => input,
+                None => {
+                    println!(" ;");
+                    continue;
+                },
+            };
+            print!(" ");
+            let _ = io::stdout().flush();
+
+            let mut reply = String::new();
+            if input.read_line(&mut reply).is_err() || reply.trim() != ";" {
+                println!(".");
+                return;
+            }
+        }
+    }
+}
+
+/// Creates the search strategy with the given name.
+fn strategy<'a, T>(name: &str) -> Option<Box<dyn SearchStrategy<'a, T> + 'a>> where T: PartialEq + Unif<T> + 'a {
+    match name {
+        "dfs" => Some(Box::new(DepthFirst::new())),
+        "interleave" => Some(Box::new(Interleaving::new())),
+        "bfs" => Some(Box::new(BreadthFirst::new())),
+        "iddfs" => Some(Box::new(IterativeDeepening::new(8))),
+        _ => None,
+    }
+}
+
+/// Describes the bindings of the query's named variables, in the order in
+/// which they appear in the query.
+fn describe(vars: &[(String, Var)], state: &State<Term>) -> String {
+    let answer = state.answer();
+    let bindings: Vec<String> = vars.iter()
+        .filter(|&(name, _)| !name.starts_with('_'))
+        .map(|(name, var)| match answer.get(name) {
+            Some(val) => format!("{} = {}", name, val),
+            None => format!("{} = {}", name, var),
+        })
+        .collect();
+    if bindings.is_empty() { "true".to_string() } else { bindings.join(",\n") }
+}
+
+fn main() {
+    let mut repl = Repl {
+        program: Program::new(),
+        interactive: io::stdin().is_terminal(),
+        trace: false,
+        stats: false,
+        limit: None,
+        steps: None,
+        timeout: None,
+        compact: Some(COMPACT_INTERVAL),
+        distinct: None,
+        strategy: "dfs".to_string(),
+    };
+
+    for path in env::args().skip(1) {
+        if let Err(err) = repl.load(&path) {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    loop {
+        if repl.interactive {
+            print!("?- ");
+            let _ = io::stdout().flush();
+        }
+
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {},
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            },
+        }
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('%') {
+            continue;
+        }
+        if line.starts_with(':') {
+            if !repl.command(line) {
+                break;
+            }
+            continue;
+        }
+
+        match parse_query(line) {
+            Ok(query) => {
+                if !repl.interactive {
+                    println!("?- {}", line.trim_start_matches("?-").trim());
+                }
+                repl.answer(&query, if repl.interactive { Some(&mut input) } else { None });
+            },
+            Err(err) => eprintln!("error: {}", err),
+        }
+    }
+}
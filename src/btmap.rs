@@ -1,24 +1,47 @@
 //! # Binary Tree Map
 //!
-//! An immutable map implemented with a binary tree.
+//! An immutable map implemented with a balanced (AVL) binary tree.  Changing a
+//! map returns a new map that shares all the untouched parts of the tree with
+//! the original, so copies are cheap and old versions remain usable.  The tree
+//! is rebalanced as it changes, so it is never more than about 1.44 log2(n)
+//! levels deep, whatever order the keys are added in.
+//!
+//! ```
+//! use rslogic::btmap::BtMap;
+//!
+//! let m: BtMap<u32, &str> = vec![(3, "c"), (1, "a"), (2, "b")].into_iter().collect();
+//! let n = m.remove(&2).replace(4, "d");
+//! assert_eq!(m.keys().cloned().collect::<Vec<_>>(), vec![1, 2, 3]);
+//! assert_eq!(n.iter().collect::<Vec<_>>(), vec![(&1, &"a"), (&3, &"c"), (&4, &"d")]);
+//! assert_eq!(n.range(2..).count(), 2);
+//! ```
 
 use std::borrow::Borrow;
 use std::cmp::Ord;
 use std::cmp::Ordering;
-use std::rc::Rc;
+use std::error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
 
 struct Node<K, V> where K: Ord {
-    key: K,
-    val: V,
-    left: Option<Rc<Node<K, V>>>,
-    right: Option<Rc<Node<K, V>>>,
+    key: Arc<K>,
+    val: Arc<V>,
+    left: Option<Arc<Node<K, V>>>,
+    right: Option<Arc<Node<K, V>>>,
+    height: usize, // of the subtree under the node
 }
 
+/// A link to a subtree, which may be empty.
+type Link<K, V> = Option<Arc<Node<K, V>>>;
+
 impl<K, V> Node<K, V> where K: Ord {
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
-        where K: Ord + Borrow<Q>, Q: Ord
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+        where K: Ord + Borrow<Q>, Q: Ord + ?Sized
     {
-        match key.cmp(self.key.borrow()) {
+        match key.cmp((*self.key).borrow()) {
             Ordering::Equal => return Some(&self.val),
             Ordering::Less => if let Some(ref child) = self.left { return child.get(key) },
             Ordering::Greater => if let Some(ref child) = self.right { return child.get(key) },
@@ -27,10 +50,23 @@ impl<K, V> Node<K, V> where K: Ord {
     }
 }
 
-/// An immutable map implemented with a binary tree.
+/// An error returned by `BtMap::insert` when the map already has an item
+/// corresponding to the key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DuplicateKey;
+
+impl fmt::Display for DuplicateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the map already has an item with the key")
+    }
+}
+
+impl error::Error for DuplicateKey {}
+
+/// An immutable map implemented with a balanced binary tree.
 pub struct BtMap<K, V> where K : Ord {
     size: usize,
-    root: Option<Rc<Node<K, V>>>
+    root: Link<K, V>,
 }
 
 impl<K, V> BtMap<K, V> where K : Ord {
@@ -40,14 +76,19 @@ impl<K, V> BtMap<K, V> where K : Ord {
     }
 
     /// Returns the number of items in the map.
-    pub fn _len(&self) -> usize {
+    pub fn len(&self) -> usize {
         self.size
     }
 
+    /// Returns `true` if the map has no items.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
     /// Returns a reference to the item in the map corresponding to the key,
     /// or `None` if there is no item corresponding the the key.
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
-        where K: Ord + Borrow<Q>, Q: Ord
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+        where K: Ord + Borrow<Q>, Q: Ord + ?Sized
     {
         match self.root {
             Some(ref node) => node.get(key),
@@ -56,8 +97,8 @@ impl<K, V> BtMap<K, V> where K : Ord {
     }
 
     /// Returns `true` if the map contains an item corresponding to the key.
-    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
-        where K: Ord + Borrow<Q>, Q: Ord
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+        where K: Ord + Borrow<Q>, Q: Ord + ?Sized
     {
         self.get(key).is_some()
     }
@@ -65,42 +106,346 @@ impl<K, V> BtMap<K, V> where K : Ord {
     /// Returns a new map containing all the items in the original map,
     /// as well as the new item.  Returns an error if there is already
     /// an item corresponding to the key.
-    pub fn insert(&self, key: K, item: V) -> Result<BtMap<K, V>, ()> {
-        match self.root {
+    pub fn insert(&self, key: K, item: V) -> Result<BtMap<K, V>, DuplicateKey> {
+        Ok(BtMap {
+            size: self.size + 1,
+            root: Some(Arc::new(Node::insert(&self.root, key, item)?)),
+        })
+    }
+
+    /// Returns a new map containing all the items in the original map, with
+    /// the item corresponding to the key replaced by the new item, or added
+    /// if there was none.
+    pub fn replace(&self, key: K, item: V) -> BtMap<K, V> {
+        let (root, added) = Node::replace(&self.root, key, item);
+        BtMap {
+            size: if added { self.size + 1 } else { self.size },
+            root: Some(Arc::new(root)),
+        }
+    }
+
+    /// Returns a new map in which the item corresponding to the key is the
+    /// result of calling the function with the current item, if any.
+    pub fn update<F>(&self, key: K, f: F) -> BtMap<K, V> where F: FnOnce(Option<&V>) -> V {
+        let item = f(self.get(&key));
+        self.replace(key, item)
+    }
+
+    /// Returns a new map containing all the items in the original map except
+    /// the one corresponding to the key, if there was one.
+    pub fn remove<Q>(&self, key: &Q) -> BtMap<K, V>
+        where K: Ord + Borrow<Q>, Q: Ord + ?Sized
+    {
+        match Node::remove(&self.root, key) {
+            Some(root) => BtMap { size: self.size - 1, root },
+            None => self.clone(),
+        }
+    }
+
+    /// Returns a new map containing the items of both maps.  Where both have
+    /// an item corresponding to a key, the item in `other` is kept.
+    pub fn union(&self, other: &BtMap<K, V>) -> BtMap<K, V> where K: Clone, V: Clone {
+        if self.len() < other.len() {
+            self.iter().fold(other.clone(), |map, (key, item)| {
+                if map.contains_key(key) { map } else { map.replace(key.clone(), item.clone()) }
+            })
+        } else {
+            other.iter().fold(self.clone(), |map, (key, item)| map.replace(key.clone(), item.clone()))
+        }
+    }
+
+    /// Returns an iterator over the items in the map, in order of their keys.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left(&self.root);
+        iter
+    }
+
+    /// Returns an iterator over the keys in the map, in order.
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { iter: self.iter() }
+    }
+
+    /// Returns an iterator over the items in the map, in order of their keys.
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { iter: self.iter() }
+    }
+
+    /// Returns an iterator over the items whose keys are in the range, in
+    /// order of their keys.
+    pub fn range<R>(&self, range: R) -> Range<'_, K, V, R> where R: RangeBounds<K> {
+        let mut iter = Iter { stack: Vec::new() };
+        let mut link = &self.root;
+        while let Some(ref node) = *link {
+            let after_start = match range.start_bound() {
+                Bound::Included(start) => *node.key >= *start,
+                Bound::Excluded(start) => *node.key > *start,
+                Bound::Unbounded => true,
+            };
+            if after_start {
+                iter.stack.push(node);
+                link = &node.left;
+            } else {
+                link = &node.right;
+            }
+        }
+        Range { iter, range }
+    }
+
+    /// Returns `true` if the maps share the same tree, so that they are equal
+    /// without comparing their items.
+    pub fn ptr_eq(&self, other: &BtMap<K, V>) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<K, V> Node<K, V> where K: Ord {
+    /// Makes a node from its parts.
+    fn new(key: Arc<K>, val: Arc<V>, left: Link<K, V>, right: Link<K, V>) -> Node<K, V> {
+        let height = 1 + Node::height(&left).max(Node::height(&right));
+        Node { key, val, left, right, height }
+    }
+
+    fn height(link: &Link<K, V>) -> usize {
+        link.as_ref().map_or(0, |node| node.height)
+    }
+
+    /// Makes a node from its parts, rotating it if one subtree has become two
+    /// levels taller than the other, so that the tree stays balanced.
+    fn balance(key: Arc<K>, val: Arc<V>, left: Link<K, V>, right: Link<K, V>) -> Node<K, V> {
+        let (lh, rh) = (Node::height(&left), Node::height(&right));
+        if lh > rh + 1 {
+            let l = left.expect("the taller subtree is not empty");
+            if Node::height(&l.left) >= Node::height(&l.right) {
+                let right = Node::new(key, val, l.right.clone(), right);
+                Node::new(l.key.clone(), l.val.clone(), l.left.clone(), Some(Arc::new(right)))
+            } else {
+                let lr = l.right.as_ref().expect("the taller subtree is not empty");
+                let left = Node::new(l.key.clone(), l.val.clone(), l.left.clone(), lr.left.clone());
+                let right = Node::new(key, val, lr.right.clone(), right);
+                Node::new(lr.key.clone(), lr.val.clone(), Some(Arc::new(left)), Some(Arc::new(right)))
+            }
+        } else if rh > lh + 1 {
+            let r = right.expect("the taller subtree is not empty");
+            if Node::height(&r.right) >= Node::height(&r.left) {
+                let left = Node::new(key, val, left, r.left.clone());
+                Node::new(r.key.clone(), r.val.clone(), Some(Arc::new(left)), r.right.clone())
+            } else {
+                let rl = r.left.as_ref().expect("the taller subtree is not empty");
+                let left = Node::new(key, val, left, rl.left.clone());
+                let right = Node::new(r.key.clone(), r.val.clone(), rl.right.clone(), r.right.clone());
+                Node::new(rl.key.clone(), rl.val.clone(), Some(Arc::new(left)), Some(Arc::new(right)))
+            }
+        } else {
+            Node::new(key, val, left, right)
+        }
+    }
+
+    /// Copies the path from `node` down to the position of the new key,
+    /// sharing all the untouched subtrees with the original tree.
+    fn insert(node: &Link<K, V>, key: K, item: V) -> Result<Node<K, V>, DuplicateKey> {
+        match *node {
             Some(ref node) => {
-                match key.cmp(&node.key) {
-                    Ordering::Equal => Err(()),
-                    Ordering::Less => Ok(BtMap {
-                        size: self.size + 1,
-                        root: Some(Rc::new(Node {
-                            key: key,
-                            val: item,
-                            left: None,
-                            right: Some(node.clone()),
-                        }))
-                    }),
-                    Ordering::Greater => Ok(BtMap {
-                        size: self.size + 1,
-                        root: Some(Rc::new(Node {
-                            key: key,
-                            val: item,
-                            left: Some(node.clone()),
-                            right: None,
-                        }))
-                    })
+                match key.cmp(&*node.key) {
+                    Ordering::Equal => Err(DuplicateKey),
+                    Ordering::Less => {
+                        let left = Node::insert(&node.left, key, item)?;
+                        Ok(Node::balance(node.key.clone(), node.val.clone(), Some(Arc::new(left)), node.right.clone()))
+                    },
+                    Ordering::Greater => {
+                        let right = Node::insert(&node.right, key, item)?;
+                        Ok(Node::balance(node.key.clone(), node.val.clone(), node.left.clone(), Some(Arc::new(right))))
+                    },
                 }
             },
-            None => {
-                Ok(BtMap {
-                    size: 1,
-                    root: Some(Rc::new(Node {
-                        key: key,
-                        val: item,
-                        left: None,
-                        right: None,
-                    }))
-                })
-            }
+            None => Ok(Node::new(Arc::new(key), Arc::new(item), None, None)),
+        }
+    }
+
+    /// Copies the path from `node` down to the position of the key, replacing
+    /// the item there.  Also returns `true` if the key was not already present.
+    fn replace(node: &Link<K, V>, key: K, item: V) -> (Node<K, V>, bool) {
+        match *node {
+            Some(ref node) => {
+                match key.cmp(&*node.key) {
+                    Ordering::Equal => (Node { val: Arc::new(item), .. node.copy() }, false),
+                    Ordering::Less => {
+                        let (left, added) = Node::replace(&node.left, key, item);
+                        (Node::balance(node.key.clone(), node.val.clone(), Some(Arc::new(left)), node.right.clone()), added)
+                    },
+                    Ordering::Greater => {
+                        let (right, added) = Node::replace(&node.right, key, item);
+                        (Node::balance(node.key.clone(), node.val.clone(), node.left.clone(), Some(Arc::new(right))), added)
+                    },
+                }
+            },
+            None => (Node::new(Arc::new(key), Arc::new(item), None, None), true),
+        }
+    }
+
+    /// Copies the path from `node` down to the key, leaving the item there
+    /// out.  Returns the new subtree, or `None` if the key is not present.
+    fn remove<Q>(node: &Link<K, V>, key: &Q) -> Option<Link<K, V>>
+        where K: Ord + Borrow<Q>, Q: Ord + ?Sized
+    {
+        let node = node.as_ref()?;
+        Some(match key.cmp((*node.key).borrow()) {
+            Ordering::Less => {
+                let left = Node::remove(&node.left, key)?;
+                Some(Arc::new(Node::balance(node.key.clone(), node.val.clone(), left, node.right.clone())))
+            },
+            Ordering::Greater => {
+                let right = Node::remove(&node.right, key)?;
+                Some(Arc::new(Node::balance(node.key.clone(), node.val.clone(), node.left.clone(), right)))
+            },
+            Ordering::Equal => match (&node.left, &node.right) {
+                (None, right) => right.clone(),
+                (left, None) => left.clone(),
+                (left, Some(right)) => {
+                    // the least item on the right takes the place of the removed one
+                    let (key, val, right) = Node::remove_least(right);
+                    Some(Arc::new(Node::balance(key, val, left.clone(), right)))
+                },
+            },
+        })
+    }
+
+    /// Copies the path from `node` down to its least key, leaving the item
+    /// there out.  Returns the key and item, and the new subtree.
+    fn remove_least(node: &Arc<Node<K, V>>) -> (Arc<K>, Arc<V>, Link<K, V>) {
+        match node.left {
+            Some(ref left) => {
+                let (key, val, left) = Node::remove_least(left);
+                (key, val, Some(Arc::new(Node::balance(node.key.clone(), node.val.clone(), left, node.right.clone()))))
+            },
+            None => (node.key.clone(), node.val.clone(), node.right.clone()),
+        }
+    }
+
+    /// Returns a node with the same key, item and subtrees.
+    fn copy(&self) -> Node<K, V> {
+        Node { key: self.key.clone(), val: self.val.clone(), left: self.left.clone(), right: self.right.clone(), height: self.height }
+    }
+}
+
+/// An iterator over the items of a `BtMap`, in order of their keys.
+pub struct Iter<'a, K, V> where K: Ord + 'a, V: 'a {
+    stack: Vec<&'a Arc<Node<K, V>>>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> where K: Ord {
+    /// Pushes the node and its chain of left children, which are the next
+    /// items to visit, least last.
+    fn push_left(&mut self, mut link: &'a Link<K, V>) {
+        while let Some(ref node) = *link {
+            self.stack.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> where K: Ord {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        let node = self.stack.pop()?;
+        self.push_left(&node.right);
+        Some((&node.key, &node.val))
+    }
+}
+
+/// An iterator over the keys of a `BtMap`, in order.
+pub struct Keys<'a, K, V> where K: Ord + 'a, V: 'a {
+    iter: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> where K: Ord {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<&'a K> {
+        self.iter.next().map(|(key, _)| key)
+    }
+}
+
+/// An iterator over the items of a `BtMap`, in order of their keys.
+pub struct Values<'a, K, V> where K: Ord + 'a, V: 'a {
+    iter: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Values<'a, K, V> where K: Ord {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        self.iter.next().map(|(_, val)| val)
+    }
+}
+
+/// An iterator over the items of a `BtMap` whose keys are in a range, in order.
+pub struct Range<'a, K, V, R> where K: Ord + 'a, V: 'a {
+    iter: Iter<'a, K, V>,
+    range: R,
+}
+
+impl<'a, K, V, R> Iterator for Range<'a, K, V, R> where K: Ord, R: RangeBounds<K> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        let (key, val) = self.iter.next()?;
+        let before_end = match self.range.end_bound() {
+            Bound::Included(end) => key <= end,
+            Bound::Excluded(end) => key < end,
+            Bound::Unbounded => true,
+        };
+        if before_end {
+            Some((key, val))
+        } else {
+            self.iter.stack.clear();
+            None
+        }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a BtMap<K, V> where K: Ord {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+/// Collects the items into a map.  Where several items have the same key,
+/// the last is kept.
+impl<K, V> FromIterator<(K, V)> for BtMap<K, V> where K: Ord {
+    fn from_iter<I>(iter: I) -> BtMap<K, V> where I: IntoIterator<Item = (K, V)> {
+        iter.into_iter().fold(BtMap::empty(), |map, (key, item)| map.replace(key, item))
+    }
+}
+
+impl<K, V> Default for BtMap<K, V> where K: Ord {
+    fn default() -> BtMap<K, V> {
+        BtMap::empty()
+    }
+}
+
+impl<K, V> PartialEq for BtMap<K, V> where K: Ord, V: PartialEq {
+    fn eq(&self, other: &BtMap<K, V>) -> bool {
+        self.ptr_eq(other) || (self.len() == other.len() && self.iter().eq(other.iter()))
+    }
+}
+
+impl<K, V> Eq for BtMap<K, V> where K: Ord, V: Eq {}
+
+impl<K, V> Hash for BtMap<K, V> where K: Ord + Hash, V: Hash {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for (key, val) in self {
+            key.hash(state);
+            val.hash(state);
         }
     }
 }
@@ -111,26 +456,26 @@ impl<K, V> Clone for BtMap<K, V> where K: Ord {
     fn clone(&self) -> BtMap<K, V> {
         BtMap {
             size: self.size,
-            root: match self.root {
-                Some(ref root) => Some(root.clone()),
-                None => None
-            }
+            root: self.root.clone(),
         }
     }
 
     fn clone_from(&mut self, source: &BtMap<K, V>) {
         self.size = source.size;
-        self.root = match source.root {
-            Some(ref root) => Some(root.clone()),
-            None => None,
-        }
+        self.root = source.root.clone();
+    }
+}
+
+impl<K, V> fmt::Debug for BtMap<K, V> where K: Ord + fmt::Debug, V: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
     }
 }
 
 use std::ops::Index;
 
-impl<'a, K, V, Q: ?Sized> Index<&'a Q> for BtMap<K, V>
-    where K: Ord + Borrow<Q>, Q: Ord
+impl<K, V, Q> Index<&Q> for BtMap<K, V>
+    where K: Ord + Borrow<Q>, Q: Ord + ?Sized
 {
     type Output = V;
     fn index(&self, index: &Q) -> &V {
@@ -140,12 +485,14 @@ impl<'a, K, V, Q: ?Sized> Index<&'a Q> for BtMap<K, V>
 
 #[cfg(test)]
 mod tests {
-    use super::BtMap;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use super::{BtMap, DuplicateKey, Link};
 
     #[test]
     fn test_empty() {
         let m : BtMap<i32, i32> = BtMap::empty();
-        assert!(m._len() == 0);
+        assert!(m.is_empty());
         assert!(m.get(&34).is_none());
         assert!(!m.contains_key(&34));
     }
@@ -158,11 +505,125 @@ mod tests {
         let m = m.insert(8876, "EightEightSevenSix".to_string()).unwrap();
         let m = m.insert(22, "TwentyTwo".to_string()).unwrap();
 
-        assert!(m._len() == 4);
+        assert!(m.len() == 4);
         assert!(m.get(&22).unwrap() == "TwentyTwo");
         assert!(m.get(&5543).unwrap() == "FiveFiveFourThree");
         assert!(m.get(&3332).is_none());
         assert!(m.contains_key(&22));
         assert!(!m.contains_key(&111));
     }
+
+    #[test]
+    fn test_insert_order() {
+        let keys = [5, 3, 4, 8, 1, 7, 2, 6, 0, 9];
+        let mut m : BtMap<usize, usize> = BtMap::empty();
+        for &k in keys.iter() {
+            m = m.insert(k, k * 10).unwrap();
+        }
+
+        assert!(m.len() == keys.len());
+        for &k in keys.iter() {
+            assert!(m.get(&k) == Some(&(k * 10)));
+        }
+        assert_eq!(m.insert(4, 0).err(), Some(DuplicateKey));
+    }
+
+    #[test]
+    fn test_debug() {
+        let m : BtMap<usize, &str> = BtMap::empty();
+        assert_eq!(format!("{:?}", m), "{}");
+        let m = m.insert(5, "five").unwrap().insert(3, "three").unwrap().insert(8, "eight").unwrap();
+        assert_eq!(format!("{:?}", m), r#"{3: "three", 5: "five", 8: "eight"}"#);
+    }
+
+    #[test]
+    fn test_replace() {
+        let m : BtMap<usize, usize> = BtMap::empty();
+        let m = m.insert(5, 50).unwrap().insert(3, 30).unwrap();
+        let r = m.replace(3, 33).replace(8, 80);
+
+        assert!(r.len() == 3);
+        assert!(r.get(&3) == Some(&33) && r.get(&8) == Some(&80) && r.get(&5) == Some(&50));
+        assert!(m.get(&3) == Some(&30) && m.get(&8).is_none());
+    }
+
+    #[test]
+    fn test_remove() {
+        let keys = [5, 3, 4, 8, 1, 7, 2, 6, 0, 9];
+        let m: BtMap<usize, usize> = keys.iter().map(|&k| (k, k * 10)).collect();
+        for &k in keys.iter() {
+            let r = m.remove(&k);
+            assert_eq!(r.len(), keys.len() - 1);
+            assert!(!r.contains_key(&k));
+            assert_eq!(r.keys().cloned().collect::<Vec<_>>(), (0..10).filter(|&i| i != k).collect::<Vec<_>>());
+        }
+        let r = keys.iter().fold(m.clone(), |r, k| r.remove(k));
+        assert!(r.is_empty() && r.iter().next().is_none());
+        assert_eq!(m.remove(&11).len(), keys.len());
+        assert_eq!(m.get(&5), Some(&50));
+    }
+
+    /// Checks that the subtree is balanced and its heights are right, and
+    /// returns its height.
+    fn check_balanced(link: &Link<usize, usize>) -> usize {
+        match *link {
+            Some(ref node) => {
+                let (lh, rh) = (check_balanced(&node.left), check_balanced(&node.right));
+                assert!(lh <= rh + 1 && rh <= lh + 1, "unbalanced at {}", node.key);
+                assert_eq!(node.height, 1 + lh.max(rh));
+                node.height
+            },
+            None => 0,
+        }
+    }
+
+    #[test]
+    fn test_balanced() {
+        // keys added in order would make a list of an unbalanced tree
+        let m: BtMap<usize, usize> = (0..100_000).map(|k| (k, k)).collect();
+        assert!(check_balanced(&m.root) <= 25);
+        assert_eq!(m.get(&99_999), Some(&99_999));
+
+        let r = (0..100_000).filter(|k| k % 3 != 0).fold(m.clone(), |r, k| r.remove(&k));
+        check_balanced(&r.root);
+        assert_eq!(r.len(), 33_334);
+        assert!(r.keys().cloned().eq((0..100_000).filter(|k| k % 3 == 0)));
+
+        let i = (0..1000).rev().fold(BtMap::empty(), |m, k| m.insert(k, k).unwrap());
+        check_balanced(&i.root);
+        assert_eq!(i.len(), 1000);
+    }
+
+    #[test]
+    fn test_iter() {
+        let m: BtMap<i32, char> = vec![(3, 'c'), (1, 'a'), (4, 'd'), (2, 'b'), (1, 'z')].into_iter().collect();
+        assert_eq!(m.len(), 4);
+        assert_eq!(m.iter().collect::<Vec<_>>(), vec![(&1, &'z'), (&2, &'b'), (&3, &'c'), (&4, &'d')]);
+        assert_eq!(m.values().collect::<String>(), "zbcd");
+        assert_eq!(m.range(2..4).map(|(k, _)| *k).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(m.range(..=2).map(|(k, _)| *k).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(m.range(5..).count(), 0);
+        assert_eq!(m.update(2, |c| c.unwrap().to_ascii_uppercase())[&2], 'B');
+        assert_eq!(m.update(7, |c| c.cloned().unwrap_or('g'))[&7], 'g');
+    }
+
+    #[test]
+    fn test_union_eq_hash() {
+        let a: BtMap<i32, i32> = vec![(1, 1), (2, 2)].into_iter().collect();
+        let b: BtMap<i32, i32> = vec![(2, 20), (3, 30), (4, 40)].into_iter().collect();
+        let u = a.union(&b);
+        assert_eq!(u.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(), vec![(1, 1), (2, 20), (3, 30), (4, 40)]);
+        assert_eq!(b.union(&a)[&2], 2);
+
+        // maps with the same items are equal however they were built
+        let c: BtMap<i32, i32> = vec![(4, 40), (3, 30), (2, 20), (1, 1)].into_iter().collect();
+        assert_eq!(u, c);
+        assert!(u != a);
+        let hash = |m: &BtMap<i32, i32>| {
+            let mut h = DefaultHasher::new();
+            m.hash(&mut h);
+            h.finish()
+        };
+        assert_eq!(hash(&u), hash(&c));
+    }
 }
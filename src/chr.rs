@@ -0,0 +1,544 @@
+//! # Constraint Handling Rules
+//!
+//! User-defined constraints can be simplified declaratively with Constraint
+//! Handling Rules.  A constraint is a name applied to some variables, such as
+//! `leq(X, Y)`; the constraints that have been told are kept in the state.
+//! Whenever a constraint is told, and whenever one of the variables of a
+//! constraint in the store is bound or unified with another, the rules of a
+//! `Chr` program are applied to the constraints in the store until none
+//! applies:
+//!
+//! - a **simplification** rule replaces the constraints it matches with its body;
+//! - a **propagation** rule keeps the constraints it matches, and adds its body;
+//! - a **simpagation** rule keeps some of the constraints it matches, and
+//!   replaces the others with its body.
+//!
+//! A rule's heads name the constraints it matches.  Its guard is a goal, built
+//! from the matched constraints, that must succeed without binding any
+//! variables for the rule to apply.  Its body is a goal, which may tell
+//! further constraints.  A propagation rule applies only once to any
+//! combination of constraints, as recorded in the state's propagation history.
+//!
+//! Telling a constraint is a goal, so constraints can appear anywhere goals
+//! can, such as inside `conj` and `disj`.
+//!
+//! ```
+//! use rslogic::chr::{Chr, Rule, identical};
+//! use rslogic::goal::{Goal, conj, unify_vars};
+//! use rslogic::state::State;
+//!
+//! let leq = Chr::<i32>::new()
+//!     .rule(Rule::simplification("reflexivity", &["leq"])
+//!         .guard(|c| Box::new(identical(&c[0].args[0], &c[0].args[1]))))
+//!     .rule(Rule::simplification("antisymmetry", &["leq", "leq"])
+//!         .guard(|c| Box::new(conj(identical(&c[0].args[0], &c[1].args[1]), identical(&c[0].args[1], &c[1].args[0]))))
+//!         .body(|_, c| Box::new(unify_vars(&c[0].args[0], &c[0].args[1]))))
+//!     .rule(Rule::propagation("transitivity", &["leq", "leq"])
+//!         .guard(|c| Box::new(identical(&c[0].args[1], &c[1].args[0])))
+//!         .body(|chr, c| Box::new(chr.tell("leq", &[c[0].args[0], c[1].args[1]]))));
+//!
+//! let s = State::<i32>::empty();
+//! let (a, s) = s.make_var();
+//! let (b, s) = s.make_var();
+//! let g = conj(leq.tell("leq", &[a, b]), leq.tell("leq", &[b, a]));
+//!
+//! let results = g.eval(&s);
+//! assert_eq!(results.len(), 1);
+//! assert!(identical(&a, &b).eval(&results[0]).len() == 1);
+//! assert!(rslogic::chr::constraints(&results[0]).is_empty());
+//! ```
+//!
+//! The variables of a told constraint are given an attribute (see `attr`)
+//! that applies the rules again when they are bound or unified, so the
+//! constraints in the store wake up as the variables they constrain change.
+
+use attr::{Attribute, Goals};
+use btmap::BtMap;
+use error::Error;
+use goal::Goal;
+use state::{Unif, Var, State, PossibleStates, expect};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// A user-defined constraint on some variables.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constraint {
+    pub name: String,
+    pub args: Vec<Var>,
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// The constraints that have been told in a state, and the combinations of
+/// them to which propagation rules have been applied.
+#[derive(Clone)]
+pub(crate) struct ConstraintStore {
+    constraints: Vec<(usize, Arc<Constraint>)>,
+    next_id: usize,
+    history: BtMap<(String, Vec<usize>), ()>,
+}
+
+impl ConstraintStore {
+    pub(crate) fn new() -> ConstraintStore {
+        ConstraintStore { constraints: Vec::new(), next_id: 0, history: BtMap::empty() }
+    }
+}
+
+/// Returns the constraints in the state's store, in the order in which they were told.
+pub fn constraints<T>(state: &State<T>) -> Vec<&Constraint> where T: PartialEq + Unif<T> {
+    state.constraints().constraints.iter().map(|c| &*c.1).collect()
+}
+
+/// Builds a goal from the constraints matched by a rule's heads.
+type Guard<T> = Arc<dyn Fn(&[Constraint]) -> Box<dyn Goal<T>> + Send + Sync>;
+
+/// Builds the body of a rule from the constraints matched by its heads.
+type Body<T> = Arc<dyn Fn(&Chr<T>, &[Constraint]) -> Box<dyn Goal<T>> + Send + Sync>;
+
+/// A constraint handling rule.
+pub struct Rule<T> where T: PartialEq + Unif<T> {
+    name: String,
+    kept: Vec<String>,
+    removed: Vec<String>,
+    guard: Option<Guard<T>>,
+    body: Option<Body<T>>,
+}
+
+impl<T> Clone for Rule<T> where T: PartialEq + Unif<T> {
+    fn clone(&self) -> Rule<T> {
+        Rule {
+            name: self.name.clone(),
+            kept: self.kept.clone(),
+            removed: self.removed.clone(),
+            guard: self.guard.clone(),
+            body: self.body.clone(),
+        }
+    }
+}
+
+impl<T> Rule<T> where T: PartialEq + Unif<T> {
+    fn new(name: &str, kept: &[&str], removed: &[&str]) -> Rule<T> {
+        Rule {
+            name: name.to_string(),
+            kept: kept.iter().map(|h| h.to_string()).collect(),
+            removed: removed.iter().map(|h| h.to_string()).collect(),
+            guard: None,
+            body: None,
+        }
+    }
+
+    /// Creates a rule that replaces the constraints with the given names with its body.
+    pub fn simplification(name: &str, heads: &[&str]) -> Rule<T> {
+        Rule::new(name, &[], heads)
+    }
+
+    /// Creates a rule that adds its body to the constraints with the given names.
+    pub fn propagation(name: &str, heads: &[&str]) -> Rule<T> {
+        Rule::new(name, heads, &[])
+    }
+
+    /// Creates a rule that keeps the constraints named by `kept`, and replaces
+    /// those named by `removed` with its body.
+    pub fn simpagation(name: &str, kept: &[&str], removed: &[&str]) -> Rule<T> {
+        Rule::new(name, kept, removed)
+    }
+
+    /// Returns the rule with a guard.  The guard is passed the matched
+    /// constraints, kept heads first, and returns a goal that must succeed
+    /// without binding any variables for the rule to apply.
+    pub fn guard<F>(self, guard: F) -> Rule<T> where F: Fn(&[Constraint]) -> Box<dyn Goal<T>> + Send + Sync + 'static {
+        Rule { guard: Some(Arc::new(guard)), .. self }
+    }
+
+    /// Returns the rule with a body.  The body is passed the program, with
+    /// which it can tell constraints, and the matched constraints, kept heads
+    /// first, and returns the goal to prove when the rule applies.
+    pub fn body<F>(self, body: F) -> Rule<T> where F: Fn(&Chr<T>, &[Constraint]) -> Box<dyn Goal<T>> + Send + Sync + 'static {
+        Rule { body: Some(Arc::new(body)), .. self }
+    }
+
+    fn heads(&self) -> impl Iterator<Item = &String> {
+        self.kept.iter().chain(self.removed.iter())
+    }
+}
+
+/// Evaluates the guard or body of a rule.
+type Eval<T> = dyn Fn(&dyn Goal<T>, &State<T>) -> Result<PossibleStates<T>, Error>;
+
+/// A program of constraint handling rules, which are tried in order.  Clones
+/// of a program share its rules.
+pub struct Chr<T> where T: PartialEq + Unif<T> {
+    rules: Arc<Vec<Rule<T>>>,
+}
+
+impl<T> Default for Chr<T> where T: PartialEq + Unif<T> {
+    fn default() -> Chr<T> {
+        Chr { rules: Arc::new(Vec::new()) }
+    }
+}
+
+impl<T> Clone for Chr<T> where T: PartialEq + Unif<T> {
+    fn clone(&self) -> Chr<T> {
+        Chr { rules: self.rules.clone() }
+    }
+}
+
+impl<T> Chr<T> where T: PartialEq + Unif<T> {
+    /// Creates a program with no rules.
+    pub fn new() -> Chr<T> {
+        Chr::default()
+    }
+
+    /// Returns the program with the rule added after its other rules.
+    pub fn rule(mut self, rule: Rule<T>) -> Chr<T> {
+        Arc::make_mut(&mut self.rules).push(rule);
+        self
+    }
+
+    /// Creates a goal that tells the constraint, then applies the rules.
+    pub fn tell(&self, name: &str, args: &[Var]) -> Tell<T> {
+        Tell { chr: self.clone(), constraint: Some(Constraint { name: name.to_string(), args: args.to_vec() }) }
+    }
+
+    /// Creates a goal that applies the rules to the constraints in the store.
+    /// The variables of the constraints wake the rules up when they are bound
+    /// or unified, so this is only needed when guards depend on other variables.
+    pub fn settle(&self) -> Tell<T> {
+        Tell { chr: self.clone(), constraint: None }
+    }
+
+    /// Applies the first rule that matches some constraints in the store, then
+    /// the rules again to each of the resulting states, until none applies.
+    /// Guards and bodies are evaluated with `eval`.
+    fn apply(&self, state: State<T>, eval: &Eval<T>) -> Result<PossibleStates<T>, Error> {
+        for rule in self.rules.iter() {
+            let heads: Vec<&String> = rule.heads().collect();
+            let mut chosen = Vec::with_capacity(heads.len());
+            if let Some(states) = self.try_rule(rule, &heads, &mut chosen, &state, eval)? {
+                let mut results = PossibleStates::new();
+                for state in states {
+                    results.append(&mut self.apply(state, eval)?);
+                }
+                return Ok(results);
+            }
+        }
+        Ok(vec![state])
+    }
+
+    /// Tries the rule with each combination of distinct constraints matching
+    /// the remaining heads, returning the result of the first that fires.
+    fn try_rule(&self, rule: &Rule<T>, heads: &[&String], chosen: &mut Vec<(usize, Arc<Constraint>)>, state: &State<T>, eval: &Eval<T>)
+        -> Result<Option<PossibleStates<T>>, Error>
+    {
+        if chosen.len() == heads.len() {
+            return self.fire(rule, chosen, state, eval);
+        }
+        let store = state.constraints();
+        for &(id, ref constraint) in &store.constraints {
+            if constraint.name != *heads[chosen.len()] || chosen.iter().any(|c| c.0 == id) {
+                continue;
+            }
+            chosen.push((id, constraint.clone()));
+            let fired = self.try_rule(rule, heads, chosen, state, eval);
+            chosen.pop();
+            if let Ok(None) = fired {
+                continue;
+            }
+            return fired;
+        }
+        Ok(None)
+    }
+
+    /// Fires the rule on the chosen constraints, if its guard succeeds and it
+    /// is not a propagation rule that has already fired on them.
+    fn fire(&self, rule: &Rule<T>, chosen: &[(usize, Arc<Constraint>)], state: &State<T>, eval: &Eval<T>)
+        -> Result<Option<PossibleStates<T>>, Error>
+    {
+        let store = state.constraints();
+        let ids: Vec<usize> = chosen.iter().map(|c| c.0).collect();
+        let key = (rule.name.clone(), ids);
+        if rule.removed.is_empty() && store.history.contains_key(&key) {
+            return Ok(None);
+        }
+        let matched: Vec<Constraint> = chosen.iter().map(|c| (*c.1).clone()).collect();
+        if let Some(ref guard) = rule.guard {
+            let entailed = eval(&*guard(&matched), state)?.iter().any(|s| s.same_bindings(state));
+            if !entailed {
+                return Ok(None);
+            }
+        }
+
+        let removed = &key.1[rule.kept.len()..];
+        let mut next = (**store).clone();
+        next.constraints.retain(|c| !removed.contains(&c.0));
+        if rule.removed.is_empty() {
+            next.history = next.history.replace(key.clone(), ());
+        }
+        let state = state.with_constraints(Arc::new(next));
+        Ok(Some(match rule.body {
+            Some(ref body) => eval(&*body(self, &matched), &state)?,
+            None => vec![state],
+        }))
+    }
+}
+
+/// Evaluating a `Tell` goal adds a constraint to the store, if it has one,
+/// then applies the rules of the program until none applies.
+pub struct Tell<T> where T: PartialEq + Unif<T> {
+    chr: Chr<T>,
+    constraint: Option<Constraint>,
+}
+
+impl<T> Tell<T> where T: PartialEq + Unif<T> + 'static {
+    /// Returns the state with the constraint added to its store, and the
+    /// program attached to the constraint's variables so that they wake it.
+    fn told(&self, state: &State<T>) -> State<T> {
+        match self.constraint {
+            Some(ref constraint) => {
+                let mut store = (**state.constraints()).clone();
+                store.constraints.push((store.next_id, Arc::new(constraint.clone())));
+                store.next_id += 1;
+                let state = state.with_constraints(Arc::new(store));
+                constraint.args.iter().fold(state, |state, var| Wake::attach(&state, var, &self.chr))
+            },
+            None => state.clone(),
+        }
+    }
+}
+
+/// An attribute of the variables of told constraints, which applies the
+/// programs that told them when the variables are bound or unified.
+struct Wake<T> where T: PartialEq + Unif<T> {
+    programs: Vec<Chr<T>>,
+}
+
+impl<T> Wake<T> where T: PartialEq + Unif<T> + 'static {
+    /// Returns the state with the program added to the variable's attribute,
+    /// if it is not there already.
+    fn attach(state: &State<T>, var: &Var, chr: &Chr<T>) -> State<T> {
+        let mut programs = match state.get_attr::<Wake<T>>(var) {
+            Some(wake) if wake.programs.iter().any(|p| Arc::ptr_eq(&p.rules, &chr.rules)) => return state.clone(),
+            Some(wake) => wake.programs.clone(),
+            None => Vec::new(),
+        };
+        programs.push(chr.clone());
+        state.put_attr(var, Wake { programs })
+    }
+
+    fn settle(&self) -> Goals<T> {
+        self.programs.iter().map(|chr| Box::new(chr.settle()) as Box<dyn Goal<T>>).collect()
+    }
+}
+
+impl<T> Attribute<T> for Wake<T> where T: PartialEq + Unif<T> + 'static {
+    fn verify_attributes(&self, _: &Var, _: &T, _: &State<T>) -> Option<Goals<T>> {
+        Some(self.settle())
+    }
+
+    fn verify_unified(&self, _: &Var, _: &State<T>) -> Option<Goals<T>> {
+        Some(self.settle())
+    }
+}
+
+impl<T> Goal<T> for Tell<T> where T: PartialEq + Unif<T> + 'static {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        expect(self.chr.apply(self.told(state), &|goal, state| Ok(goal.eval(state))))
+    }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        if let Some(ref constraint) = self.constraint {
+            for var in &constraint.args {
+                state.try_get(var)?;
+            }
+        }
+        self.chr.apply(self.told(state), &|goal, state| goal.try_eval(state))
+    }
+}
+
+/// A `Tell` goal is displayed as its constraint, or as `settle` if it has none.
+impl<T> fmt::Display for Tell<T> where T: PartialEq + Unif<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.constraint {
+            Some(ref constraint) => fmt::Display::fmt(constraint, f),
+            None => write!(f, "settle"),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Tell<T> where T: PartialEq + Unif<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Evaluating an `Identical` goal succeeds if the variables have been unified
+/// with each other, or are bound to equal values.  It binds no variables.
+pub struct Identical<T> where T: PartialEq + Unif<T> {
+    v1: Var,
+    v2: Var,
+    _m: PhantomData<T>,
+}
+
+impl<T> Goal<T> for Identical<T> where T: PartialEq + Unif<T> {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        let same = self.v1 == self.v2 || match (state.get(&self.v1), state.get(&self.v2)) {
+            (Some(a), Some(b)) => a == b,
+            (None, None) => state.slot(self.v1.index()).is_some() && state.slot(self.v1.index()) == state.slot(self.v2.index()),
+            _ => false,
+        };
+        if same { vec![state.clone()] } else { PossibleStates::new() }
+    }
+}
+
+impl<T> fmt::Display for Identical<T> where T: PartialEq + Unif<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} == {}", self.v1, self.v2)
+    }
+}
+
+impl<T> fmt::Debug for Identical<T> where T: PartialEq + Unif<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Creates an `Identical` goal, which tests whether the variables are already equal.
+pub fn identical<T>(v1: &Var, v2: &Var) -> Identical<T> where T: PartialEq + Unif<T> {
+    Identical { v1: *v1, v2: *v2, _m: PhantomData }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use goal::{Goal, conj, disj, unify_val, unify_vars};
+    use search::Search;
+    use state::{State, Var};
+    use store::{Trail, solve_all};
+    use super::{Chr, Rule, identical, constraints};
+
+    fn leq() -> Chr<i32> {
+        Chr::new()
+            .rule(Rule::simplification("reflexivity", &["leq"])
+                .guard(|c| Box::new(identical(&c[0].args[0], &c[0].args[1]))))
+            .rule(Rule::simplification("antisymmetry", &["leq", "leq"])
+                .guard(|c| Box::new(conj(identical(&c[0].args[0], &c[1].args[1]), identical(&c[0].args[1], &c[1].args[0]))))
+                .body(|_, c| Box::new(unify_vars(&c[0].args[0], &c[0].args[1]))))
+            .rule(Rule::simpagation("idempotence", &["leq"], &["leq"])
+                .guard(|c| Box::new(conj(identical(&c[0].args[0], &c[1].args[0]), identical(&c[0].args[1], &c[1].args[1])))))
+            .rule(Rule::propagation("transitivity", &["leq", "leq"])
+                .guard(|c| Box::new(identical(&c[0].args[1], &c[1].args[0])))
+                .body(|chr, c| Box::new(chr.tell("leq", &[c[0].args[0], c[1].args[1]]))))
+    }
+
+    fn vars(count: usize) -> (Vec<Var>, State<i32>) {
+        let mut s = State::empty();
+        let mut vars = Vec::new();
+        for _ in 0..count {
+            let (v, next) = s.make_var();
+            vars.push(v);
+            s = next;
+        }
+        (vars, s)
+    }
+
+    #[test]
+    fn test_leq_cycle() {
+        let chr = leq();
+        let (v, s) = vars(3);
+        let g = conj(chr.tell("leq", &[v[0], v[1]]), conj(chr.tell("leq", &[v[1], v[2]]), chr.tell("leq", &[v[2], v[0]])));
+
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(identical(&v[0], &v[1]).eval(&results[0]).len(), 1);
+        assert_eq!(identical(&v[1], &v[2]).eval(&results[0]).len(), 1);
+        assert!(constraints(&results[0]).is_empty());
+    }
+
+    #[test]
+    fn test_propagation_history() {
+        let chr = leq();
+        let (v, s) = vars(4);
+        let g = conj(chr.tell("leq", &[v[0], v[1]]), conj(chr.tell("leq", &[v[1], v[2]]), chr.tell("leq", &[v[2], v[3]])));
+
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        let mut pairs: Vec<(usize, usize)> = constraints(&results[0]).iter()
+            .map(|c| (v.iter().position(|&x| x == c.args[0]).unwrap(), v.iter().position(|&x| x == c.args[1]).unwrap()))
+            .collect();
+        pairs.sort();
+        assert_eq!(pairs, vec![(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)]);
+    }
+
+    #[test]
+    fn test_goals() {
+        // a constraint that its variable is not 2
+        let chr = Chr::<i32>::new()
+            .rule(Rule::simplification("not_two", &["not_two"])
+                .guard(|c| {
+                    let v = c[0].args[0];
+                    Box::new(unify_val(&v, 2))
+                })
+                .body(|_, _| Box::new(::goal::fail())));
+        let (v, s) = vars(1);
+
+        let g = conj(disj(unify_val(&v[0], 1), unify_val(&v[0], 2)), chr.tell("not_two", &[v[0]]));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get(&v[0]), Some(&1));
+
+        // bindings made after the constraint is told wake it up
+        let g = conj(chr.tell("not_two", &[v[0]]), disj(unify_val(&v[0], 1), unify_val(&v[0], 2)));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get(&v[0]), Some(&1));
+        assert_eq!(Search::new(&g, &s).count(), 1);
+        assert_eq!(solve_all(&g, &mut Trail::from_state(&s)).len(), 1);
+    }
+
+    #[test]
+    fn test_wake_on_unification() {
+        let chr = leq();
+        let (v, s) = vars(3);
+
+        let g = conj(chr.tell("leq", &[v[0], v[1]]), unify_vars(&v[0], &v[1]));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert!(constraints(&results[0]).is_empty());
+        let results = solve_all(&g, &mut Trail::from_state(&s));
+        assert_eq!(results.len(), 1);
+        assert!(constraints(&results[0]).is_empty());
+
+        // unifying the ends of a chain collapses it
+        let g = conj(conj(chr.tell("leq", &[v[0], v[1]]), chr.tell("leq", &[v[1], v[2]])), unify_vars(&v[2], &v[0]));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(identical(&v[0], &v[1]).eval(&results[0]).len(), 1);
+        assert!(constraints(&results[0]).is_empty());
+    }
+
+    #[test]
+    fn test_try_eval() {
+        let (foreign, _) = State::<i32>::empty().make_var();
+        let chr = Chr::<i32>::new()
+            .rule(Rule::simplification("bind", &["bind"])
+                .body(move |_, _| Box::new(unify_val(&foreign, 1))));
+        let (v, s) = vars(1);
+
+        assert_eq!(leq().tell("leq", &[v[0], foreign]).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(chr.tell("bind", &[v[0]]).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(leq().tell("leq", &[v[0], v[0]]).try_eval(&s).map(|r| r.len()), Ok(1));
+    }
+}
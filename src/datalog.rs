@@ -0,0 +1,490 @@
+//! # Datalog
+//!
+//! Bottom-up evaluation of rules over fact relations.
+//!
+//! A `Program` collects facts and rules.  Evaluating the program computes all
+//! the facts that the rules derive, using semi-naive evaluation: after the
+//! first round, a rule is only re-applied to joins involving at least one fact
+//! that was new in the previous round.  Rules are evaluated one stratum at a
+//! time, so that a negated relation has always been completely derived before
+//! it is consulted.
+//!
+//! The derived relations are exposed as goals, and so can be combined with the
+//! other goals using `conj`, `disj`, etc.
+//!
+//! ```
+//! use rslogic::state::State;
+//! use rslogic::goal::Goal;
+//! use rslogic::datalog::{Program, Arg, atom};
+//!
+//! let mut p = Program::<u32>::new();
+//! p.fact("edge", vec![1, 2]).unwrap();
+//! p.fact("edge", vec![2, 3]).unwrap();
+//!
+//! // path(x, y) :- edge(x, y).
+//! // path(x, z) :- edge(x, y), path(y, z).
+//! let (x, y, z) = (Arg::Var(0), Arg::Var(1), Arg::Var(2));
+//! p.rule(atom("path", vec![x.clone(), y.clone()]), vec![atom("edge", vec![x.clone(), y.clone()]).into()]).unwrap();
+//! p.rule(atom("path", vec![x.clone(), z.clone()]),
+//!        vec![atom("edge", vec![x, y.clone()]).into(), atom("path", vec![y, z]).into()]).unwrap();
+//!
+//! let db = p.evaluate().unwrap();
+//! assert!(db.relation("path").unwrap().contains(&[1, 3]));
+//!
+//! let s = State::<u32>::empty();
+//! let (a, s) = s.make_var();
+//! let (b, s) = s.make_var();
+//! assert_eq!(db.goal("path", &[a, b]).eval(&s).len(), 3);
+//! ```
+
+use facts::{Relation, RelationGoal};
+use state::{Unif, Var};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::hash::Hash;
+
+/// An argument of an atom: either a variable local to the rule, or a value.
+#[derive(Clone)]
+pub enum Arg<T> {
+    Var(usize),
+    Val(T),
+}
+
+/// An atom names a relation and supplies its arguments.
+#[derive(Clone)]
+pub struct Atom<T> {
+    relation: String,
+    args: Vec<Arg<T>>,
+}
+
+/// Creates an atom for the named relation.
+pub fn atom<T>(relation: &str, args: Vec<Arg<T>>) -> Atom<T> {
+    Atom { relation: relation.to_string(), args }
+}
+
+/// A literal in the body of a rule requires that an atom holds (`Pos`) or
+/// that it does not hold (`Neg`).
+#[derive(Clone)]
+pub enum Literal<T> {
+    Pos(Atom<T>),
+    Neg(Atom<T>),
+}
+
+impl<T> Literal<T> {
+    fn atom(&self) -> &Atom<T> {
+        match *self {
+            Literal::Pos(ref atom) | Literal::Neg(ref atom) => atom,
+        }
+    }
+}
+
+impl<T> From<Atom<T>> for Literal<T> {
+    fn from(atom: Atom<T>) -> Literal<T> {
+        Literal::Pos(atom)
+    }
+}
+
+/// A rule derives its head whenever all the literals in its body hold.
+struct Rule<T> {
+    head: Atom<T>,
+    body: Vec<Literal<T>>,
+}
+
+
+/// Errors that are detected while building or evaluating a program.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// A relation was used with a different number of arguments than before.
+    ArityMismatch { relation: String, expected: usize, found: usize },
+    /// A rule variable appears in the head or in a negated literal, but not
+    /// in any positive literal of the body.
+    UnsafeRule { relation: String, var: usize },
+    /// The relation depends negatively on itself.
+    NotStratifiable { relation: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ArityMismatch { ref relation, expected, found } =>
+                write!(f, "relation {} has arity {}, but was used with {} arguments", relation, expected, found),
+            Error::UnsafeRule { ref relation, var } =>
+                write!(f, "variable {} in a rule for {} does not appear in a positive body literal", var, relation),
+            Error::NotStratifiable { ref relation } =>
+                write!(f, "relation {} depends negatively on itself", relation),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+
+/// A collection of facts and rules.
+pub struct Program<T> where T: Clone + Eq + Hash + Unif<T> {
+    arities: HashMap<String, usize>,
+    facts: HashMap<String, Relation<T>>,
+    rules: Vec<Rule<T>>,
+}
+
+impl<T> Default for Program<T> where T: Clone + Eq + Hash + Unif<T> {
+    fn default() -> Program<T> {
+        Program::new()
+    }
+}
+
+impl<T> Program<T> where T: Clone + Eq + Hash + Unif<T> {
+    /// Creates an empty program.
+    pub fn new() -> Program<T> {
+        Program { arities: HashMap::new(), facts: HashMap::new(), rules: Vec::new() }
+    }
+
+    /// Adds a fact to the named relation.
+    pub fn fact(&mut self, relation: &str, tuple: Vec<T>) -> Result<(), Error> {
+        self.declare(relation, tuple.len())?;
+        let arity = tuple.len();
+        self.facts.entry(relation.to_string())
+            .or_insert_with(|| Relation::new(arity))
+            .insert(tuple);
+        Ok(())
+    }
+
+    /// Adds a rule that derives `head` whenever all the literals in `body` hold.
+    /// Every variable in the rule must appear in at least one positive literal.
+    pub fn rule(&mut self, head: Atom<T>, body: Vec<Literal<T>>) -> Result<(), Error> {
+        let mut bound = Vec::new();
+        for lit in body.iter() {
+            if let Literal::Pos(ref atom) = *lit {
+                bound.extend(atom.args.iter().filter_map(|arg| match *arg { Arg::Var(v) => Some(v), Arg::Val(_) => None }));
+            }
+        }
+
+        let negated = body.iter().filter_map(|lit| match *lit { Literal::Neg(ref atom) => Some(atom), Literal::Pos(_) => None });
+        for atom in Some(&head).into_iter().chain(negated) {
+            for arg in atom.args.iter() {
+                if let Arg::Var(v) = *arg {
+                    if !bound.contains(&v) {
+                        return Err(Error::UnsafeRule { relation: head.relation.clone(), var: v });
+                    }
+                }
+            }
+        }
+
+        self.declare(&head.relation, head.args.len())?;
+        for lit in body.iter() {
+            let atom = lit.atom();
+            self.declare(&atom.relation, atom.args.len())?;
+        }
+        self.rules.push(Rule { head, body });
+        Ok(())
+    }
+
+    /// Records the arity of a relation, or checks it against the recorded arity.
+    fn declare(&mut self, relation: &str, arity: usize) -> Result<(), Error> {
+        match self.arities.get(relation) {
+            Some(&expected) if expected != arity =>
+                Err(Error::ArityMismatch { relation: relation.to_string(), expected, found: arity }),
+            Some(_) => Ok(()),
+            None => {
+                self.arities.insert(relation.to_string(), arity);
+                Ok(())
+            }
+        }
+    }
+
+    /// Assigns each relation to a stratum, so that a relation only depends
+    /// positively on relations in the same or lower strata, and negatively on
+    /// relations in lower strata.
+    fn strata(&self) -> Result<HashMap<&str, usize>, Error> {
+        let mut strata: HashMap<&str, usize> = self.arities.keys().map(|name| (name.as_str(), 0)).collect();
+        let limit = self.arities.len();
+        loop {
+            let mut changed = false;
+            for rule in self.rules.iter() {
+                for lit in rule.body.iter() {
+                    let min = match *lit {
+                        Literal::Pos(ref atom) => strata[atom.relation.as_str()],
+                        Literal::Neg(ref atom) => strata[atom.relation.as_str()] + 1,
+                    };
+                    if strata[rule.head.relation.as_str()] < min {
+                        if min > limit {
+                            return Err(Error::NotStratifiable { relation: rule.head.relation.clone() });
+                        }
+                        strata.insert(rule.head.relation.as_str(), min);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return Ok(strata);
+            }
+        }
+    }
+
+    /// Evaluates the rules to a fixpoint, and returns a database containing
+    /// all the given and derived facts.
+    pub fn evaluate(&self) -> Result<Database<T>, Error> {
+        let strata = self.strata()?;
+        let mut full: HashMap<String, Relation<T>> = self.arities.iter()
+            .map(|(name, &arity)| (name.clone(), self.facts.get(name).cloned().unwrap_or_else(|| Relation::new(arity))))
+            .collect();
+
+        let top = strata.values().cloned().max().unwrap_or(0);
+        for stratum in 0..=top {
+            let rules: Vec<&Rule<T>> = self.rules.iter()
+                .filter(|rule| strata[rule.head.relation.as_str()] == stratum)
+                .collect();
+
+            // the first round considers all the facts
+            let mut delta: HashMap<String, Relation<T>> = HashMap::new();
+            for rule in rules.iter() {
+                self.fire(rule, &full, None, &mut delta);
+            }
+
+            // subsequent rounds only consider joins with new facts
+            while !delta.is_empty() {
+                for (name, rel) in delta.iter() {
+                    full.get_mut(name).unwrap().extend(rel.iter().cloned());
+                }
+
+                let mut next = HashMap::new();
+                for rule in rules.iter() {
+                    for (i, lit) in rule.body.iter().enumerate() {
+                        if let Literal::Pos(ref atom) = *lit {
+                            if let Some(rel) = delta.get(&atom.relation) {
+                                self.fire(rule, &full, Some((i, rel)), &mut next);
+                            }
+                        }
+                    }
+                }
+                delta = next;
+            }
+        }
+
+        Ok(Database { relations: full })
+    }
+
+    /// Applies a rule, adding any head tuples that are not already in `full` to `out`.
+    /// If `delta` is given, the literal at that position is matched against the
+    /// delta relation instead of the full relation.
+    fn fire(&self, rule: &Rule<T>, full: &HashMap<String, Relation<T>>,
+            delta: Option<(usize, &Relation<T>)>, out: &mut HashMap<String, Relation<T>>)
+    {
+        // negated literals are checked once all the variables are bound
+        let mut lits: Vec<(usize, &Literal<T>)> = rule.body.iter().enumerate()
+            .filter(|&(_, lit)| match *lit { Literal::Pos(_) => true, Literal::Neg(_) => false })
+            .collect();
+        lits.extend(rule.body.iter().enumerate().filter(|&(_, lit)| match *lit { Literal::Neg(_) => true, Literal::Pos(_) => false }));
+
+        let vars = rule.body.iter()
+            .flat_map(|lit| lit.atom().args.iter())
+            .filter_map(|arg| match *arg { Arg::Var(v) => Some(v + 1), Arg::Val(_) => None })
+            .max().unwrap_or(0);
+        let mut env = vec![None; vars];
+
+        let mut tuples = Vec::new();
+        join(&lits, full, delta, &mut env, &rule.head, &mut tuples);
+
+        let existing = &full[&rule.head.relation];
+        for tuple in tuples {
+            if !existing.contains(&tuple) {
+                out.entry(rule.head.relation.clone())
+                    .or_insert_with(|| Relation::new(existing.arity()))
+                    .insert(tuple);
+            }
+        }
+    }
+}
+
+/// Matches the literals in turn, binding the rule's variables in `env`, and
+/// adds an instance of the head to `out` for every complete match.
+fn join<T>(lits: &[(usize, &Literal<T>)], full: &HashMap<String, Relation<T>>, delta: Option<(usize, &Relation<T>)>,
+           env: &mut Vec<Option<T>>, head: &Atom<T>, out: &mut Vec<Vec<T>>)
+    where T: Clone + Eq + Hash + Unif<T>
+{
+    let instantiate = |atom: &Atom<T>, env: &Vec<Option<T>>| -> Vec<T> {
+        atom.args.iter().map(|arg| match *arg {
+            Arg::Var(v) => env[v].clone().expect("unbound variable in rule"),
+            Arg::Val(ref val) => val.clone(),
+        }).collect()
+    };
+
+    let (&(pos, lit), rest) = match lits.split_first() {
+        Some(first) => first,
+        None => {
+            out.push(instantiate(head, env));
+            return;
+        }
+    };
+
+    match *lit {
+        Literal::Pos(ref atom) => {
+            let rel = match delta {
+                Some((i, rel)) if i == pos => rel,
+                _ => &full[&atom.relation],
+            };
+            let pattern: Vec<Option<T>> = atom.args.iter().map(|arg| match *arg {
+                Arg::Var(v) => env[v].clone(),
+                Arg::Val(ref val) => Some(val.clone()),
+            }).collect();
+            let pattern: Vec<Option<&T>> = pattern.iter().map(Option::as_ref).collect();
+
+            for tuple in rel.select(&pattern) {
+                let mut bound = Vec::new();
+                let mut matched = true;
+                for (arg, val) in atom.args.iter().zip(tuple) {
+                    if let Arg::Var(v) = *arg {
+                        match env[v] {
+                            Some(ref existing) => if existing != val { matched = false; break },
+                            None => {
+                                env[v] = Some(val.clone());
+                                bound.push(v);
+                            }
+                        }
+                    }
+                }
+                if matched {
+                    join(rest, full, delta, env, head, out);
+                }
+                for v in bound {
+                    env[v] = None;
+                }
+            }
+        },
+        Literal::Neg(ref atom) => {
+            if !full[&atom.relation].contains(&instantiate(atom, env)) {
+                join(rest, full, delta, env, head, out);
+            }
+        },
+    }
+}
+
+
+/// The result of evaluating a program: the given and derived facts of every relation.
+pub struct Database<T> where T: Clone + Eq + Hash + Unif<T> {
+    relations: HashMap<String, Relation<T>>,
+}
+
+impl<T> Database<T> where T: Clone + Eq + Hash + Unif<T> {
+    /// Returns the named relation, or `None` if the program did not mention it.
+    pub fn relation(&self, name: &str) -> Option<&Relation<T>> {
+        self.relations.get(name)
+    }
+
+    /// Creates a goal that unifies the variables with the tuples of the named relation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the program did not mention the relation, or if the number
+    /// of variables is not the relation's arity.
+    pub fn goal(&self, relation: &str, args: &[Var]) -> RelationGoal<'_, T> {
+        self.relations.get(relation).expect("no relation with that name").goal(args)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use state::State;
+    use goal::{Goal, conj, unify_val};
+    use super::{Program, Arg, Atom, Literal, Error, atom};
+
+    fn v(n: usize) -> Arg<u32> {
+        Arg::Var(n)
+    }
+
+    fn edges(p: &mut Program<u32>, edges: &[(u32, u32)]) {
+        for &(a, b) in edges {
+            p.fact("edge", vec![a, b]).unwrap();
+        }
+    }
+
+    fn reachability(p: &mut Program<u32>) {
+        // path(x, y) :- edge(x, y).
+        // path(x, z) :- path(x, y), edge(y, z).
+        p.rule(atom("path", vec![v(0), v(1)]), vec![atom("edge", vec![v(0), v(1)]).into()]).unwrap();
+        p.rule(atom("path", vec![v(0), v(2)]),
+               vec![atom("path", vec![v(0), v(1)]).into(), atom("edge", vec![v(1), v(2)]).into()]).unwrap();
+    }
+
+    #[test]
+    fn test_transitive_closure() {
+        let mut p = Program::new();
+        edges(&mut p, &[(1, 2), (2, 3), (3, 4), (4, 2), (5, 6)]);
+        reachability(&mut p);
+
+        let db = p.evaluate().unwrap();
+        let path = db.relation("path").unwrap();
+        assert_eq!(path.len(), 3 + 3 + 3 + 3 + 1);
+        assert!(path.contains(&[1, 4]));
+        assert!(path.contains(&[2, 2]));
+        assert!(!path.contains(&[1, 1]));
+        assert!(!path.contains(&[1, 6]));
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut p = Program::new();
+        edges(&mut p, &[(1, 2), (2, 3), (3, 1), (4, 5)]);
+        reachability(&mut p);
+        // from_one(y) :- path(1, y).
+        p.rule(atom("from_one", vec![v(0)]), vec![atom("path", vec![Arg::Val(1), v(0)]).into()]).unwrap();
+
+        let db = p.evaluate().unwrap();
+        let mut found: Vec<u32> = db.relation("from_one").unwrap().iter().map(|t| t[0]).collect();
+        found.sort();
+        assert_eq!(found, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_negation() {
+        let mut p = Program::new();
+        edges(&mut p, &[(1, 2), (2, 3), (4, 5)]);
+        reachability(&mut p);
+        for n in 1..6 {
+            p.fact("node", vec![n]).unwrap();
+        }
+        // unreachable(y) :- node(y), not path(1, y).
+        p.rule(atom("unreachable", vec![v(0)]),
+               vec![atom("node", vec![v(0)]).into(), Literal::Neg(atom("path", vec![Arg::Val(1), v(0)]))]).unwrap();
+
+        let db = p.evaluate().unwrap();
+        let mut found: Vec<u32> = db.relation("unreachable").unwrap().iter().map(|t| t[0]).collect();
+        found.sort();
+        assert_eq!(found, vec![1, 4, 5]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut p = Program::<u32>::new();
+        p.fact("edge", vec![1, 2]).unwrap();
+        assert_eq!(p.fact("edge", vec![1]),
+                   Err(Error::ArityMismatch { relation: "edge".to_string(), expected: 2, found: 1 }));
+
+        let head: Atom<u32> = atom("bad", vec![v(0), v(1)]);
+        assert_eq!(p.rule(head, vec![atom("edge", vec![v(0), v(2)]).into()]),
+                   Err(Error::UnsafeRule { relation: "bad".to_string(), var: 1 }));
+
+        // p(x) :- edge(x, y), not p(y).
+        p.rule(atom("p", vec![v(0)]),
+               vec![atom("edge", vec![v(0), v(1)]).into(), Literal::Neg(atom("p", vec![v(1)]))]).unwrap();
+        assert_eq!(p.evaluate().err(), Some(Error::NotStratifiable { relation: "p".to_string() }));
+    }
+
+    #[test]
+    fn test_goal() {
+        let mut p = Program::new();
+        edges(&mut p, &[(1, 2), (2, 3), (3, 4)]);
+        reachability(&mut p);
+        let db = p.evaluate().unwrap();
+
+        let s = State::<u32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let g = conj(unify_val(&b, 4), db.goal("path", &[a, b]));
+
+        let mut found: Vec<u32> = g.eval(&s).iter().map(|r| *r.get(&a).unwrap()).collect();
+        found.sort();
+        assert_eq!(found, vec![1, 2, 3]);
+    }
+}
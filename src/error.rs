@@ -0,0 +1,81 @@
+//! # Errors
+//!
+//! Evaluating a goal can fail logically, producing no states, or it can go
+//! wrong: a variable can be used with a state that cannot know it, or a
+//! state's bindings can be found to be inconsistent.  The panicking APIs, such
+//! as `State::unify_val` and `Goal::eval`, treat the latter as bugs; their
+//! `try_` counterparts return an `Error` instead.
+//!
+//! ```
+//! use rslogic::Error;
+//! use rslogic::goal::{Goal, unify_val};
+//! use rslogic::state::State;
+//!
+//! let (a, _) = State::<i32>::empty().make_var();
+//! let s = State::<i32>::empty();
+//!
+//! match unify_val(&a, 1).try_eval(&s) {
+//!     Err(Error::ForeignVar(var)) => assert!(var == a),
+//!     _ => panic!("expected an error"),
+//! }
+//! ```
+
+use state::Var;
+use std::error;
+use std::fmt;
+
+/// An error in evaluating a goal, as opposed to a logical failure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The variable was created by a state of a different lineage.
+    ForeignVar(Var),
+    /// The variable was created after the state, so the state cannot bind it.
+    UnknownVar(Var),
+    /// The state's bindings of the variables are inconsistent, which is a bug.
+    Inconsistent(Vec<Var>),
+}
+
+impl Error {
+    /// Returns the variables that the error concerns.
+    pub fn vars(&self) -> &[Var] {
+        match self {
+            Error::ForeignVar(var) | Error::UnknownVar(var) => std::slice::from_ref(var),
+            Error::Inconsistent(vars) => vars,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ForeignVar(var) => write!(f, "variable {} belongs to a different state lineage", var),
+            Error::UnknownVar(var) => write!(f, "variable {} was created after the state", var),
+            Error::Inconsistent(vars) => {
+                write!(f, "inconsistent bindings of")?;
+                for var in vars {
+                    write!(f, " {}", var)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+
+#[cfg(test)]
+mod tests {
+    use state::Var;
+    use super::Error;
+
+    #[test]
+    fn test_display() {
+        let (a, b) = (Var::new(1, 0), Var::new(3, 0));
+        assert_eq!(Error::ForeignVar(a).to_string(), "variable _1 belongs to a different state lineage");
+        assert_eq!(Error::UnknownVar(b).to_string(), "variable _3 was created after the state");
+        let error = Error::Inconsistent(vec![a, b]);
+        assert_eq!(error.to_string(), "inconsistent bindings of _1 _3");
+        assert_eq!(error.vars(), [a, b]);
+    }
+}
@@ -0,0 +1,261 @@
+//! # Fact Relations
+//!
+//! A relation is an in-memory table of ground tuples.  Every column of the
+//! table is indexed, so that a relation goal only needs to consider the tuples
+//! that agree with the arguments that are already bound in the state.
+
+use error::Error;
+use state::{Unif, Var, State, PossibleStates};
+use goal::Goal;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// An indexed table of ground tuples, all of the same arity.
+#[derive(Clone)]
+pub struct Relation<T> where T: Clone + Eq + Hash + Unif<T> {
+    arity: usize,
+    tuples: Vec<Vec<T>>,
+    indexes: Vec<HashMap<T, Vec<usize>>>, // column -> value -> tuple positions
+}
+
+impl<T> Relation<T> where T: Clone + Eq + Hash + Unif<T> {
+    /// Creates an empty relation whose tuples have `arity` columns.
+    pub fn new(arity: usize) -> Relation<T> {
+        Relation {
+            arity,
+            tuples: Vec::new(),
+            indexes: (0..arity).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    /// Returns the number of columns in the relation's tuples.
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Returns the number of tuples in the relation.
+    pub fn len(&self) -> usize {
+        self.tuples.len()
+    }
+
+    /// Returns `true` if the relation contains no tuples.
+    pub fn is_empty(&self) -> bool {
+        self.tuples.is_empty()
+    }
+
+    /// Returns an iterator over the tuples in the order they were inserted.
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Vec<T>> {
+        self.tuples.iter()
+    }
+
+    /// Returns `true` if the relation contains the tuple.
+    pub fn contains(&self, tuple: &[T]) -> bool {
+        if tuple.len() != self.arity {
+            return false;
+        }
+        let pattern: Vec<Option<&T>> = tuple.iter().map(Some).collect();
+        self.select(&pattern).next().is_some()
+    }
+
+    /// Adds a tuple to the relation.  Returns `false` if the relation already
+    /// contained the tuple.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tuple does not have the relation's arity.
+    pub fn insert(&mut self, tuple: Vec<T>) -> bool {
+        assert_eq!(tuple.len(), self.arity, "tuple does not have the relation's arity");
+        if self.contains(&tuple) {
+            return false;
+        }
+
+        let row = self.tuples.len();
+        for (index, val) in self.indexes.iter_mut().zip(&tuple) {
+            index.entry(val.clone()).or_default().push(row);
+        }
+        self.tuples.push(tuple);
+        true
+    }
+
+    /// Creates a `RelationGoal` that unifies the variables with the columns of
+    /// each matching tuple in the relation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of variables is not the relation's arity.
+    pub fn goal(&self, args: &[Var]) -> RelationGoal<'_, T> {
+        assert_eq!(args.len(), self.arity, "wrong number of arguments for the relation");
+        RelationGoal { relation: self, args: args.to_vec() }
+    }
+
+    /// Returns an iterator over the tuples that match a pattern, where each
+    /// column of the pattern is either a value or `None` to match any value.
+    /// The smallest index among the pattern's values is used to find candidates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern does not have the relation's arity.
+    pub fn select<'s>(&'s self, pattern: &[Option<&'s T>]) -> impl Iterator<Item = &'s Vec<T>> + 's {
+        assert_eq!(pattern.len(), self.arity, "pattern does not have the relation's arity");
+
+        let mut best: Option<&Vec<usize>> = None;
+        let mut found = true;
+        for (index, val) in self.indexes.iter().zip(pattern) {
+            if let Some(val) = *val {
+                match index.get(val) {
+                    Some(rows) => if best.is_none_or(|b| rows.len() < b.len()) { best = Some(rows) },
+                    None => found = false,
+                }
+            }
+        }
+
+        let rows: Box<dyn Iterator<Item = usize> + 's> = match best {
+            _ if !found => Box::new(None.into_iter()),
+            Some(rows) => Box::new(rows.iter().cloned()),
+            None => Box::new(0..self.tuples.len()),
+        };
+        let pattern = pattern.to_vec();
+        rows.map(move |row| &self.tuples[row])
+            .filter(move |tuple| pattern.iter().zip(tuple.iter()).all(|(p, val)| p.is_none_or(|p| p == val)))
+    }
+}
+
+impl<T> Extend<Vec<T>> for Relation<T> where T: Clone + Eq + Hash + Unif<T> {
+    fn extend<I: IntoIterator<Item = Vec<T>>>(&mut self, iter: I) {
+        for tuple in iter {
+            self.insert(tuple);
+        }
+    }
+}
+
+
+/// Evaluating a `RelationGoal` produces one state for each tuple in the relation
+/// that unifies with the arguments.
+pub struct RelationGoal<'a, T> where T: Clone + Eq + Hash + Unif<T> + 'a {
+    relation: &'a Relation<T>,
+    args: Vec<Var>,
+}
+
+impl<'a, T> RelationGoal<'a, T> where T: Clone + Eq + Hash + Unif<T> {
+    /// Returns an iterator that lazily produces a state for each matching tuple.
+    pub fn matches<'s>(&'s self, state: &'s State<T>) -> impl Iterator<Item = State<T>> + 's {
+        let pattern: Vec<Option<&T>> = self.args.iter().map(|var| state.get(var)).collect();
+        self.relation.select(&pattern).flat_map(move |tuple| {
+            let mut states = vec![state.clone()];
+            for (var, val) in self.args.iter().zip(tuple) {
+                states = states.iter().flat_map(|s| s.unify_val(var, val.clone())).collect();
+            }
+            states
+        })
+    }
+}
+
+impl<'a, T> Goal<T> for RelationGoal<'a, T> where T: Clone + Eq + Hash + Unif<T> {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        self.matches(state).collect()
+    }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        for var in &self.args {
+            state.try_get(var)?;
+        }
+        Ok(self.matches(state).collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use state::State;
+    use goal::{Goal, conj, unify_val};
+    use super::Relation;
+
+    fn parents() -> Relation<u32> {
+        let mut rel = Relation::new(2);
+        rel.extend(vec![vec![1, 2], vec![1, 3], vec![2, 4], vec![3, 5], vec![3, 6]]);
+        rel
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut rel = parents();
+        assert_eq!(rel.len(), 5);
+        assert!(rel.contains(&[3, 5]));
+        assert!(!rel.contains(&[5, 3]));
+        assert!(!rel.insert(vec![2, 4]));
+        assert!(rel.insert(vec![4, 2]));
+        assert_eq!(rel.len(), 6);
+    }
+
+    #[test]
+    fn test_unbound() {
+        let rel = parents();
+        let s = State::<u32>::empty();
+        let (p, s) = s.make_var();
+        let (c, s) = s.make_var();
+
+        let results = rel.goal(&[p, c]).eval(&s);
+        assert_eq!(results.len(), 5);
+        let pairs: Vec<(u32, u32)> = results.iter().map(|r| (*r.get(&p).unwrap(), *r.get(&c).unwrap())).collect();
+        assert_eq!(pairs, vec![(1, 2), (1, 3), (2, 4), (3, 5), (3, 6)]);
+    }
+
+    #[test]
+    fn test_bound() {
+        let rel = parents();
+        let s = State::<u32>::empty();
+        let (p, s) = s.make_var();
+        let (c, s) = s.make_var();
+
+        let g = conj(unify_val(&p, 3), rel.goal(&[p, c]));
+        let results = g.eval(&s);
+        let children: Vec<u32> = results.iter().map(|r| *r.get(&c).unwrap()).collect();
+        assert_eq!(children, vec![5, 6]);
+
+        let g = conj(unify_val(&c, 4), rel.goal(&[p, c]));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get(&p).unwrap(), &2);
+
+        let g = conj(unify_val(&p, 7), rel.goal(&[p, c]));
+        assert_eq!(g.eval(&s).len(), 0);
+    }
+
+    #[test]
+    fn test_join() {
+        let rel = parents();
+        let s = State::<u32>::empty();
+        let (g, s) = s.make_var();
+        let (p, s) = s.make_var();
+        let (c, s) = s.make_var();
+
+        // grandparent(g, c) :- parent(g, p), parent(p, c)
+        let goal = conj(rel.goal(&[g, p]), rel.goal(&[p, c]));
+        let results = goal.eval(&s);
+        let pairs: Vec<(u32, u32)> = results.iter().map(|r| (*r.get(&g).unwrap(), *r.get(&c).unwrap())).collect();
+        assert_eq!(pairs, vec![(1, 4), (1, 5), (1, 6)]);
+    }
+
+    #[test]
+    fn test_repeated_var() {
+        let mut rel = Relation::new(2);
+        rel.extend(vec![vec![1, 1], vec![1, 2], vec![2, 2]]);
+        let s = State::<u32>::empty();
+        let (x, s) = s.make_var();
+
+        let results = rel.goal(&[x, x]).eval(&s);
+        let vals: Vec<u32> = results.iter().map(|r| *r.get(&x).unwrap()).collect();
+        assert_eq!(vals, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_try_eval() {
+        let rel = parents();
+        let s = State::<u32>::empty();
+        let (p, s) = s.make_var();
+        let (foreign, _) = State::<u32>::empty().make_var();
+        assert_eq!(rel.goal(&[p, foreign]).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(rel.goal(&[p, p]).try_eval(&s).map(|r| r.len()), Ok(0));
+    }
+}
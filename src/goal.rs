@@ -2,13 +2,158 @@
 //!
 //! Goals are used to specify logical statements.
 
-use state::{Unif, Var, State, PossibleStates};
+use error::Error;
+use search::{Search, SearchStrategy, DepthFirst, Node};
+use state::{Binding, Unif, Reify, Rename, Var, State, PossibleStates, expect};
+use store::{self, Store, Next};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
 use std::marker::PhantomData;
+use std::rc::Rc;
 
 /// Evaluate a `Goal` to produce zero or more `State`s, or
 /// collections of variable bindings.
 pub trait Goal<T> where T: PartialEq + Unif<T> {
     fn eval(&self, state: &State<T>) -> PossibleStates<T>;
+
+    /// Like `eval`, but returns an error instead of panicking if a variable
+    /// cannot be used with the state, or a state turns out to be
+    /// inconsistent.  A goal that fails logically returns no states.
+    ///
+    /// The default calls `eval`, which suits goals that neither unify
+    /// variables themselves nor evaluate sub-goals; a goal that does should
+    /// call the `try_eval` of its sub-goals.
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        Ok(self.eval(state))
+    }
+
+    /// Expands the goal by one step, for incremental evaluation by a
+    /// `search::Search`.  Each branch is an alternative state, along with the
+    /// sub-goals that remain to be proven in it.
+    ///
+    /// The default evaluates the goal completely, producing branches with no
+    /// remaining sub-goals.
+    fn expand<'a>(&'a self, state: &State<T>) -> Vec<Branch<'a, T>> {
+        self.eval(state).into_iter().map(Branch::done).collect()
+    }
+
+    /// Expands a goal that was created during the search, and is shared by
+    /// the branches that refer to it.  The resulting branches cannot borrow
+    /// from the goal itself.
+    ///
+    /// The default evaluates the goal completely.
+    fn expand_shared<'a>(self: Rc<Self>, state: &State<T>) -> Vec<Branch<'a, T>> where Self: 'a {
+        self.eval(state).into_iter().map(Branch::done).collect()
+    }
+
+    /// Proves the goal depth-first in a mutable `store::Store`, calling `next`
+    /// with the store as it is in each solution, until `next` returns `true`
+    /// to stop.  Returns `true` if it was stopped.  The store is left as it
+    /// was found.
+    ///
+    /// The default evaluates the goal in a snapshot of the store.
+    fn solve(&self, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool {
+        let states = self.eval(&store.snapshot());
+        store::each_state(states, store, next)
+    }
+
+    /// Calls `f` with each variable that the goal refers to, and returns
+    /// `true`; or returns `false` if the goal cannot tell which variables it
+    /// refers to.  A `search::Search` that compacts its states keeps the
+    /// bindings of the variables of the goals that remain to be proven, so it
+    /// leaves alone the states of paths with goals that cannot tell.
+    ///
+    /// The default returns `false`.
+    fn each_var(&self, _f: &mut dyn FnMut(&Var)) -> bool {
+        false
+    }
+}
+
+impl<T, G> Goal<T> for Box<G> where T: PartialEq + Unif<T>, G: Goal<T> + ?Sized {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        (**self).eval(state)
+    }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        (**self).try_eval(state)
+    }
+
+    fn expand<'a>(&'a self, state: &State<T>) -> Vec<Branch<'a, T>> {
+        (**self).expand(state)
+    }
+
+    fn solve(&self, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool {
+        (**self).solve(store, next)
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        (**self).each_var(f)
+    }
+}
+
+
+/// A goal that remains to be proven in a `Branch`.
+pub enum Subgoal<'a, T> where T: PartialEq + Unif<T> {
+    /// A goal that outlives the search, such as part of the original goal.
+    Borrowed(&'a (dyn Goal<T> + 'a)),
+    /// A goal that was created during the search.
+    Shared(Rc<dyn Goal<T> + 'a>),
+}
+
+impl<'a, T> Subgoal<'a, T> where T: PartialEq + Unif<T> {
+    /// Expands the sub-goal by one step.
+    pub fn expand(&self, state: &State<T>) -> Vec<Branch<'a, T>> {
+        match *self {
+            Subgoal::Borrowed(goal) => goal.expand(state),
+            Subgoal::Shared(ref goal) => goal.clone().expand_shared(state),
+        }
+    }
+
+    /// Calls `f` with each variable that the sub-goal refers to; see `Goal::each_var`.
+    pub fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        match *self {
+            Subgoal::Borrowed(goal) => goal.each_var(f),
+            Subgoal::Shared(ref goal) => goal.each_var(f),
+        }
+    }
+}
+
+impl<'a, T> Clone for Subgoal<'a, T> where T: PartialEq + Unif<T> {
+    fn clone(&self) -> Subgoal<'a, T> {
+        match *self {
+            Subgoal::Borrowed(goal) => Subgoal::Borrowed(goal),
+            Subgoal::Shared(ref goal) => Subgoal::Shared(goal.clone()),
+        }
+    }
+}
+
+/// One alternative resulting from expanding a goal: a state, and the sub-goals
+/// that must be proven in it, in order.  Taking the branch adds its cost to
+/// the total cost of the path through it.
+pub struct Branch<'a, T> where T: PartialEq + Unif<T> {
+    pub state: State<T>,
+    pub goals: Vec<Subgoal<'a, T>>,
+    pub cost: u64,
+}
+
+impl<'a, T> Branch<'a, T> where T: PartialEq + Unif<T> {
+    /// Creates a branch with no sub-goals remaining.
+    pub fn done(state: State<T>) -> Branch<'a, T> {
+        Branch { state, goals: Vec::new(), cost: 0 }
+    }
+
+    /// Creates a branch in which the sub-goals remain to be proven.
+    pub fn new(state: State<T>, goals: Vec<Subgoal<'a, T>>) -> Branch<'a, T> {
+        Branch { state, goals, cost: 0 }
+    }
+
+    /// Returns the branch with the given cost.
+    pub fn with_cost(self, cost: u64) -> Branch<'a, T> {
+        Branch { cost, .. self }
+    }
 }
 
 
@@ -21,6 +166,26 @@ impl<T> Goal<T> for Fail<T> where T: PartialEq + Unif<T> {
     fn eval(&self, _: &State<T>) -> PossibleStates<T> {
         Vec::with_capacity(0)
     }
+
+    fn solve(&self, _: &mut dyn Store<T>, _: &mut Next<T>) -> bool {
+        false
+    }
+
+    fn each_var(&self, _: &mut dyn FnMut(&Var)) -> bool {
+        true
+    }
+}
+
+impl<T> fmt::Display for Fail<T> where T: PartialEq + Unif<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fail")
+    }
+}
+
+impl<T> fmt::Debug for Fail<T> where T: PartialEq + Unif<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
 }
 
 /// Creates a `Fail` goal.
@@ -39,11 +204,36 @@ impl<T> Goal<T> for UnifyVal<T> where T: Clone + Eq + Unif<T> {
     fn eval(&self, state: &State<T>) -> PossibleStates<T> {
         state.unify_val(&self.var, self.val.clone())
     }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        state.try_unify_val(&self.var, self.val.clone())
+    }
+
+    fn solve(&self, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool {
+        store.unify_val(&self.var, self.val.clone(), next)
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        f(&self.var);
+        self.val.each_var(f)
+    }
+}
+
+impl<T> fmt::Display for UnifyVal<T> where T: PartialEq + Unif<T> + fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}", self.var, self.val)
+    }
+}
+
+impl<T> fmt::Debug for UnifyVal<T> where T: PartialEq + Unif<T> + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {:?}", self.var, self.val)
+    }
 }
 
 /// Creates a `UnifyVal` goal that attempts to unify the variable and the value.
 pub fn unify_val<T>(var: &Var, val: T) -> UnifyVal<T> where T: PartialEq + Unif<T> {
-    UnifyVal { var: *var, val: val }
+    UnifyVal { var: *var, val }
 }
 
 
@@ -58,6 +248,32 @@ impl<T> Goal<T> for UnifyVar<T> where T: PartialEq + Unif<T> {
     fn eval(&self, state: &State<T>) -> PossibleStates<T> {
         state.unify_var(&self.v1, &self.v2)
     }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        state.try_unify_var(&self.v1, &self.v2)
+    }
+
+    fn solve(&self, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool {
+        store.unify_vars(&self.v1, &self.v2, next)
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        f(&self.v1);
+        f(&self.v2);
+        true
+    }
+}
+
+impl<T> fmt::Display for UnifyVar<T> where T: PartialEq + Unif<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}", self.v1, self.v2)
+    }
+}
+
+impl<T> fmt::Debug for UnifyVar<T> where T: PartialEq + Unif<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
 }
 
 /// Creates a `UnifyVar` goal that attempts to unify the variables.
@@ -69,8 +285,8 @@ pub fn unify_vars<T>(v1: &Var, v2: &Var) -> UnifyVar<T> where T: PartialEq + Uni
 /// A `Conjunction` goal evaluates its sub-goal `a` using a given state,
 /// then evaluates sub-goal `b` using the results.
 pub struct Conjunction<T, A, B> where T: PartialEq + Unif<T>, A: Goal<T>, B: Goal<T> {
-    a: A,
-    b: B,
+    pub(crate) a: A,
+    pub(crate) b: B,
     _m: PhantomData<T>,
 }
 
@@ -84,43 +300,446 @@ impl<T, A, B> Goal<T> for Conjunction<T, A, B> where T: PartialEq + Unif<T>, A:
         }
         result
     }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        let mut result = PossibleStates::new();
+        for s in self.a.try_eval(state)? {
+            result.append(&mut self.b.try_eval(&s)?);
+        }
+        Ok(result)
+    }
+
+    fn expand<'a>(&'a self, state: &State<T>) -> Vec<Branch<'a, T>> {
+        vec![Branch::new(state.clone(), vec![Subgoal::Borrowed(&self.a), Subgoal::Borrowed(&self.b)])]
+    }
+
+    fn solve(&self, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool {
+        let b = &self.b;
+        self.a.solve(store, &mut |store: &mut dyn Store<T>| b.solve(store, next))
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        self.a.each_var(f) && self.b.each_var(f)
+    }
+}
+
+impl<T, A, B> fmt::Display for Conjunction<T, A, B> where T: PartialEq + Unif<T>, A: Goal<T> + fmt::Display, B: Goal<T> + fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {}", self.a, self.b)
+    }
+}
+
+impl<T, A, B> fmt::Debug for Conjunction<T, A, B> where T: PartialEq + Unif<T>, A: Goal<T> + fmt::Debug, B: Goal<T> + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}, {:?}", self.a, self.b)
+    }
 }
 
 /// Creates a `Conjunction` goal which returns the conjunction (logical AND) of evaluating the two sub-goals.
 pub fn conj<T, A, B>(a: A, b: B) -> Conjunction<T, A, B> where T: PartialEq + Unif<T>, A: Goal<T>, B: Goal<T> {
-    Conjunction { a: a, b: b, _m: PhantomData }
+    Conjunction { a, b, _m: PhantomData }
 }
 
 
 /// Evaluating a `Disjunction` goal returns all the possible states of evaluating `a` and `b`.
 pub struct Disjunction<T, A, B> where T: PartialEq + Unif<T>, A: Goal<T>, B: Goal<T> {
-    a: A,
-    b: B,
+    pub(crate) a: A,
+    pub(crate) b: B,
     _m: PhantomData<T>,
 }
 
 impl<T, A, B> Goal<T> for Disjunction<T, A, B> where T: PartialEq + Unif<T>, A: Goal<T>, B: Goal<T> {
     fn eval(&self, state: &State<T>) -> PossibleStates<T> {
-        let mut da = self.a.eval(state).into_iter();
-        let mut db = self.b.eval(state).into_iter();
-        let mut result: Vec<State<T>> = Vec::with_capacity(0);
-        loop {
-            let sa = da.next();
-            let sb = db.next();
-
-            let mut found = false;
-            if let Some(state) = sa { result.push(state); found = true; }
-            if let Some(state) = sb { result.push(state); found = true; }
-
-            if !found { break; }
+        if let Some(stats) = state.stats() {
+            stats.record_branches(2);
         }
-        result
+        interleave(self.a.eval(state), self.b.eval(state))
+    }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        if let Some(stats) = state.stats() {
+            stats.record_branches(2);
+        }
+        Ok(interleave(self.a.try_eval(state)?, self.b.try_eval(state)?))
+    }
+
+    fn expand<'a>(&'a self, state: &State<T>) -> Vec<Branch<'a, T>> {
+        vec![
+            Branch::new(state.clone(), vec![Subgoal::Borrowed(&self.a)]),
+            Branch::new(state.clone(), vec![Subgoal::Borrowed(&self.b)]),
+        ]
+    }
+
+    fn solve(&self, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool {
+        self.a.solve(store, next) || self.b.solve(store, next)
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        self.a.each_var(f) && self.b.each_var(f)
+    }
+}
+
+/// Alternates the states of the two disjuncts.
+fn interleave<T>(a: PossibleStates<T>, b: PossibleStates<T>) -> PossibleStates<T> where T: PartialEq + Unif<T> {
+    let mut da = a.into_iter();
+    let mut db = b.into_iter();
+    let mut result: Vec<State<T>> = Vec::with_capacity(0);
+    loop {
+        let sa = da.next();
+        let sb = db.next();
+
+        let mut found = false;
+        if let Some(state) = sa { result.push(state); found = true; }
+        if let Some(state) = sb { result.push(state); found = true; }
+
+        if !found { break; }
+    }
+    result
+}
+
+/// A disjunction is written in parentheses, as it binds less tightly than a conjunction.
+impl<T, A, B> fmt::Display for Disjunction<T, A, B> where T: PartialEq + Unif<T>, A: Goal<T> + fmt::Display, B: Goal<T> + fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}; {})", self.a, self.b)
+    }
+}
+
+impl<T, A, B> fmt::Debug for Disjunction<T, A, B> where T: PartialEq + Unif<T>, A: Goal<T> + fmt::Debug, B: Goal<T> + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({:?}; {:?})", self.a, self.b)
     }
 }
 
 /// Creates a `Disjunction` goal which returns the disjunction (logical OR) of evaluating the two sub-goals.
 pub fn disj<T, A, B>(a: A, b: B) -> Disjunction<T, A, B> where T: PartialEq + Unif<T>, A: Goal<T>, B: Goal<T> {
-    Disjunction { a: a, b: b, _m: PhantomData }
+    Disjunction { a, b, _m: PhantomData }
+}
+
+
+/// Proving a `Cost` goal proves its sub-goal, adding a cost to the path of
+/// the search on which it is proven.  Costs only matter to a search that
+/// takes them into account, such as `search::BestFirst`; `eval` ignores them.
+pub struct Cost<G> {
+    cost: u64,
+    goal: Rc<G>,
+}
+
+impl<T, G> Goal<T> for Cost<G> where T: PartialEq + Unif<T>, G: Goal<T> {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        self.goal.eval(state)
+    }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        self.goal.try_eval(state)
+    }
+
+    fn expand<'a>(&'a self, state: &State<T>) -> Vec<Branch<'a, T>> {
+        vec![Branch::new(state.clone(), vec![Subgoal::Borrowed(&*self.goal)]).with_cost(self.cost)]
+    }
+
+    fn expand_shared<'a>(self: Rc<Self>, state: &State<T>) -> Vec<Branch<'a, T>> where Self: 'a {
+        vec![Branch::new(state.clone(), vec![Subgoal::Shared(self.goal.clone())]).with_cost(self.cost)]
+    }
+
+    fn solve(&self, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool {
+        self.goal.solve(store, next)
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        self.goal.each_var(f)
+    }
+}
+
+impl<G> fmt::Display for Cost<G> where G: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cost({}, {})", self.cost, self.goal)
+    }
+}
+
+impl<G> fmt::Debug for Cost<G> where G: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cost({}, {:?})", self.cost, self.goal)
+    }
+}
+
+/// Creates a `Cost` goal, which proves the sub-goal at the given cost.
+pub fn cost<T, G>(cost: u64, goal: G) -> Cost<G> where T: PartialEq + Unif<T>, G: Goal<T> {
+    Cost { cost, goal: Rc::new(goal) }
+}
+
+
+/// Checks that the variables that the goal refers to (see `Goal::each_var`)
+/// can be used with the state.  Goals that search their sub-goals, rather
+/// than evaluating them, use it in `try_eval` to check them first.
+pub(crate) fn check_vars<T, G>(goal: &G, state: &State<T>) -> Result<(), Error> where T: PartialEq + Unif<T>, G: Goal<T> + ?Sized {
+    let mut vars = Vec::new();
+    goal.each_var(&mut |var| vars.push(*var));
+    for var in &vars {
+        state.try_get(var)?;
+    }
+    Ok(())
+}
+
+/// Gives an optimistic bound on the objective of any answer reachable from a state.
+type Bound<T> = Box<dyn Fn(&State<T>) -> Option<T>>;
+
+/// Evaluating an `Optimize` goal returns the states in which the sub-goal
+/// holds with the best value of the objective variable: the least value for
+/// `minimize`, or the greatest for `maximize`.  All the states that share the
+/// best value are returned.  Answers that leave the objective unbound are ignored.
+///
+/// The sub-goal is searched by branch and bound: once an answer has been
+/// found, any path whose objective cannot beat it is abandoned.  By default
+/// a path can only be judged once it binds the objective.  A bound function,
+/// given with `with_bound`, can judge paths earlier, by giving the best value
+/// that the objective could take on any answer reachable from a state; this
+/// is where the bounds of a finite-domain store would be supplied, as there
+/// is no such store in this crate.
+pub struct Optimize<T, G> where T: PartialEq + Unif<T> {
+    var: Var,
+    goal: G,
+    better: Ordering,
+    bound: Option<Bound<T>>,
+}
+
+impl<T, G> Optimize<T, G> where T: PartialEq + Unif<T> + Ord + Clone, G: Goal<T> {
+    /// Uses the function to bound the objective of the answers reachable from
+    /// each state.  The function must never return a value worse than that of
+    /// a reachable answer, or optimal answers may be missed.
+    pub fn with_bound<F>(self, bound: F) -> Optimize<T, G> where F: Fn(&State<T>) -> Option<T> + 'static {
+        Optimize { bound: Some(Box::new(bound)), .. self }
+    }
+
+    /// Returns the best value that the objective could take on the answers reachable from the state.
+    fn optimistic(&self, state: &State<T>) -> Option<T> {
+        match state.get(&self.var) {
+            Some(value) => Some(value.clone()),
+            None => self.bound.as_ref().and_then(|bound| bound(state)),
+        }
+    }
+}
+
+impl<T, G> Goal<T> for Optimize<T, G> where T: PartialEq + Unif<T> + Ord + Clone, G: Goal<T> {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        let best: Rc<RefCell<Option<T>>> = Rc::new(RefCell::new(None));
+        let strategy = Bounded { optimize: self, best: best.clone(), inner: DepthFirst::new() };
+
+        let mut results = Vec::new();
+        for answer in Search::with_strategy(&self.goal, state, Box::new(strategy)) {
+            let value = match answer.get(&self.var) {
+                Some(value) => value.clone(),
+                None => continue,
+            };
+            let order = match *best.borrow() {
+                Some(ref best) => value.cmp(best),
+                None => self.better,
+            };
+            if order == self.better {
+                *best.borrow_mut() = Some(value);
+                results.clear();
+                results.push(answer);
+            } else if order == Ordering::Equal {
+                results.push(answer);
+            }
+        }
+        results
+    }
+
+    /// Checks the objective and the variables of the sub-goal, then searches
+    /// as `eval` does, so that the same paths are abandoned.
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        state.try_get(&self.var)?;
+        check_vars(&self.goal, state)?;
+        Ok(self.eval(state))
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        // a bound function may look at any variable
+        if self.bound.is_some() {
+            return false;
+        }
+        f(&self.var);
+        self.goal.each_var(f)
+    }
+}
+
+impl<T, G> Optimize<T, G> where T: PartialEq + Unif<T> {
+    fn name(&self) -> &'static str {
+        if self.better == Ordering::Less { "minimize" } else { "maximize" }
+    }
+}
+
+impl<T, G> fmt::Display for Optimize<T, G> where T: PartialEq + Unif<T>, G: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({}, {})", self.name(), self.var, self.goal)
+    }
+}
+
+impl<T, G> fmt::Debug for Optimize<T, G> where T: PartialEq + Unif<T>, G: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({}, {:?})", self.name(), self.var, self.goal)
+    }
+}
+
+/// A depth-first search strategy that abandons the nodes that cannot lead to
+/// an answer as good as the best found so far.
+struct Bounded<'a, T, G> where T: PartialEq + Unif<T> + 'a, G: 'a {
+    optimize: &'a Optimize<T, G>,
+    best: Rc<RefCell<Option<T>>>,
+    inner: DepthFirst<'a, T>,
+}
+
+impl<'a, T, G> SearchStrategy<'a, T> for Bounded<'a, T, G> where T: PartialEq + Unif<T> + Ord + Clone, G: Goal<T> {
+    fn push(&mut self, children: Vec<Node<'a, T>>) {
+        self.inner.push(children);
+    }
+
+    fn pop(&mut self) -> Option<Node<'a, T>> {
+        while let Some(node) = self.inner.pop() {
+            let worse = match (self.best.borrow().as_ref(), self.optimize.optimistic(node.state())) {
+                (Some(best), Some(value)) => best.cmp(&value) == self.optimize.better,
+                _ => false,
+            };
+            if !worse {
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    fn restore(&mut self, node: Node<'a, T>) {
+        self.inner.restore(node);
+    }
+}
+
+/// Creates an `Optimize` goal that finds the answers of the sub-goal with the
+/// least value of the variable.
+pub fn minimize<T, G>(var: &Var, goal: G) -> Optimize<T, G> where T: PartialEq + Unif<T> + Ord + Clone, G: Goal<T> {
+    Optimize { var: *var, goal, better: Ordering::Less, bound: None }
+}
+
+/// Creates an `Optimize` goal that finds the answers of the sub-goal with the
+/// greatest value of the variable.
+pub fn maximize<T, G>(var: &Var, goal: G) -> Optimize<T, G> where T: PartialEq + Unif<T> + Ord + Clone, G: Goal<T> {
+    Optimize { var: *var, goal, better: Ordering::Greater, bound: None }
+}
+
+
+/// Decides which answers to keep, dropping those whose variables have the
+/// same values as an earlier answer's, once reified and with the variables
+/// left unbound in them renamed (see `State::form`).  In subsuming mode, it
+/// also drops answers that are instances of an earlier, more general answer.
+/// Answers must belong to the same lineage.
+pub struct AnswerFilter<T> where T: PartialEq + Unif<T> {
+    vars: Vec<Var>,
+    seen: HashSet<Vec<Binding<T>>>,
+    general: Option<Vec<State<T>>>,
+}
+
+impl<T> AnswerFilter<T> where T: Eq + Hash + Unif<T> + Reify<T> + Rename<T> {
+    /// Creates a filter that compares answers by the values of the variables.
+    pub fn new(vars: &[Var]) -> AnswerFilter<T> {
+        AnswerFilter { vars: vars.to_vec(), seen: HashSet::new(), general: None }
+    }
+
+    /// Also drops the answers that are subsumed by earlier ones (see
+    /// `State::subsumes`).  Each answer is compared with every answer kept
+    /// before it.
+    pub fn subsuming(self) -> AnswerFilter<T> {
+        AnswerFilter { general: Some(Vec::new()), .. self }
+    }
+
+    /// Returns `true` if the answer should be kept, and remembers it.
+    pub fn admit(&mut self, answer: &State<T>) -> bool {
+        if !self.seen.insert(answer.form(&self.vars)) {
+            return false;
+        }
+        let vars = &self.vars;
+        if let Some(ref mut general) = self.general {
+            if general.iter().any(|earlier| earlier.subsumes(answer, vars)) {
+                return false;
+            }
+            general.push(answer.clone());
+        }
+        true
+    }
+}
+
+/// Evaluating a `Distinct` goal returns the states of its sub-goal, without
+/// those that repeat an earlier state's answer: the values of the variables
+/// of the state in which the goal is evaluated, or of the variables given
+/// with `on`.  See `AnswerFilter`.
+pub struct Distinct<T, G> where T: PartialEq + Unif<T> {
+    goal: G,
+    vars: Option<Vec<Var>>,
+    subsume: bool,
+    _m: PhantomData<T>,
+}
+
+impl<T, G> Distinct<T, G> where T: PartialEq + Unif<T> {
+    /// Compares answers by the values of the given variables only.
+    pub fn on(self, vars: &[Var]) -> Distinct<T, G> {
+        Distinct { vars: Some(vars.to_vec()), .. self }
+    }
+
+    /// Also drops the answers that are subsumed by earlier ones.
+    pub fn subsuming(self) -> Distinct<T, G> {
+        Distinct { subsume: true, .. self }
+    }
+}
+
+impl<T, G> Distinct<T, G> where T: Eq + Hash + Unif<T> + Reify<T> + Rename<T> {
+    /// Keeps the answers found from the state that do not repeat earlier ones.
+    fn filter(&self, state: &State<T>, answers: PossibleStates<T>) -> PossibleStates<T> {
+        let vars = match self.vars {
+            Some(ref vars) => vars.clone(),
+            None => (0..state.next_index()).map(|index| Var::new(index, state.lineage())).collect(),
+        };
+        let mut filter = AnswerFilter::new(&vars);
+        if self.subsume {
+            filter = filter.subsuming();
+        }
+        answers.into_iter().filter(|answer| filter.admit(answer)).collect()
+    }
+}
+
+impl<T, G> Goal<T> for Distinct<T, G> where T: Eq + Hash + Unif<T> + Reify<T> + Rename<T>, G: Goal<T> {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        self.filter(state, self.goal.eval(state))
+    }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        if let Some(ref vars) = self.vars {
+            for var in vars {
+                state.try_get(var)?;
+            }
+        }
+        Ok(self.filter(state, self.goal.try_eval(state)?))
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        if let Some(ref vars) = self.vars {
+            vars.iter().for_each(&mut *f);
+        }
+        self.goal.each_var(f)
+    }
+}
+
+impl<T, G> fmt::Display for Distinct<T, G> where T: PartialEq + Unif<T>, G: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "distinct({})", self.goal)
+    }
+}
+
+impl<T, G> fmt::Debug for Distinct<T, G> where T: PartialEq + Unif<T>, G: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "distinct({:?})", self.goal)
+    }
+}
+
+/// Creates a `Distinct` goal, which drops the repeated answers of the sub-goal.
+pub fn distinct<T, G>(goal: G) -> Distinct<T, G> where T: PartialEq + Unif<T>, G: Goal<T> {
+    Distinct { goal, vars: None, subsume: false, _m: PhantomData }
 }
 
 
@@ -139,11 +758,218 @@ impl<'a, T, F> Goal<T> for Predicate<'a, T, F> where T: PartialEq + Unif<T>, F:
             Vec::with_capacity(0)
         }
     }
+
+    fn solve(&self, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool {
+        let f = self.f;
+        f(&store.snapshot()) && next(store)
+    }
+}
+
+impl<'a, T, F> fmt::Display for Predicate<'a, T, F> where T: PartialEq + Unif<T>, F: Fn(&State<T>) -> bool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pred")
+    }
+}
+
+impl<'a, T, F> fmt::Debug for Predicate<'a, T, F> where T: PartialEq + Unif<T>, F: Fn(&State<T>) -> bool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
 }
 
 /// Creates a `Predicate` goal that filters a set of possible states with the given function.
 pub fn pred<'a, T, F>(f: &'a F) -> Predicate<'a, T, F> where T: PartialEq + Unif<T>, F: Fn(&State<T>) -> bool {
-    Predicate { f: f, _m: PhantomData }
+    Predicate { f, _m: PhantomData }
+}
+
+
+/// The ports through which evaluation enters and leaves a goal, in the
+/// "box" model of Prolog tracers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+    /// The goal is about to be evaluated.
+    Call,
+    /// The goal has produced a solution.
+    Exit,
+    /// Evaluation has come back to the goal to look for another solution.
+    Redo,
+    /// The goal has no (more) solutions.
+    Fail,
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Port::Call => "Call",
+            Port::Exit => "Exit",
+            Port::Redo => "Redo",
+            Port::Fail => "Fail",
+        };
+        f.pad(name)
+    }
+}
+
+/// Receives a report each time evaluation passes through a port of a traced goal.
+pub trait Tracer<T> where T: PartialEq + Unif<T> {
+    /// Reports that evaluation has passed through a port of the goal described
+    /// by `goal`.  `depth` is the number of traced goals that enclose it, and
+    /// `state` holds the current bindings.
+    fn port(&self, port: Port, depth: usize, goal: &str, state: &State<T>);
+}
+
+/// Describes the bindings in a state, for a `PrettyTracer`.
+type Describe<T> = Box<dyn Fn(&State<T>) -> String>;
+
+/// A `Tracer` that formats each report as a line of text, indented according
+/// to its depth.  The lines are either kept, so that tests can examine them,
+/// or written to standard error.
+pub struct PrettyTracer<T> where T: PartialEq + Unif<T> {
+    lines: Option<RefCell<Vec<String>>>,
+    bindings: Option<Describe<T>>,
+}
+
+impl<T> Default for PrettyTracer<T> where T: PartialEq + Unif<T> {
+    fn default() -> PrettyTracer<T> {
+        PrettyTracer::new()
+    }
+}
+
+impl<T> PrettyTracer<T> where T: PartialEq + Unif<T> {
+    /// Creates a tracer that keeps the lines it formats.
+    pub fn new() -> PrettyTracer<T> {
+        PrettyTracer { lines: Some(RefCell::new(Vec::new())), bindings: None }
+    }
+
+    /// Creates a tracer that writes the lines it formats to standard error.
+    pub fn stderr() -> PrettyTracer<T> {
+        PrettyTracer { lines: None, bindings: None }
+    }
+
+    /// Uses the function to describe the bindings in the state at each port.
+    pub fn with_bindings<F>(self, f: F) -> PrettyTracer<T> where F: Fn(&State<T>) -> String + 'static {
+        PrettyTracer { bindings: Some(Box::new(f)), .. self }
+    }
+
+    /// Describes the bindings in the state at each port by the values of its
+    /// named variables, as in `X = 1, Y = f(2)`.
+    pub fn with_answers(self) -> PrettyTracer<T> where T: Reify<T> + fmt::Display + 'static {
+        self.with_bindings(|state| {
+            let answer: Vec<String> = state.answer().iter().map(|(name, val)| format!("{} = {}", name, val)).collect();
+            answer.join(", ")
+        })
+    }
+
+    /// Returns the lines formatted so far, if the tracer keeps them.
+    pub fn lines(&self) -> Vec<String> {
+        match self.lines {
+            Some(ref lines) => lines.borrow().clone(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl<T> Tracer<T> for PrettyTracer<T> where T: PartialEq + Unif<T> {
+    fn port(&self, port: Port, depth: usize, goal: &str, state: &State<T>) {
+        let mut line = format!("{:indent$}{}: {}", "", port, goal, indent = depth * 2);
+        if let Some(ref bindings) = self.bindings {
+            line = format!("{} {{{}}}", line, bindings(state));
+        }
+        match self.lines {
+            Some(ref lines) => lines.borrow_mut().push(line),
+            None => eprintln!("{}", line),
+        }
+    }
+}
+
+/// Keeps track of the depth of traced goals, and passes their reports on to a `Tracer`.
+pub struct Tracing<'t, T> where T: PartialEq + Unif<T> {
+    tracer: &'t dyn Tracer<T>,
+    depth: Cell<usize>,
+}
+
+impl<'t, T> Tracing<'t, T> where T: PartialEq + Unif<T> {
+    /// Creates a tracing context that reports to the tracer.
+    pub fn new(tracer: &'t dyn Tracer<T>) -> Tracing<'t, T> {
+        Tracing { tracer, depth: Cell::new(0) }
+    }
+
+    /// Creates a `Traced` goal that reports the ports of the goal under the given name.
+    pub fn goal<G>(&self, name: &str, goal: G) -> Traced<'_, 't, T, G> where G: Goal<T> {
+        Traced { tracing: self, name: name.to_string(), goal }
+    }
+}
+
+/// Evaluating a `Traced` goal evaluates its sub-goal, reporting each port that
+/// evaluation passes through to the tracer.
+///
+/// The sub-goal is always evaluated completely, even when it is part of a
+/// `search::Search`.
+pub struct Traced<'c, 't, T, G> where T: PartialEq + Unif<T> + 'c, G: Goal<T>, 't: 'c {
+    tracing: &'c Tracing<'t, T>,
+    name: String,
+    goal: G,
+}
+
+impl<'c, 't, T, G> Traced<'c, 't, T, G> where T: PartialEq + Unif<T>, G: Goal<T> {
+    /// Evaluates the sub-goal with `eval`, reporting the ports it passes
+    /// through.  An error is reported as a failure.
+    fn trace<E>(&self, state: &State<T>, eval: E) -> Result<PossibleStates<T>, Error>
+        where E: FnOnce(&G, &State<T>) -> Result<PossibleStates<T>, Error>
+    {
+        let tracer = self.tracing.tracer;
+        let depth = self.tracing.depth.get();
+        tracer.port(Port::Call, depth, &self.name, state);
+
+        self.tracing.depth.set(depth + 1);
+        let results = eval(&self.goal, state);
+        self.tracing.depth.set(depth);
+        let results = match results {
+            Ok(results) => results,
+            Err(error) => {
+                tracer.port(Port::Fail, depth, &self.name, state);
+                return Err(error);
+            },
+        };
+
+        for (i, result) in results.iter().enumerate() {
+            if i > 0 {
+                tracer.port(Port::Redo, depth, &self.name, state);
+            }
+            tracer.port(Port::Exit, depth, &self.name, result);
+        }
+        if !results.is_empty() {
+            tracer.port(Port::Redo, depth, &self.name, state);
+        }
+        tracer.port(Port::Fail, depth, &self.name, state);
+        Ok(results)
+    }
+}
+
+impl<'c, 't, T, G> Goal<T> for Traced<'c, 't, T, G> where T: PartialEq + Unif<T>, G: Goal<T> {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        expect(self.trace(state, |goal, state| Ok(goal.eval(state))))
+    }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        self.trace(state, |goal, state| goal.try_eval(state))
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        self.goal.each_var(f)
+    }
+}
+
+/// A traced goal is displayed as its sub-goal.
+impl<'c, 't, T, G> fmt::Display for Traced<'c, 't, T, G> where T: PartialEq + Unif<T>, G: Goal<T> + fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.goal, f)
+    }
+}
+
+impl<'c, 't, T, G> fmt::Debug for Traced<'c, 't, T, G> where T: PartialEq + Unif<T>, G: Goal<T> + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "trace({:?}, {:?})", self.name, self.goal)
+    }
 }
 
 
@@ -153,6 +979,26 @@ macro_rules! unif_prim {
             fn unify(&self, other: &$t, prev: &State<$t>) -> PossibleStates<$t> {
                 if self.eq(other) { vec![prev.clone()] } else { PossibleStates::new() }
             }
+
+            fn unify_in(&self, other: &$t, store: &mut dyn Store<$t>, next: &mut Next<$t>) -> bool {
+                self.eq(other) && next(store)
+            }
+
+            fn each_var(&self, _: &mut dyn FnMut(&Var)) -> bool {
+                true
+            }
+        }
+
+        impl Reify<$t> for $t {
+            fn reify(&self, _: &State<$t>) -> $t {
+                self.clone()
+            }
+        }
+
+        impl Rename<$t> for $t {
+            fn rename(&self, _: &mut dyn FnMut(&Var) -> Var) -> $t {
+                self.clone()
+            }
         }
     }
 }
@@ -176,8 +1022,12 @@ unif_prim!(String);
 
 #[cfg(test)]
 mod tests {
-    use state::{State};
-    use super::{Goal, fail, unify_val, unify_vars, conj, disj, pred};
+    use error::Error;
+    use state::{State, PossibleStates};
+    use term::Term;
+    use testing::Never;
+    use super::{AnswerFilter, Goal, PrettyTracer, Tracing};
+    use super::{fail, unify_val, unify_vars, conj, disj, distinct, pred, minimize, maximize};
 
     #[test]
     fn test_bind_val() {
@@ -274,4 +1124,170 @@ mod tests {
         let val = results[0].get(&a).unwrap();
         assert_eq!(val, &987);
     }
+
+    #[test]
+    fn test_trace() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+
+        let tracer = PrettyTracer::new().with_bindings(move |s: &State<i32>| match s.get(&a) {
+            Some(n) => format!("a = {}", n),
+            None => "a unbound".to_string(),
+        });
+        let tracing = Tracing::new(&tracer);
+        let choose = tracing.goal("choose", disj(unify_val(&a, 1), unify_val(&a, 2)));
+        let check = tracing.goal("check", conj(unify_vars(&a, &b), unify_val(&b, 2)));
+        let g = tracing.goal("query", conj(choose, check));
+
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(tracer.lines(), vec![
+            "Call: query {a unbound}",
+            "  Call: choose {a unbound}",
+            "  Exit: choose {a = 1}",
+            "  Redo: choose {a unbound}",
+            "  Exit: choose {a = 2}",
+            "  Redo: choose {a unbound}",
+            "  Fail: choose {a unbound}",
+            "  Call: check {a = 1}",
+            "  Fail: check {a = 1}",
+            "  Call: check {a = 2}",
+            "  Exit: check {a = 2}",
+            "  Redo: check {a = 2}",
+            "  Fail: check {a = 2}",
+            "Exit: query {a = 2}",
+            "Redo: query {a unbound}",
+            "Fail: query {a unbound}",
+        ]);
+    }
+
+    #[test]
+    fn test_trace_answers() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_named_var("A");
+        let (b, s) = s.make_var();
+
+        let tracer = PrettyTracer::new().with_answers();
+        let tracing = Tracing::new(&tracer);
+        let g = tracing.goal("choose", conj(unify_val(&b, 0), disj(unify_val(&a, 1), unify_val(&a, 2))));
+        assert_eq!(g.eval(&s).len(), 2);
+        assert_eq!(tracer.lines(), vec![
+            "Call: choose {}",
+            "Exit: choose {A = 1}",
+            "Redo: choose {}",
+            "Exit: choose {A = 2}",
+            "Redo: choose {}",
+            "Fail: choose {}",
+        ]);
+    }
+
+    #[test]
+    fn test_display() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, _) = s.make_var();
+        let g = conj(unify_vars(&a, &b), disj(unify_val(&a, 1), conj(unify_val(&b, 2), fail())));
+        assert_eq!(g.to_string(), "_0 = _1, (_0 = 1; _1 = 2, fail)");
+        assert_eq!(format!("{:?}", minimize(&a, unify_val(&a, 3))), "minimize(_0, _0 = 3)");
+    }
+
+    #[test]
+    fn test_optimize() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let choose = |v, ns: [i32; 4]| disj(disj(unify_val(v, ns[0]), unify_val(v, ns[1])),
+                                            disj(unify_val(v, ns[2]), unify_val(v, ns[3])));
+
+        let results = minimize(&a, choose(&a, [4, 2, 7, 2])).eval(&s);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|s| s.get(&a) == Some(&2)));
+
+        let results = maximize(&a, conj(choose(&a, [4, 2, 7, 2]), choose(&b, [1, 2, 3, 4]))).eval(&s);
+        assert_eq!(results.len(), 4);
+        assert!(results.iter().all(|s| s.get(&a) == Some(&7)));
+        assert!(minimize(&a, fail()).eval(&s).is_empty());
+    }
+
+    #[test]
+    fn test_branch_and_bound() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+
+        // once a = 1 is found, the path binding a = 5 is abandoned before it runs forever
+        let g = minimize(&a, disj(unify_val(&a, 1), conj(unify_val(&a, 5), Never)));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get(&a), Some(&1));
+        assert_eq!(g.try_eval(&s).map(|r| r.len()), Ok(1));
+
+        // with a bound, a path can be abandoned before it binds the objective
+        let g = minimize(&a, disj(conj(unify_val(&b, 0), unify_val(&a, 3)),
+                                  conj(unify_val(&b, 1), conj(Never, unify_val(&a, 4)))))
+            .with_bound(move |s| s.get(&b).map(|b| b + 3));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get(&a), Some(&3));
+    }
+
+    #[test]
+    fn test_distinct() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let g = disj(unify_val(&a, 1), disj(unify_val(&a, 2), unify_val(&a, 1)));
+        assert_eq!(g.eval(&s).len(), 3);
+        let values = |states: PossibleStates<i32>| states.iter().map(|s| *s.get(&a).unwrap()).collect::<Vec<_>>();
+        assert_eq!(values(distinct(g).eval(&s)), vec![1, 2]);
+
+        // by default every variable of the state counts; `on` picks some of them
+        let (b, t) = s.make_var();
+        let g = || conj(unify_val(&a, 1), disj(unify_val(&b, 1), unify_val(&b, 2)));
+        assert_eq!(distinct(g()).eval(&t).len(), 2);
+        assert_eq!(distinct(g()).on(&[a]).eval(&t).len(), 1);
+        assert_eq!(distinct(g()).to_string(), "distinct(_0 = 1, (_1 = 1; _1 = 2))");
+    }
+
+    #[test]
+    fn test_try_eval() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (foreign, _) = State::<i32>::empty().make_var();
+        let g = || disj(unify_val(&a, 1), unify_val(&foreign, 2));
+        assert_eq!(distinct(g()).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(distinct(unify_val(&a, 1)).on(&[foreign]).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(minimize(&a, g()).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(maximize(&foreign, unify_val(&a, 1)).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+
+        let tracer = PrettyTracer::new();
+        let tracing = Tracing::new(&tracer);
+        assert_eq!(tracing.goal("g", g()).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+
+        let values = |states: PossibleStates<i32>| states.iter().map(|s| *s.get(&a).unwrap()).collect::<Vec<_>>();
+        let g = || disj(unify_val(&a, 2), disj(unify_val(&a, 1), unify_val(&a, 2)));
+        assert_eq!(values(distinct(g()).try_eval(&s).unwrap()), vec![2, 1]);
+        assert_eq!(values(minimize(&a, g()).try_eval(&s).unwrap()), vec![1]);
+        assert_eq!(values(tracing.goal("g", g()).try_eval(&s).unwrap()), vec![2, 1, 2]);
+    }
+
+    #[test]
+    fn test_subsuming() {
+        let s = State::<Term>::empty();
+        let (a, s) = s.make_var();
+        let (x, s) = s.make_var();
+        let (y, s) = s.make_var();
+        let f = |arg| unify_val(&a, Term::compound("f", vec![arg]));
+        let general = || disj(f(Term::Var(x)), disj(f(Term::Var(y)), f(Term::Int(1))));
+        let specific = || disj(f(Term::Int(1)), f(Term::Var(x)));
+
+        assert_eq!(distinct(general()).on(&[a]).eval(&s).len(), 2);
+        assert_eq!(distinct(general()).on(&[a]).subsuming().eval(&s).len(), 1);
+        assert_eq!(distinct(specific()).on(&[a]).subsuming().eval(&s).len(), 2);
+
+        // with `x` compared too, `a = f(y)` is neither a repeat nor an instance of `a = f(x)`
+        let mut filter = AnswerFilter::new(&[a, x]).subsuming();
+        let answers = general().eval(&s);
+        assert_eq!(answers.iter().filter(|answer| filter.admit(answer)).count(), 2);
+    }
 }
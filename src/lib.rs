@@ -34,6 +34,25 @@
 //! both `v1` and `v2`.
 //!
 
-mod btmap;
+pub mod btmap;
+pub mod attr;
+pub mod chr;
+pub mod aggregate;
+pub mod datalog;
+pub mod error;
+pub mod facts;
 pub mod goal;
+pub mod parallel;
+pub mod radix;
+pub mod search;
+pub mod sorted;
 pub mod state;
+pub mod stats;
+pub mod store;
+pub mod syntax;
+pub mod term;
+
+#[cfg(test)]
+mod testing;
+
+pub use error::Error;
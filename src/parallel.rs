@@ -0,0 +1,345 @@
+//! # Parallel Evaluation
+//!
+//! Goals whose sub-goals are independent can be evaluated on several threads
+//! at once.  A `ParGoal` is a goal that can be shared between threads, and
+//! that can split itself into alternatives: a `Disjunction` splits into its
+//! two branches, and a `Conjunction` into its two sub-goals in sequence.  The
+//! alternatives are shared out among a pool of worker threads, each of which
+//! steals work from the others when it runs out.
+//!
+//! The answers are the same as those of `Goal::eval`, but their order
+//! depends on how the work happens to be shared out.
+//!
+//! ```
+//! use rslogic::state::State;
+//! use rslogic::goal::{conj, disj, unify_val};
+//! use rslogic::parallel;
+//!
+//! let s = State::<i32>::empty();
+//! let (a, s) = s.make_var();
+//! let (b, s) = s.make_var();
+//! let g = conj(disj(unify_val(&a, 1), unify_val(&a, 2)), disj(unify_val(&b, 3), unify_val(&b, 4)));
+//!
+//! let mut pairs: Vec<(i32, i32)> = parallel::eval(&g, &s, 4).iter()
+//!     .map(|s| (*s.get(&a).unwrap(), *s.get(&b).unwrap()))
+//!     .collect();
+//! pairs.sort();
+//! assert_eq!(pairs, vec![(1, 3), (1, 4), (2, 3), (2, 4)]);
+//! ```
+
+use error::Error;
+use goal::{Goal, Conjunction, Disjunction, Fail, UnifyVal, UnifyVar};
+use state::{Unif, State, PossibleStates};
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+/// A goal that can be evaluated in parallel.
+pub trait ParGoal<T>: Send + Sync where T: PartialEq + Unif<T> {
+    /// Splits the goal into the alternatives that result from taking one step
+    /// in proving it.  A goal that cannot be split returns its solutions, as
+    /// alternatives with no sub-goals.
+    fn split<'a>(&'a self, state: &State<T>) -> Vec<ParBranch<'a, T>>;
+}
+
+/// One alternative resulting from splitting a `ParGoal`: a state, and the
+/// sub-goals that must be proven in it, in order.
+pub struct ParBranch<'a, T> where T: PartialEq + Unif<T> {
+    pub state: State<T>,
+    pub goals: Vec<&'a (dyn ParGoal<T> + 'a)>,
+}
+
+impl<'a, T> ParBranch<'a, T> where T: PartialEq + Unif<T> {
+    /// Creates a branch with no sub-goals remaining.
+    pub fn done(state: State<T>) -> ParBranch<'a, T> {
+        ParBranch { state, goals: Vec::new() }
+    }
+
+    /// Creates a branch in which the sub-goals remain to be proven.
+    pub fn new(state: State<T>, goals: Vec<&'a (dyn ParGoal<T> + 'a)>) -> ParBranch<'a, T> {
+        ParBranch { state, goals }
+    }
+}
+
+/// Evaluates a goal that cannot be split.
+fn solutions<'a, T, G>(goal: &G, state: &State<T>) -> Vec<ParBranch<'a, T>> where T: PartialEq + Unif<T>, G: Goal<T> {
+    goal.eval(state).into_iter().map(ParBranch::done).collect()
+}
+
+impl<T> ParGoal<T> for Fail<T> where T: PartialEq + Unif<T> + Send + Sync {
+    fn split<'a>(&'a self, _: &State<T>) -> Vec<ParBranch<'a, T>> {
+        Vec::new()
+    }
+}
+
+impl<T> ParGoal<T> for UnifyVal<T> where T: Clone + Eq + Unif<T> + Send + Sync {
+    fn split<'a>(&'a self, state: &State<T>) -> Vec<ParBranch<'a, T>> {
+        solutions(self, state)
+    }
+}
+
+impl<T> ParGoal<T> for UnifyVar<T> where T: PartialEq + Unif<T> + Send + Sync {
+    fn split<'a>(&'a self, state: &State<T>) -> Vec<ParBranch<'a, T>> {
+        solutions(self, state)
+    }
+}
+
+impl<T, A, B> ParGoal<T> for Conjunction<T, A, B> where T: PartialEq + Unif<T> + Send + Sync, A: Goal<T> + ParGoal<T>, B: Goal<T> + ParGoal<T> {
+    fn split<'a>(&'a self, state: &State<T>) -> Vec<ParBranch<'a, T>> {
+        vec![ParBranch::new(state.clone(), vec![&self.a, &self.b])]
+    }
+}
+
+impl<T, A, B> ParGoal<T> for Disjunction<T, A, B> where T: PartialEq + Unif<T> + Send + Sync, A: Goal<T> + ParGoal<T>, B: Goal<T> + ParGoal<T> {
+    fn split<'a>(&'a self, state: &State<T>) -> Vec<ParBranch<'a, T>> {
+        if let Some(stats) = state.stats() {
+            stats.record_branches(2);
+        }
+        vec![ParBranch::new(state.clone(), vec![&self.a]), ParBranch::new(state.clone(), vec![&self.b])]
+    }
+}
+
+/// A `Leaf` goal makes any goal that can be shared between threads into a
+/// `ParGoal`, which is evaluated on one thread without being split.
+pub struct Leaf<G> {
+    goal: G,
+}
+
+impl<T, G> Goal<T> for Leaf<G> where T: PartialEq + Unif<T>, G: Goal<T> {
+    fn eval(&self, state: &State<T>) -> PossibleStates<T> {
+        self.goal.eval(state)
+    }
+
+    fn try_eval(&self, state: &State<T>) -> Result<PossibleStates<T>, Error> {
+        self.goal.try_eval(state)
+    }
+}
+
+impl<T, G> ParGoal<T> for Leaf<G> where T: PartialEq + Unif<T>, G: Goal<T> + Send + Sync {
+    fn split<'a>(&'a self, state: &State<T>) -> Vec<ParBranch<'a, T>> {
+        solutions(&self.goal, state)
+    }
+}
+
+impl<G> fmt::Display for Leaf<G> where G: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.goal, f)
+    }
+}
+
+impl<G> fmt::Debug for Leaf<G> where G: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.goal, f)
+    }
+}
+
+/// Creates a `Leaf` goal, which evaluates the goal without splitting it.
+pub fn leaf<T, G>(goal: G) -> Leaf<G> where T: PartialEq + Unif<T>, G: Goal<T> + Send + Sync {
+    Leaf { goal }
+}
+
+
+/// A unit of work for the pool: a state, and the goals that remain to be proven in it.
+type Task<'a, T> = ParBranch<'a, T>;
+
+/// The work shared by a pool of threads.  Each thread takes work from the back
+/// of its own queue, and steals from the front of the others' queues.  Threads
+/// that find no work wait to be woken when there is more, or when all the work
+/// is done or has been abandoned because working on a task panicked.
+struct Pool<'a, T> where T: PartialEq + Unif<T> {
+    queues: Vec<Mutex<VecDeque<Task<'a, T>>>>,
+    pending: AtomicUsize,
+    aborted: AtomicBool,
+    idle: Mutex<()>,
+    wake: Condvar,
+}
+
+impl<'a, T> Pool<'a, T> where T: PartialEq + Unif<T> + Send + Sync {
+    fn new(threads: usize) -> Pool<'a, T> {
+        Pool {
+            queues: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+            pending: AtomicUsize::new(0),
+            aborted: AtomicBool::new(false),
+            idle: Mutex::new(()),
+            wake: Condvar::new(),
+        }
+    }
+
+    fn push(&self, worker: usize, task: Task<'a, T>) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.queues[worker].lock().unwrap().push_back(task);
+        // taking the lock means a thread can't miss the wakeup between finding
+        // no work and starting to wait
+        let _idle = self.idle.lock().unwrap();
+        self.wake.notify_one();
+    }
+
+    fn take(&self, worker: usize) -> Option<Task<'a, T>> {
+        if let Some(task) = self.queues[worker].lock().unwrap().pop_back() {
+            return Some(task);
+        }
+        let count = self.queues.len();
+        (1..count).filter_map(|i| self.queues[(worker + i) % count].lock().unwrap().pop_front()).next()
+    }
+
+    /// Takes a task, waiting for one while other threads are still working.
+    /// Returns `None` once all the work is done or has been abandoned.
+    fn next(&self, worker: usize) -> Option<Task<'a, T>> {
+        if self.aborted.load(Ordering::SeqCst) {
+            return None;
+        }
+        if let Some(task) = self.take(worker) {
+            return Some(task);
+        }
+        let mut idle = self.idle.lock().unwrap();
+        loop {
+            if self.aborted.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(task) = self.take(worker) {
+                return Some(task);
+            }
+            if self.pending.load(Ordering::SeqCst) == 0 {
+                return None;
+            }
+            idle = self.wake.wait(idle).unwrap();
+        }
+    }
+
+    /// Works on tasks until there are none left anywhere, and returns the answers found.
+    fn work(&self, worker: usize) -> PossibleStates<T> {
+        let mut answers = PossibleStates::new();
+        while let Some(task) = self.next(worker) {
+            // only once its children are counted is this task finished
+            let _finished = Finished { pool: self };
+            let mut goals = task.goals.into_iter();
+            match goals.next() {
+                Some(goal) => {
+                    let rest: Vec<&'a (dyn ParGoal<T> + 'a)> = goals.collect();
+                    for branch in goal.split(&task.state) {
+                        let mut remaining = branch.goals;
+                        remaining.extend(rest.iter().cloned());
+                        self.push(worker, Task { state: branch.state, goals: remaining });
+                    }
+                },
+                None => answers.push(task.state),
+            }
+        }
+        answers
+    }
+}
+
+/// Counts a task as finished when dropped, even if working on it panicked, in
+/// which case the rest of the pool's work is abandoned.
+struct Finished<'p, 'a, T> where T: PartialEq + Unif<T> + 'p, 'a: 'p {
+    pool: &'p Pool<'a, T>,
+}
+
+impl<'p, 'a, T> Drop for Finished<'p, 'a, T> where T: PartialEq + Unif<T> + 'p, 'a: 'p {
+    fn drop(&mut self) {
+        let panicking = thread::panicking();
+        if panicking {
+            self.pool.aborted.store(true, Ordering::SeqCst);
+        }
+        if self.pool.pending.fetch_sub(1, Ordering::SeqCst) == 1 || panicking {
+            let _idle = self.pool.idle.lock().unwrap();
+            self.pool.wake.notify_all();
+        }
+    }
+}
+
+/// Evaluates the goal on a pool of `threads` threads, returning all its
+/// solutions, in no particular order.
+///
+/// # Panics
+///
+/// If evaluating any of the goal's sub-goals panics, the other threads stop,
+/// and the panic is resumed on the calling thread.
+pub fn eval<T, G>(goal: &G, state: &State<T>, threads: usize) -> PossibleStates<T>
+    where T: PartialEq + Unif<T> + Send + Sync, G: ParGoal<T> {
+    let threads = threads.max(1);
+    let pool = Pool::new(threads);
+    pool.push(0, Task::new(state.clone(), vec![goal]));
+
+    thread::scope(|scope| {
+        let pool = &pool;
+        let workers: Vec<_> = (0..threads).map(|worker| scope.spawn(move || pool.work(worker))).collect();
+        let results: Vec<_> = workers.into_iter().map(|worker| worker.join()).collect();
+        let mut answers = PossibleStates::new();
+        for result in results {
+            match result {
+                Ok(states) => answers.extend(states),
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        }
+        answers
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use goal::{Goal, conj, disj, fail, unify_val, unify_vars};
+    use state::State;
+    use stats::Stats;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Arc;
+    use super::{eval, leaf};
+
+    fn assert_send_sync<S: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<State<i32>>();
+        assert_send_sync::<Stats>();
+    }
+
+    #[test]
+    fn test_same_answers() {
+        let stats = Arc::new(Stats::new());
+        let s = State::<i32>::empty().with_stats(stats.clone());
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let (c, s) = s.make_var();
+        let digit = |v| disj(disj(unify_val(&v, 1), unify_val(&v, 2)), disj(unify_val(&v, 3), disj(unify_val(&v, 4), fail())));
+        let g = conj(conj(digit(a), digit(b)), conj(leaf(unify_vars(&b, &c)), digit(c)));
+
+        let answers = |states: Vec<State<i32>>| {
+            let mut answers: Vec<(i32, i32, i32)> = states.iter()
+                .map(|s| (*s.get(&a).unwrap(), *s.get(&b).unwrap(), *s.get(&c).unwrap()))
+                .collect();
+            answers.sort();
+            answers
+        };
+        let expected = answers(g.eval(&s));
+        assert_eq!(expected.len(), 16);
+        for threads in 1..5 {
+            assert_eq!(answers(eval(&g, &s, threads)), expected);
+        }
+        assert!(stats.branches() > 0);
+    }
+
+    #[test]
+    fn test_try_eval() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (foreign, _) = State::<i32>::empty().make_var();
+        assert_eq!(leaf(unify_val(&foreign, 1)).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(leaf(unify_val(&a, 1)).try_eval(&s).map(|r| r.len()), Ok(1));
+    }
+
+    #[test]
+    fn test_panic() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (foreign, _) = State::<i32>::empty().make_var();
+        let g = disj(disj(unify_val(&a, 1), unify_val(&foreign, 2)), disj(unify_val(&a, 3), unify_val(&a, 4)));
+        for threads in 1..4 {
+            assert!(panic::catch_unwind(AssertUnwindSafe(|| eval(&g, &s, threads))).is_err());
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! # Radix Map
+//!
+//! An immutable map from dense `usize` keys, such as the indices of
+//! variables, implemented with a radix trie: each level of the trie is indexed
+//! by five bits of the key.  Looking up or changing a key visits one node per
+//! level, and there are only as many levels as the largest key needs, so a
+//! map with a million keys is four levels deep whatever order they were added
+//! in.  Changing a map returns a new map that shares all the untouched parts
+//! of the trie with the original.
+//!
+//! ```
+//! use rslogic::radix::RadixMap;
+//!
+//! let m: RadixMap<&str> = RadixMap::empty().replace(1, "a").replace(40, "b");
+//! let n = m.remove(&1);
+//! assert_eq!(m.get(&40), Some(&"b"));
+//! assert_eq!(n.iter().collect::<Vec<_>>(), vec![(40, &"b")]);
+//! ```
+
+use btmap::DuplicateKey;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Index;
+use std::sync::Arc;
+
+/// The number of bits of the key that index each level of the trie.
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+type Link<V> = Option<Arc<Node<V>>>;
+
+enum Node<V> {
+    Branch(Vec<Link<V>>),
+    Leaf(Vec<Option<Arc<V>>>),
+}
+
+impl<V> Node<V> {
+    fn is_empty(&self) -> bool {
+        match self {
+            Node::Branch(children) => children.iter().all(Option::is_none),
+            Node::Leaf(items) => items.iter().all(Option::is_none),
+        }
+    }
+
+    /// Copies the path from `node`, which is at the level that `shift` bits
+    /// of the key index, down to the key, putting the item there.  Returns
+    /// the new node, which is `None` if it would be empty, and `true` if there
+    /// was an item there before.
+    fn set(node: &Link<V>, shift: u32, key: usize, item: Option<Arc<V>>) -> (Link<V>, bool) {
+        let slot = (key >> shift) & MASK;
+        let (node, present) = match (node.as_deref(), shift) {
+            (Some(Node::Leaf(items)), _) => {
+                let mut items = items.clone();
+                let present = items[slot].is_some();
+                items[slot] = item;
+                (Node::Leaf(items), present)
+            },
+            (Some(Node::Branch(children)), _) => {
+                let mut children = children.clone();
+                let (child, present) = Node::set(&children[slot], shift - BITS, key, item);
+                children[slot] = child;
+                (Node::Branch(children), present)
+            },
+            (None, _) if item.is_none() => return (None, false),
+            (None, 0) => {
+                let mut items = vec![None; WIDTH];
+                items[slot] = item;
+                (Node::Leaf(items), false)
+            },
+            (None, _) => {
+                let mut children = vec![None; WIDTH];
+                children[slot] = Node::set(&None, shift - BITS, key, item).0;
+                (Node::Branch(children), false)
+            },
+        };
+        if node.is_empty() { (None, present) } else { (Some(Arc::new(node)), present) }
+    }
+}
+
+/// An immutable map from `usize` keys, implemented with a radix trie.
+pub struct RadixMap<V> {
+    size: usize,
+    shift: u32, // the bits of the key below those that index the root
+    root: Link<V>,
+}
+
+impl<V> RadixMap<V> {
+    /// Creates an empty map.
+    pub fn empty() -> RadixMap<V> {
+        RadixMap { size: 0, shift: 0, root: None }
+    }
+
+    /// Returns the number of items in the map.
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Returns `true` if the map has no items.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns `true` if the trie is deep enough to hold the key.
+    fn holds(&self, key: usize) -> bool {
+        key.checked_shr(self.shift + BITS).unwrap_or(0) == 0
+    }
+
+    /// Returns a reference to the item in the map corresponding to the key,
+    /// or `None` if there is no item corresponding to the key.
+    pub fn get(&self, key: &usize) -> Option<&V> {
+        let key = *key;
+        if !self.holds(key) {
+            return None;
+        }
+        let mut node = self.root.as_ref()?;
+        let mut shift = self.shift;
+        loop {
+            match **node {
+                Node::Branch(ref children) => {
+                    node = children[(key >> shift) & MASK].as_ref()?;
+                    shift -= BITS;
+                },
+                Node::Leaf(ref items) => return items[key & MASK].as_deref(),
+            }
+        }
+    }
+
+    /// Returns `true` if the map contains an item corresponding to the key.
+    pub fn contains_key(&self, key: &usize) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns a new map containing all the items in the original map,
+    /// as well as the new item.  Returns an error if there is already
+    /// an item corresponding to the key.
+    pub fn insert(&self, key: usize, item: V) -> Result<RadixMap<V>, DuplicateKey> {
+        if self.contains_key(&key) {
+            return Err(DuplicateKey);
+        }
+        Ok(self.replace(key, item))
+    }
+
+    /// Returns a new map containing all the items in the original map, with
+    /// the item corresponding to the key replaced by the new item, or added
+    /// if there was none.
+    pub fn replace(&self, key: usize, item: V) -> RadixMap<V> {
+        let mut shift = self.shift;
+        let mut root = self.root.clone();
+        while key.checked_shr(shift + BITS).unwrap_or(0) != 0 {
+            // the trie grows a level, with the old root as its first child
+            if root.is_some() {
+                let mut children = vec![None; WIDTH];
+                children[0] = root;
+                root = Some(Arc::new(Node::Branch(children)));
+            }
+            shift += BITS;
+        }
+        let (root, present) = Node::set(&root, shift, key, Some(Arc::new(item)));
+        RadixMap { size: if present { self.size } else { self.size + 1 }, shift, root }
+    }
+
+    /// Returns a new map containing all the items in the original map except
+    /// the one corresponding to the key, if there was one.
+    pub fn remove(&self, key: &usize) -> RadixMap<V> {
+        if !self.contains_key(key) {
+            return self.clone();
+        }
+        let (root, _) = Node::set(&self.root, self.shift, *key, None);
+        RadixMap { size: self.size - 1, shift: self.shift, root }
+    }
+
+    /// Returns an iterator over the keys and items in the map, in order of
+    /// their keys.
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter { stack: self.root.iter().map(|root| (&**root, 0, self.shift, 0)).collect() }
+    }
+
+    /// Returns `true` if the maps share the same trie, so that they are equal
+    /// without comparing their items.
+    pub fn ptr_eq(&self, other: &RadixMap<V>) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// An iterator over the keys and items of a `RadixMap`, in order of their keys.
+pub struct Iter<'a, V> where V: 'a {
+    stack: Vec<(&'a Node<V>, usize, u32, usize)>, // node, its least key, shift, next slot
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (usize, &'a V);
+
+    fn next(&mut self) -> Option<(usize, &'a V)> {
+        loop {
+            let (node, base, shift, slot) = {
+                let top = self.stack.last_mut()?;
+                if top.3 == WIDTH {
+                    self.stack.pop();
+                    continue;
+                }
+                top.3 += 1;
+                (top.0, top.1, top.2, top.3 - 1)
+            };
+            let key = base + (slot << shift);
+            match node {
+                Node::Leaf(items) => if let Some(ref item) = items[slot] {
+                    return Some((key, &**item));
+                },
+                Node::Branch(children) => if let Some(ref child) = children[slot] {
+                    self.stack.push((&**child, key, shift - BITS, 0));
+                },
+            }
+        }
+    }
+}
+
+impl<'a, V> IntoIterator for &'a RadixMap<V> {
+    type Item = (usize, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+/// Collects the items into a map.  Where several items have the same key,
+/// the last is kept.
+impl<V> FromIterator<(usize, V)> for RadixMap<V> {
+    fn from_iter<I>(iter: I) -> RadixMap<V> where I: IntoIterator<Item = (usize, V)> {
+        iter.into_iter().fold(RadixMap::empty(), |map, (key, item)| map.replace(key, item))
+    }
+}
+
+impl<V> Clone for RadixMap<V> {
+    fn clone(&self) -> RadixMap<V> {
+        RadixMap { size: self.size, shift: self.shift, root: self.root.clone() }
+    }
+}
+
+impl<V> Default for RadixMap<V> {
+    fn default() -> RadixMap<V> {
+        RadixMap::empty()
+    }
+}
+
+impl<V> PartialEq for RadixMap<V> where V: PartialEq {
+    fn eq(&self, other: &RadixMap<V>) -> bool {
+        self.ptr_eq(other) || (self.len() == other.len() && self.iter().eq(other.iter()))
+    }
+}
+
+impl<V> Eq for RadixMap<V> where V: Eq {}
+
+impl<V> fmt::Debug for RadixMap<V> where V: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<V> Index<&usize> for RadixMap<V> {
+    type Output = V;
+    fn index(&self, key: &usize) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use btmap::DuplicateKey;
+    use super::RadixMap;
+
+    #[test]
+    fn test_get_replace() {
+        let m: RadixMap<usize> = RadixMap::empty();
+        assert!(m.is_empty() && m.get(&0).is_none() && m.get(&usize::MAX).is_none());
+
+        let keys = [5, 1 << 20, 33, 0, 31, 32, 1025, usize::MAX];
+        let m = keys.iter().fold(m, |m, &k| m.replace(k, k / 2));
+        assert_eq!(m.len(), keys.len());
+        for &k in keys.iter() {
+            assert_eq!(m.get(&k), Some(&(k / 2)));
+        }
+        assert!(m.get(&6).is_none() && m.get(&(1 << 21)).is_none());
+        assert_eq!(m.replace(33, 0)[&33], 0);
+        assert_eq!(m.replace(33, 0).len(), keys.len());
+        assert_eq!(m.insert(31, 0).err(), Some(DuplicateKey));
+        assert_eq!(m.insert(30, 0).map(|m| m.len()), Ok(keys.len() + 1));
+
+        let mut sorted = keys.to_vec();
+        sorted.sort();
+        assert_eq!(m.iter().map(|(k, _)| k).collect::<Vec<_>>(), sorted);
+    }
+
+    #[test]
+    fn test_remove() {
+        let m: RadixMap<usize> = (0..2000).map(|k| (k, k)).collect();
+        let r = (0..2000).filter(|k| k % 3 != 0).fold(m.clone(), |r, k| r.remove(&k));
+        assert_eq!(r.len(), 667);
+        assert_eq!(r.iter().map(|(k, _)| k).collect::<Vec<_>>(), (0..2000).filter(|k| k % 3 == 0).collect::<Vec<_>>());
+        assert_eq!(m.len(), 2000);
+        assert_eq!(m.remove(&5000).len(), 2000);
+
+        let e = (0..2000).fold(m.clone(), |r, k| r.remove(&k));
+        assert!(e.is_empty() && e.iter().next().is_none());
+        assert_eq!(e, RadixMap::empty());
+        assert!(m != r);
+    }
+}
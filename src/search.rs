@@ -0,0 +1,896 @@
+//! # Incremental Search
+//!
+//! `Goal::eval` computes every solution of a goal before returning, which
+//! never finishes for goals with infinitely many solutions.  A `Search`
+//! instead expands goals one step at a time, depth-first, and produces the
+//! solutions lazily as an iterator.
+//!
+//! ```
+//! use rslogic::state::State;
+//! use rslogic::goal::{conj, disj, unify_val};
+//! use rslogic::search::Search;
+//!
+//! let s = State::<i32>::empty();
+//! let (a, s) = s.make_var();
+//! let (b, s) = s.make_var();
+//! let g = conj(disj(unify_val(&a, 1), unify_val(&a, 2)), unify_val(&b, 3));
+//!
+//! let mut answers = Search::new(&g, &s);
+//! assert_eq!(answers.next().unwrap().get(&a), Some(&1));
+//! assert_eq!(answers.next().unwrap().get(&a), Some(&2));
+//! assert!(answers.next().is_none());
+//! ```
+//!
+//! A search can also be run within `SearchLimits`, so that a goal that runs
+//! away can be stopped, and later resumed or abandoned.
+//!
+//! ```
+//! use rslogic::state::State;
+//! use rslogic::goal::{disj, unify_val};
+//! use rslogic::search::{Search, SearchLimits, Outcome, Limit};
+//!
+//! let s = State::<i32>::empty();
+//! let (a, s) = s.make_var();
+//! let g = disj(unify_val(&a, 1), disj(unify_val(&a, 2), unify_val(&a, 3)));
+//!
+//! let mut search = Search::new(&g, &s);
+//! let first = search.run(&SearchLimits::new().answers(2));
+//! assert_eq!(first.answers.len(), 2);
+//! assert_eq!(first.outcome, Outcome::LimitHit(Limit::Answers));
+//!
+//! let rest = search.run(&SearchLimits::new());
+//! assert_eq!(rest.answers.len(), 1);
+//! assert_eq!(rest.outcome, Outcome::Exhausted);
+//! ```
+//!
+//! The order in which a search explores the branches of goals is decided by a
+//! `SearchStrategy`.  Depth-first search, in the order of Prolog, is the
+//! default; `Interleaving`, `BreadthFirst` and `IterativeDeepening` find the
+//! same answers in other orders, and also find the answers of goals whose
+//! first branches never end.
+//!
+//! `BestFirst` search explores the cheapest paths first, where the cost of a
+//! path is the total of the costs of the branches on it (see `goal::cost`).
+//! Its answers come in order of nondecreasing cost.
+//!
+//! ```
+//! use rslogic::state::State;
+//! use rslogic::goal::{cost, disj, unify_val};
+//! use rslogic::search::{Search, BestFirst};
+//!
+//! let s = State::<String>::empty();
+//! let (route, s) = s.make_var();
+//! let via = |name: &str| unify_val(&route, name.to_string());
+//! let g = disj(cost(7, via("motorway")), disj(cost(3, via("footpath")), cost(5, via("lane"))));
+//!
+//! let routes: Vec<String> = Search::with_strategy(&g, &s, Box::new(BestFirst::new()))
+//!     .map(|s| s.get(&route).unwrap().clone())
+//!     .collect();
+//! assert_eq!(routes, vec!["footpath", "lane", "motorway"]);
+//! ```
+
+use goal::{Goal, Subgoal};
+use state::{Unif, State, Var};
+use stats::Stats;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// A persistent stack of the goals that remain to be proven on a path of the search.
+struct Cont<'a, T> where T: PartialEq + Unif<T> {
+    goal: Subgoal<'a, T>,
+    next: Option<Rc<Cont<'a, T>>>,
+}
+
+/// A point in the search: a state, and the goals that remain to be proven in it.
+pub struct Node<'a, T> where T: PartialEq + Unif<T> {
+    state: State<T>,
+    goals: Option<Rc<Cont<'a, T>>>,
+    depth: usize,
+    cost: u64,
+}
+
+impl<'a, T> Node<'a, T> where T: PartialEq + Unif<T> {
+    /// Returns the node's state.
+    pub fn state(&self) -> &State<T> {
+        &self.state
+    }
+
+    /// Returns the total cost of the branches on the path from the start of the search to the node.
+    pub fn cost(&self) -> u64 {
+        self.cost
+    }
+
+    /// Returns the number of goal expansions on the path from the start of the search to the node.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns `true` if no goals remain to be proven, so that the node's state is an answer.
+    pub fn is_answer(&self) -> bool {
+        self.goals.is_none()
+    }
+}
+
+impl<'a, T> Clone for Node<'a, T> where T: PartialEq + Unif<T> {
+    fn clone(&self) -> Node<'a, T> {
+        Node { state: self.state.clone(), goals: self.goals.clone(), depth: self.depth, cost: self.cost }
+    }
+}
+
+
+/// Decides the order in which a `Search` explores nodes, by keeping the
+/// frontier of nodes that it has yet to explore.
+pub trait SearchStrategy<'a, T> where T: PartialEq + Unif<T> {
+    /// Adds the node at which the search starts.
+    fn start(&mut self, root: Node<'a, T>) {
+        self.push(vec![root]);
+    }
+
+    /// Adds the nodes that result from expanding the node last taken, in the
+    /// order of the branches they come from.
+    fn push(&mut self, children: Vec<Node<'a, T>>);
+
+    /// Takes the next node to explore, or returns `None` if there are none left.
+    fn pop(&mut self) -> Option<Node<'a, T>>;
+
+    /// Puts back the node last taken, so that it is the next to be taken.
+    fn restore(&mut self, node: Node<'a, T>);
+}
+
+/// Explores the first branch of each goal completely before the next, as
+/// Prolog does.  This uses the least memory, but never leaves a branch that
+/// does not end.
+pub struct DepthFirst<'a, T> where T: PartialEq + Unif<T> {
+    stack: Vec<Node<'a, T>>,
+}
+
+impl<'a, T> DepthFirst<'a, T> where T: PartialEq + Unif<T> {
+    pub fn new() -> DepthFirst<'a, T> {
+        DepthFirst { stack: Vec::new() }
+    }
+}
+
+impl<'a, T> Default for DepthFirst<'a, T> where T: PartialEq + Unif<T> {
+    fn default() -> DepthFirst<'a, T> {
+        DepthFirst::new()
+    }
+}
+
+impl<'a, T> SearchStrategy<'a, T> for DepthFirst<'a, T> where T: PartialEq + Unif<T> {
+    fn push(&mut self, children: Vec<Node<'a, T>>) {
+        // push the children in reverse, so that the first one is explored first
+        self.stack.extend(children.into_iter().rev());
+    }
+
+    fn pop(&mut self) -> Option<Node<'a, T>> {
+        self.stack.pop()
+    }
+
+    fn restore(&mut self, node: Node<'a, T>) {
+        self.stack.push(node);
+    }
+}
+
+/// Alternates between the branches of each goal, in the manner of µKanren's
+/// interleaving streams: after taking a step in one branch, the next step is
+/// taken in its sibling.  Every answer is eventually found, even if some
+/// branches never end.
+pub struct Interleaving<'a, T> where T: PartialEq + Unif<T> {
+    stream: Option<Stream<'a, T>>,
+    taken: bool,
+}
+
+/// The nodes of an `Interleaving` search, arranged as a tree of the choices
+/// that led to them.  The node last taken leaves a hole at the left of the tree.
+enum Stream<'a, T> where T: PartialEq + Unif<T> {
+    Hole,
+    Leaf(Node<'a, T>),
+    Fork(Box<Stream<'a, T>>, Box<Stream<'a, T>>),
+}
+
+impl<'a, T> Stream<'a, T> where T: PartialEq + Unif<T> {
+    /// Builds a stream of the nodes, which alternates between them in order.
+    fn of(nodes: Vec<Node<'a, T>>) -> Option<Stream<'a, T>> {
+        nodes.into_iter().rev().fold(None, |rest, node| match rest {
+            Some(rest) => Some(Stream::Fork(Box::new(Stream::Leaf(node)), Box::new(rest))),
+            None => Some(Stream::Leaf(node)),
+        })
+    }
+
+    /// Takes the leftmost node, leaving a hole in its place.
+    fn take(self) -> (Node<'a, T>, Stream<'a, T>) {
+        match self {
+            Stream::Leaf(node) => (node, Stream::Hole),
+            Stream::Fork(a, b) => {
+                let (node, a) = a.take();
+                (node, Stream::Fork(Box::new(a), b))
+            },
+            Stream::Hole => unreachable!("a hole is filled before the next node is taken"),
+        }
+    }
+
+    /// Fills the hole, then swaps each fork on the way to it if `turn` is set,
+    /// so that its other branch takes the next step.
+    fn fill(self, with: Option<Stream<'a, T>>, turn: bool) -> Option<Stream<'a, T>> {
+        match self {
+            Stream::Hole => with,
+            Stream::Fork(a, b) => match a.fill(with, turn) {
+                Some(a) if turn => Some(Stream::Fork(b, Box::new(a))),
+                Some(a) => Some(Stream::Fork(Box::new(a), b)),
+                None => Some(*b),
+            },
+            leaf => Some(leaf),
+        }
+    }
+}
+
+impl<'a, T> Interleaving<'a, T> where T: PartialEq + Unif<T> {
+    pub fn new() -> Interleaving<'a, T> {
+        Interleaving { stream: None, taken: false }
+    }
+
+    fn fill(&mut self, with: Option<Stream<'a, T>>, turn: bool) {
+        if self.taken {
+            self.taken = false;
+            self.stream = self.stream.take().and_then(|stream| stream.fill(with, turn));
+        } else if let Some(with) = with {
+            self.stream = Some(match self.stream.take() {
+                Some(stream) => Stream::Fork(Box::new(with), Box::new(stream)),
+                None => with,
+            });
+        }
+    }
+}
+
+impl<'a, T> Default for Interleaving<'a, T> where T: PartialEq + Unif<T> {
+    fn default() -> Interleaving<'a, T> {
+        Interleaving::new()
+    }
+}
+
+impl<'a, T> SearchStrategy<'a, T> for Interleaving<'a, T> where T: PartialEq + Unif<T> {
+    fn push(&mut self, children: Vec<Node<'a, T>>) {
+        self.fill(Stream::of(children), true);
+    }
+
+    fn pop(&mut self) -> Option<Node<'a, T>> {
+        // a node taken without being expanded (an answer, or one set aside) leaves nothing behind
+        self.fill(None, true);
+        let (node, rest) = self.stream.take()?.take();
+        self.stream = Some(rest);
+        self.taken = true;
+        Some(node)
+    }
+
+    fn restore(&mut self, node: Node<'a, T>) {
+        self.fill(Some(Stream::Leaf(node)), false);
+    }
+}
+
+/// Explores all the nodes at one depth before any at the next, so that the
+/// answers nearest the start are found first.  This can use a great deal of memory.
+pub struct BreadthFirst<'a, T> where T: PartialEq + Unif<T> {
+    queue: VecDeque<Node<'a, T>>,
+}
+
+impl<'a, T> BreadthFirst<'a, T> where T: PartialEq + Unif<T> {
+    pub fn new() -> BreadthFirst<'a, T> {
+        BreadthFirst { queue: VecDeque::new() }
+    }
+}
+
+impl<'a, T> Default for BreadthFirst<'a, T> where T: PartialEq + Unif<T> {
+    fn default() -> BreadthFirst<'a, T> {
+        BreadthFirst::new()
+    }
+}
+
+impl<'a, T> SearchStrategy<'a, T> for BreadthFirst<'a, T> where T: PartialEq + Unif<T> {
+    fn push(&mut self, children: Vec<Node<'a, T>>) {
+        self.queue.extend(children);
+    }
+
+    fn pop(&mut self) -> Option<Node<'a, T>> {
+        self.queue.pop_front()
+    }
+
+    fn restore(&mut self, node: Node<'a, T>) {
+        self.queue.push_front(node);
+    }
+}
+
+/// Searches depth-first to a depth limit, then starts again from the
+/// beginning with a deeper limit, for as long as the limit cut off any paths.
+/// Each answer is reported in the first round deep enough to reach it, so the
+/// answers come in the same order as breadth-first search would find them,
+/// grouped by round, but using only as much memory as depth-first search.
+pub struct IterativeDeepening<'a, T> where T: PartialEq + Unif<T> {
+    root: Option<Node<'a, T>>,
+    stack: Vec<Node<'a, T>>,
+    step: usize,
+    limit: usize,
+    reported: usize,
+    cut_off: bool,
+}
+
+impl<'a, T> IterativeDeepening<'a, T> where T: PartialEq + Unif<T> {
+    /// Creates a strategy whose depth limit starts at `step` and grows by `step` each round.
+    pub fn new(step: usize) -> IterativeDeepening<'a, T> {
+        let step = step.max(1);
+        IterativeDeepening { root: None, stack: Vec::new(), step, limit: step, reported: 0, cut_off: false }
+    }
+}
+
+impl<'a, T> SearchStrategy<'a, T> for IterativeDeepening<'a, T> where T: PartialEq + Unif<T> {
+    fn start(&mut self, root: Node<'a, T>) {
+        self.root = Some(root.clone());
+        self.stack.push(root);
+    }
+
+    fn push(&mut self, children: Vec<Node<'a, T>>) {
+        self.stack.extend(children.into_iter().rev());
+    }
+
+    fn pop(&mut self) -> Option<Node<'a, T>> {
+        loop {
+            let node = match self.stack.pop() {
+                Some(node) => node,
+                None => {
+                    // start the next round, if the last one missed anything
+                    if !self.cut_off {
+                        return None;
+                    }
+                    self.cut_off = false;
+                    self.reported = self.limit;
+                    self.limit += self.step;
+                    self.stack.extend(self.root.clone());
+                    continue;
+                },
+            };
+            if node.depth > self.limit {
+                self.cut_off = true;
+            } else if !node.is_answer() || node.depth > self.reported {
+                return Some(node);
+            }
+        }
+    }
+
+    fn restore(&mut self, node: Node<'a, T>) {
+        self.stack.push(node);
+    }
+}
+
+/// Bounds on the resources that a call to `Search::run` may use.  All the
+/// limits are optional; a search with no limits runs until it has found every
+/// answer.
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    steps: Option<u64>,
+    depth: Option<usize>,
+    answers: Option<usize>,
+    deadline: Option<Instant>,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl SearchLimits {
+    /// Creates a set of limits that do not limit anything.
+    pub fn new() -> SearchLimits {
+        SearchLimits::default()
+    }
+
+    /// Limits the number of goal expansions.
+    pub fn steps(self, steps: u64) -> SearchLimits {
+        SearchLimits { steps: Some(steps), .. self }
+    }
+
+    /// Limits the number of goal expansions on any one path of the search.
+    /// Paths that would go deeper are set aside, and are resumed by a later
+    /// run with a greater depth limit, or none.
+    pub fn depth(self, depth: usize) -> SearchLimits {
+        SearchLimits { depth: Some(depth), .. self }
+    }
+
+    /// Limits the number of answers.
+    pub fn answers(self, answers: usize) -> SearchLimits {
+        SearchLimits { answers: Some(answers), .. self }
+    }
+
+    /// Stops the search once the deadline has passed.
+    pub fn deadline(self, deadline: Instant) -> SearchLimits {
+        SearchLimits { deadline: Some(deadline), .. self }
+    }
+
+    /// Stops the search once the flag is set, which may be done from another thread.
+    pub fn cancel_flag(self, cancel: Arc<AtomicBool>) -> SearchLimits {
+        SearchLimits { cancel: Some(cancel), .. self }
+    }
+}
+
+/// The limit that stopped a search.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Depth,
+    Answers,
+    Deadline,
+}
+
+/// How a call to `Search::run` ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every answer has been found.
+    Exhausted,
+    /// The search stopped at a limit.  Running it again continues from where it stopped.
+    LimitHit(Limit),
+    /// The search was cancelled.  Running it again continues from where it stopped.
+    Cancelled,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Exhausted => write!(f, "no more answers"),
+            Outcome::LimitHit(Limit::Steps) => write!(f, "step limit reached"),
+            Outcome::LimitHit(Limit::Depth) => write!(f, "depth limit reached"),
+            Outcome::LimitHit(Limit::Answers) => write!(f, "answer limit reached"),
+            Outcome::LimitHit(Limit::Deadline) => write!(f, "deadline passed"),
+            Outcome::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// The answers found by a call to `Search::run`, and how it ended.
+pub struct SearchResult<T> where T: PartialEq + Unif<T> {
+    pub answers: Vec<State<T>>,
+    pub outcome: Outcome,
+}
+
+/// Estimates the cost that remains to reach an answer from a state.
+type Heuristic<T> = Box<dyn Fn(&State<T>) -> u64>;
+
+/// Explores the node with the least estimated total cost first: the cost of
+/// the path to the node, plus the heuristic's estimate of the cost that
+/// remains, in the manner of A* search.  Nodes of equal cost are explored
+/// depth-first.
+///
+/// Answers are found in order of nondecreasing cost as long as the heuristic
+/// never overestimates the remaining cost.  Without a heuristic, the
+/// estimate is zero, which is always safe.
+pub struct BestFirst<'a, T> where T: PartialEq + Unif<T> {
+    queue: BinaryHeap<Ranked<'a, T>>,
+    heuristic: Option<Heuristic<T>>,
+    count: u64,
+}
+
+/// A node in the queue of a `BestFirst` search, ordered so that the cheapest,
+/// and among those the most recently added, comes first.
+struct Ranked<'a, T> where T: PartialEq + Unif<T> {
+    estimate: u64,
+    order: u64,
+    node: Node<'a, T>,
+}
+
+impl<'a, T> PartialEq for Ranked<'a, T> where T: PartialEq + Unif<T> {
+    fn eq(&self, other: &Ranked<'a, T>) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl<'a, T> Eq for Ranked<'a, T> where T: PartialEq + Unif<T> {}
+
+impl<'a, T> PartialOrd for Ranked<'a, T> where T: PartialEq + Unif<T> {
+    fn partial_cmp(&self, other: &Ranked<'a, T>) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, T> Ord for Ranked<'a, T> where T: PartialEq + Unif<T> {
+    fn cmp(&self, other: &Ranked<'a, T>) -> CmpOrdering {
+        // `BinaryHeap` is a max-heap
+        other.estimate.cmp(&self.estimate).then(self.order.cmp(&other.order))
+    }
+}
+
+impl<'a, T> BestFirst<'a, T> where T: PartialEq + Unif<T> {
+    pub fn new() -> BestFirst<'a, T> {
+        BestFirst { queue: BinaryHeap::new(), heuristic: None, count: 0 }
+    }
+
+    /// Estimates the cost that remains to reach an answer from each node with the function.
+    pub fn with_heuristic<F>(self, heuristic: F) -> BestFirst<'a, T> where F: Fn(&State<T>) -> u64 + 'static {
+        BestFirst { heuristic: Some(Box::new(heuristic)), .. self }
+    }
+
+    fn add(&mut self, node: Node<'a, T>) {
+        let remaining = match self.heuristic {
+            Some(ref heuristic) if !node.is_answer() => heuristic(&node.state),
+            _ => 0,
+        };
+        self.count += 1;
+        self.queue.push(Ranked { estimate: node.cost.saturating_add(remaining), order: self.count, node });
+    }
+}
+
+impl<'a, T> Default for BestFirst<'a, T> where T: PartialEq + Unif<T> {
+    fn default() -> BestFirst<'a, T> {
+        BestFirst::new()
+    }
+}
+
+impl<'a, T> SearchStrategy<'a, T> for BestFirst<'a, T> where T: PartialEq + Unif<T> {
+    fn push(&mut self, children: Vec<Node<'a, T>>) {
+        // add the children in reverse, so that the first of equal cost is explored first
+        for child in children.into_iter().rev() {
+            self.add(child);
+        }
+    }
+
+    fn pop(&mut self) -> Option<Node<'a, T>> {
+        self.queue.pop().map(|ranked| ranked.node)
+    }
+
+    fn restore(&mut self, node: Node<'a, T>) {
+        self.add(node);
+    }
+}
+
+
+/// An iterator over the solutions of a goal, found by depth-first search or
+/// another `SearchStrategy`.
+///
+/// If the starting state has a `Stats` collector, the search records the
+/// branches it explores, the depth it reaches, and the time it spends.
+pub struct Search<'a, T> where T: PartialEq + Unif<T> {
+    frontier: Box<dyn SearchStrategy<'a, T> + 'a>,
+    pruned: Vec<Node<'a, T>>,
+    stats: Option<Arc<Stats>>,
+    compaction: Option<Compaction>,
+}
+
+/// How often a `Search` compacts the states it expands, and the variables
+/// whose bindings it keeps.
+struct Compaction {
+    interval: u64,
+    roots: Vec<Var>,
+    expanded: u64,
+}
+
+impl<'a, T> Search<'a, T> where T: PartialEq + Unif<T> {
+    /// Creates a depth-first search for the solutions of the goal, starting from the state.
+    pub fn new(goal: &'a (dyn Goal<T> + 'a), state: &State<T>) -> Search<'a, T> {
+        Search::with_strategy(goal, state, Box::new(DepthFirst::new()))
+    }
+
+    /// Creates a search for the solutions of the goal, starting from the
+    /// state, that explores nodes in the order decided by the strategy.
+    pub fn with_strategy(goal: &'a (dyn Goal<T> + 'a), state: &State<T>,
+                         mut strategy: Box<dyn SearchStrategy<'a, T> + 'a>) -> Search<'a, T> {
+        strategy.start(Node {
+            state: state.clone(),
+            goals: Some(Rc::new(Cont { goal: Subgoal::Borrowed(goal), next: None })),
+            depth: 0,
+            cost: 0,
+        });
+        Search { frontier: strategy, pruned: Vec::new(), stats: state.stats().cloned(), compaction: None }
+    }
+
+    /// Compacts the state of every `interval`th node that the search expands
+    /// (see `State::compact`), keeping the bindings of the root variables and
+    /// of the variables of the goals that remain to be proven at the node.
+    /// This caps the memory used by long searches that create many variables
+    /// they later forget, such as those of the clauses of a recursive program.
+    ///
+    /// The answers only bind the variables reachable from the roots.  The
+    /// states of paths with goals that cannot tell which variables they
+    /// refer to (see `Goal::each_var`) are not compacted.
+    pub fn compact_every(self, interval: u64, roots: &[Var]) -> Search<'a, T> {
+        let compaction = Compaction { interval: interval.max(1), roots: roots.to_vec(), expanded: 0 };
+        Search { compaction: Some(compaction), .. self }
+    }
+
+    /// Compacts the node's state, if its goals can tell which variables they refer to.
+    fn compact(node: &mut Node<'a, T>, roots: &[Var]) {
+        let mut vars = roots.to_vec();
+        let mut goals = node.goals.as_ref();
+        while let Some(cont) = goals {
+            if !cont.goal.each_var(&mut |var| vars.push(*var)) {
+                return;
+            }
+            goals = cont.next.as_ref();
+        }
+        node.state = node.state.compact(&vars);
+    }
+
+    /// Finds answers until the search is exhausted or stopped by one of the limits.
+    pub fn run(&mut self, limits: &SearchLimits) -> SearchResult<T> {
+        match self.stats.clone() {
+            Some(stats) => stats.time(|| self.run_within(limits)),
+            None => self.run_within(limits),
+        }
+    }
+
+    fn run_within(&mut self, limits: &SearchLimits) -> SearchResult<T> {
+        // resume the paths that earlier runs set aside, if they are now within the depth limit
+        let (resumed, pruned) = self.pruned.drain(..).partition(|node| limits.depth.is_none_or(|max| node.depth < max));
+        self.pruned = pruned;
+        let resumed: Vec<Node<'a, T>> = resumed;
+        for node in resumed.into_iter().rev() {
+            self.frontier.restore(node);
+        }
+
+        let mut answers = Vec::new();
+        let mut steps = 0;
+        loop {
+            if limits.answers.is_some_and(|max| answers.len() >= max) {
+                return SearchResult { answers, outcome: Outcome::LimitHit(Limit::Answers) };
+            }
+            match self.find_next(limits, &mut steps) {
+                Ok(state) => answers.push(state),
+                Err(outcome) => return SearchResult { answers, outcome },
+            }
+        }
+    }
+
+    /// Finds the next answer, or returns how the search ended.
+    fn find_next(&mut self, limits: &SearchLimits, steps: &mut u64) -> Result<State<T>, Outcome> {
+        while let Some(mut node) = self.frontier.pop() {
+            let cont = match node.goals {
+                Some(ref cont) => cont.clone(),
+                None => return Ok(node.state),
+            };
+
+            let stop = if limits.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                Some(Outcome::Cancelled)
+            } else if limits.steps.is_some_and(|max| *steps >= max) {
+                Some(Outcome::LimitHit(Limit::Steps))
+            } else if limits.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                Some(Outcome::LimitHit(Limit::Deadline))
+            } else {
+                None
+            };
+            if let Some(outcome) = stop {
+                self.frontier.restore(node);
+                return Err(outcome);
+            }
+            if limits.depth.is_some_and(|max| node.depth >= max) {
+                self.pruned.push(node);
+                continue;
+            }
+            *steps += 1;
+
+            if let Some(ref mut compaction) = self.compaction {
+                compaction.expanded += 1;
+                if compaction.expanded % compaction.interval == 0 {
+                    Search::compact(&mut node, &compaction.roots);
+                }
+            }
+            let branches = cont.goal.expand(&node.state);
+            if let Some(ref stats) = self.stats {
+                stats.record_depth(node.depth + 1);
+                if branches.len() > 1 {
+                    stats.record_branches(branches.len());
+                }
+            }
+
+            let children = branches.into_iter().map(|branch| {
+                let mut goals = cont.next.clone();
+                for goal in branch.goals.into_iter().rev() {
+                    goals = Some(Rc::new(Cont { goal, next: goals }));
+                }
+                Node { state: branch.state, goals, depth: node.depth + 1, cost: node.cost.saturating_add(branch.cost) }
+            }).collect();
+            self.frontier.push(children);
+        }
+        if self.pruned.is_empty() { Err(Outcome::Exhausted) } else { Err(Outcome::LimitHit(Limit::Depth)) }
+    }
+}
+
+impl<'a, T> Iterator for Search<'a, T> where T: PartialEq + Unif<T> {
+    type Item = State<T>;
+
+    fn next(&mut self) -> Option<State<T>> {
+        let mut result = self.run(&SearchLimits::new().answers(1));
+        result.answers.pop()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use state::{State, PossibleStates, Var};
+    use goal::{Goal, Branch, Subgoal, conj, cost, disj, fail, unify_val, unify_vars};
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+    use testing::Never;
+    use super::{Search, SearchLimits, Outcome, Limit, SearchStrategy};
+    use super::{DepthFirst, Interleaving, BreadthFirst, IterativeDeepening, BestFirst};
+
+    /// Binds the variable to each of the numbers from `n` upwards, without end.
+    struct From {
+        var: Var,
+        n: i32,
+    }
+
+    impl Goal<i32> for From {
+        fn eval(&self, _state: &State<i32>) -> PossibleStates<i32> {
+            panic!("infinitely many solutions")
+        }
+
+        fn expand<'a>(&'a self, state: &State<i32>) -> Vec<Branch<'a, i32>> {
+            Rc::new(From { var: self.var, n: self.n }).expand_shared(state)
+        }
+
+        fn expand_shared<'a>(self: Rc<Self>, state: &State<i32>) -> Vec<Branch<'a, i32>> {
+            let mut branches: Vec<Branch<i32>> = state.unify_val(&self.var, self.n).into_iter().map(Branch::done).collect();
+            let next = From { var: self.var, n: self.n + 1 };
+            branches.push(Branch::new(state.clone(), vec![Subgoal::Shared(Rc::new(next))]));
+            branches
+        }
+    }
+
+    fn strategies<'a>() -> Vec<Box<dyn SearchStrategy<'a, i32> + 'a>> {
+        vec![
+            Box::new(DepthFirst::new()),
+            Box::new(Interleaving::new()),
+            Box::new(BreadthFirst::new()),
+            Box::new(IterativeDeepening::new(4)),
+        ]
+    }
+
+    fn values(answers: &[State<i32>], var: &Var) -> Vec<i32> {
+        answers.iter().map(|s| *s.get(var).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_same_answers() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let g = conj(disj(unify_val(&a, 1), disj(unify_val(&a, 2), fail())),
+                     conj(unify_vars(&a, &b), disj(unify_val(&b, 2), unify_val(&b, 1))));
+
+        let mut eager: Vec<i32> = g.eval(&s).iter().map(|s| *s.get(&b).unwrap()).collect();
+        let lazy: Vec<i32> = Search::new(&g, &s).map(|s| *s.get(&b).unwrap()).collect();
+        assert_eq!(lazy, vec![1, 2]);
+        eager.sort();
+        assert_eq!(eager, lazy);
+    }
+
+    #[test]
+    fn test_depth_first_order() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let g = disj(disj(unify_val(&a, 1), unify_val(&a, 2)), unify_val(&a, 3));
+
+        let eager: Vec<i32> = g.eval(&s).iter().map(|s| *s.get(&a).unwrap()).collect();
+        let lazy: Vec<i32> = Search::new(&g, &s).map(|s| *s.get(&a).unwrap()).collect();
+        assert_eq!(eager, vec![1, 3, 2]);
+        assert_eq!(lazy, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let g = From { var: a, n: 0 };
+
+        let mut search = Search::new(&g, &s);
+        let result = search.run(&SearchLimits::new().steps(3));
+        assert_eq!(values(&result.answers, &a), vec![0, 1, 2]);
+        assert_eq!(result.outcome, Outcome::LimitHit(Limit::Steps));
+
+        // each run has its own budget, and continues where the last one stopped
+        let result = search.run(&SearchLimits::new().steps(3));
+        assert_eq!(values(&result.answers, &a), vec![3, 4, 5]);
+        assert_eq!(search.next().and_then(|s| s.get(&a).cloned()), Some(6));
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let g = disj(unify_val(&a, 1), disj(unify_val(&a, 2), unify_val(&a, 3)));
+
+        let mut search = Search::new(&g, &s);
+        let result = search.run(&SearchLimits::new().depth(2));
+        assert_eq!(values(&result.answers, &a), vec![1]);
+        assert_eq!(result.outcome, Outcome::LimitHit(Limit::Depth));
+
+        let result = search.run(&SearchLimits::new().depth(3));
+        assert_eq!(values(&result.answers, &a), vec![2, 3]);
+        assert_eq!(result.outcome, Outcome::Exhausted);
+    }
+
+    #[test]
+    fn test_cancel_and_deadline() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let g = From { var: a, n: 0 };
+        let mut search = Search::new(&g, &s);
+
+        let cancel = Arc::new(AtomicBool::new(true));
+        let result = search.run(&SearchLimits::new().cancel_flag(cancel.clone()));
+        assert!(result.answers.is_empty());
+        assert_eq!(result.outcome, Outcome::Cancelled);
+
+        let result = search.run(&SearchLimits::new().deadline(Instant::now()));
+        assert_eq!(result.outcome, Outcome::LimitHit(Limit::Deadline));
+
+        cancel.store(false, Ordering::Relaxed);
+        let result = search.run(&SearchLimits::new().cancel_flag(cancel).answers(2));
+        assert_eq!(values(&result.answers, &a), vec![0, 1]);
+        assert_eq!(result.outcome, Outcome::LimitHit(Limit::Answers));
+    }
+
+    #[test]
+    fn test_strategy_orders() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let deep = |n| conj(unify_val(&b, 0), conj(unify_val(&b, 0), unify_val(&a, n)));
+        let g = disj(disj(deep(1), unify_val(&a, 2)),
+                     disj(disj(unify_val(&a, 3), deep(4)), disj(unify_val(&a, 5), disj(deep(6), unify_val(&a, 7)))));
+
+        let orders: Vec<Vec<i32>> = strategies().into_iter()
+            .map(|strategy| Search::with_strategy(&g, &s, strategy).map(|s| *s.get(&a).unwrap()).collect())
+            .collect();
+        assert_eq!(orders, vec![
+            vec![1, 2, 3, 4, 5, 6, 7],
+            vec![2, 3, 1, 5, 7, 4, 6],
+            vec![2, 3, 5, 7, 1, 4, 6],
+            vec![2, 3, 5, 1, 4, 7, 6],
+        ]);
+        for mut order in orders {
+            order.sort();
+            assert_eq!(order, vec![1, 2, 3, 4, 5, 6, 7]);
+        }
+    }
+
+    #[test]
+    fn test_unending_branch() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let g = disj(Never, disj(unify_val(&a, 1), unify_val(&a, 2)));
+
+        let mut results = strategies().into_iter().map(|strategy| {
+            let result = Search::with_strategy(&g, &s, strategy).run(&SearchLimits::new().steps(100).answers(2));
+            (values(&result.answers, &a), result.outcome)
+        });
+        assert_eq!(results.next(), Some((vec![], Outcome::LimitHit(Limit::Steps))));
+        for result in results {
+            assert_eq!(result, (vec![1, 2], Outcome::LimitHit(Limit::Answers)));
+        }
+    }
+
+    #[test]
+    fn test_best_first() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let g = conj(disj(cost(1, unify_val(&a, 1)), cost(5, unify_val(&a, 2))),
+                     disj(cost(4, unify_val(&b, 1)), cost(1, unify_val(&b, 2))));
+        let pairs = |strategy| -> Vec<(i32, i32)> {
+            Search::with_strategy(&g, &s, strategy).map(|s| (*s.get(&a).unwrap(), *s.get(&b).unwrap())).collect()
+        };
+
+        let expected = vec![(1, 2), (1, 1), (2, 2), (2, 1)];
+        assert_eq!(pairs(Box::<BestFirst<_>>::default()), expected);
+
+        // the cheapest way to bind `b` costs 1, so counting it while `b` is unbound is admissible
+        let heuristic = move |s: &State<i32>| if s.get(&b).is_some() { 0 } else { 1 };
+        assert_eq!(pairs(Box::new(BestFirst::new().with_heuristic(heuristic))), expected);
+        assert_eq!(pairs(Box::<DepthFirst<_>>::default()), vec![(1, 1), (1, 2), (2, 1), (2, 2)]);
+
+        // costs that add up past `u64::MAX` saturate
+        let g = conj(cost(u64::MAX, unify_val(&a, 1)), cost(1, unify_val(&b, 2)));
+        let mut search = Search::with_strategy(&g, &s, Box::<BestFirst<_>>::default());
+        assert!(search.next().is_some());
+    }
+}
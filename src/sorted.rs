@@ -0,0 +1,318 @@
+//! # Multi-Sorted States
+//!
+//! A `state::State<T>` binds variables to values of a single type.  A
+//! multi-sorted state binds variables of several types at once, so that a
+//! model that mixes integers, strings and enums does not need a catch-all
+//! value type.  Each variable is typed: a `Var<V>` can only be bound to
+//! values of type `V`, and only be unified with other `Var<V>`s, which is
+//! checked at compile time.
+//!
+//! A multi-sorted state is a `state::State<Sorts>`, which holds a
+//! single-sorted `state::State<V>` for each type of value `V`, keyed by its
+//! `TypeId`.  Goals over one sort are lifted into goals over multi-sorted
+//! states with `sort`, and `unify_val` and `unify_vars` are the lifted goals
+//! of the same names for typed variables, so the goals in `goal` combine
+//! them as they do any other goals.
+//!
+//! ```
+//! use rslogic::goal::{Goal, conj, disj};
+//! use rslogic::sorted::{State, MultiSorted, unify_val, unify_vars};
+//!
+//! let s = State::empty();
+//! let (age, s) = s.make_typed_var::<i32>();
+//! let (name, s) = s.make_typed_var::<String>();
+//! let (other, s) = s.make_typed_var::<String>();
+//!
+//! let g = conj(unify_vars(&name, &other),
+//!              disj(conj(unify_val(&other, "alice".to_string()), unify_val(&age, 30)),
+//!                   conj(unify_val(&name, "bob".to_string()), unify_val(&age, 40))));
+//!
+//! let results = g.eval(&s);
+//! assert_eq!(results.len(), 2);
+//! assert_eq!(results[0].get_typed(&name).map(|n| n.as_str()), Some("alice"));
+//! assert_eq!(results[1].get_typed(&age), Some(&40));
+//! ```
+//!
+//! Variables of different types cannot be unified:
+//!
+//! ```compile_fail
+//! use rslogic::sorted::{State, MultiSorted, unify_vars};
+//!
+//! let s = State::empty();
+//! let (age, s) = s.make_typed_var::<i32>();
+//! let (name, _) = s.make_typed_var::<String>();
+//! let g = unify_vars(&age, &name);
+//! ```
+
+use attr::{Attribute, Goals};
+use btmap::BtMap;
+use error::Error;
+use goal;
+use state::{self, Unif};
+use std::any::{Any, TypeId};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The types of values that can be bound in a multi-sorted state.
+pub trait Sort: Sized + PartialEq + Unif<Self> + Send + Sync + 'static {}
+
+impl<V> Sort for V where V: PartialEq + Unif<V> + Send + Sync + 'static {}
+
+/// A logical variable that can be bound to values of type `V`.
+pub struct Var<V> {
+    var: state::Var,
+    _m: PhantomData<fn() -> V>,
+}
+
+impl<V> Var<V> {
+    /// Returns the variable in the single-sorted state of its sort, for use
+    /// in goals over that sort.
+    pub fn untyped(&self) -> state::Var {
+        self.var
+    }
+}
+
+impl<V> Clone for Var<V> {
+    fn clone(&self) -> Var<V> {
+        *self
+    }
+}
+
+impl<V> Copy for Var<V> {}
+
+impl<V> PartialEq for Var<V> {
+    fn eq(&self, other: &Var<V>) -> bool {
+        self.var == other.var
+    }
+}
+
+impl<V> Eq for Var<V> {}
+
+impl<V> Hash for Var<V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.var.hash(state)
+    }
+}
+
+impl<V> fmt::Display for Var<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.var, f)
+    }
+}
+
+impl<V> fmt::Debug for Var<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.var, f)
+    }
+}
+
+/// The value type of multi-sorted states.  Nothing is ever bound to their
+/// own variables: the bindings of typed variables are kept in the state of
+/// each sort.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Sorts {}
+
+impl Unif<Sorts> for Sorts {
+    fn unify(&self, _: &Sorts, _: &State) -> PossibleStates {
+        match *self {}
+    }
+
+    fn each_var(&self, _: &mut dyn FnMut(&state::Var)) -> bool {
+        match *self {}
+    }
+}
+
+impl fmt::Display for Sorts {
+    fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+
+/// A logical state, containing bindings of variables of several types.
+pub type State = state::State<Sorts>;
+
+/// A collection of possible multi-sorted states.
+pub type PossibleStates = state::PossibleStates<Sorts>;
+
+/// The single-sorted states of a multi-sorted state, which are kept as an
+/// attribute of its first variable.
+#[derive(Clone, Default)]
+struct SortStates {
+    sorts: BtMap<TypeId, Arc<dyn Any + Send + Sync>>, // type of value -> state::State<V>
+}
+
+impl Attribute<Sorts> for SortStates {
+    fn verify_attributes(&self, _: &state::Var, value: &Sorts, _: &State) -> Option<Goals<Sorts>> {
+        match *value {}
+    }
+}
+
+/// The operations of multi-sorted states on typed variables.
+pub trait MultiSorted: Sized {
+    /// Returns the single-sorted state holding the bindings of variables of type `V`.
+    fn sort_state<V>(&self) -> state::State<V> where V: Sort;
+
+    /// Returns a copy of the state with the bindings of variables of type `V` replaced.
+    fn with_sort_state<V>(&self, sort: state::State<V>) -> Self where V: Sort;
+
+    /// Creates a new variable of type `V`, and a new state with which it is usable.
+    fn make_typed_var<V>(&self) -> (Var<V>, Self) where V: Sort;
+
+    /// Returns a reference to the value bound to the variable in the state,
+    /// or `None` if the variable is not bound.
+    fn get_typed<V>(&self, var: &Var<V>) -> Option<&V> where V: Sort;
+}
+
+/// Returns the single-sorted states of the state, if it has any.
+fn sort_states(state: &State) -> Option<&SortStates> {
+    match state.next_index() {
+        0 => None,
+        _ => state.get_attr::<SortStates>(&state::Var::new(0, state.lineage())),
+    }
+}
+
+impl MultiSorted for State {
+    fn sort_state<V>(&self) -> state::State<V> where V: Sort {
+        match sort_states(self).and_then(|states| states.sorts.get(&TypeId::of::<V>())) {
+            Some(sort) => sort.downcast_ref::<state::State<V>>().expect("sorts are keyed by their type").clone(),
+            None => state::State::empty(),
+        }
+    }
+
+    fn with_sort_state<V>(&self, sort: state::State<V>) -> State where V: Sort {
+        let (holder, state) = match self.next_index() {
+            0 => self.make_var(),
+            _ => (state::Var::new(0, self.lineage()), self.clone()),
+        };
+        let sorts = sort_states(&state).map(|states| states.sorts.clone()).unwrap_or_default();
+        state.put_attr(&holder, SortStates { sorts: sorts.replace(TypeId::of::<V>(), Arc::new(sort)) })
+    }
+
+    fn make_typed_var<V>(&self) -> (Var<V>, State) where V: Sort {
+        let (var, sort) = self.sort_state::<V>().make_var();
+        (Var { var, _m: PhantomData }, self.with_sort_state(sort))
+    }
+
+    fn get_typed<V>(&self, var: &Var<V>) -> Option<&V> where V: Sort {
+        sort_states(self)
+            .and_then(|states| states.sorts.get(&TypeId::of::<V>()))
+            .and_then(|sort| sort.downcast_ref::<state::State<V>>())
+            .and_then(|sort| sort.get(&var.var))
+    }
+}
+
+/// Returns a copy of the state for each of the possible states of one sort.
+fn with_sort_states<V>(state: &State, sorts: state::PossibleStates<V>) -> PossibleStates where V: Sort {
+    sorts.into_iter().map(|sort| state.with_sort_state(sort)).collect()
+}
+
+
+/// Evaluating a `Sorted` goal evaluates a goal over a single sort, in the
+/// bindings of the variables of that sort.
+pub struct Sorted<V, G> {
+    goal: G,
+    _m: PhantomData<fn() -> V>,
+}
+
+impl<V, G> goal::Goal<Sorts> for Sorted<V, G> where V: Sort, G: goal::Goal<V> {
+    fn eval(&self, state: &State) -> PossibleStates {
+        with_sort_states(state, self.goal.eval(&state.sort_state::<V>()))
+    }
+
+    fn try_eval(&self, state: &State) -> Result<PossibleStates, Error> {
+        Ok(with_sort_states(state, self.goal.try_eval(&state.sort_state::<V>())?))
+    }
+}
+
+impl<V, G> fmt::Display for Sorted<V, G> where G: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.goal, f)
+    }
+}
+
+impl<V, G> fmt::Debug for Sorted<V, G> where G: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.goal, f)
+    }
+}
+
+/// Creates a `Sorted` goal, which lifts a goal over values of type `V` into
+/// a goal over multi-sorted states.  The goal refers to variables by their
+/// `Var::untyped` counterparts.
+pub fn sort<V, G>(goal: G) -> Sorted<V, G> where V: Sort, G: goal::Goal<V> {
+    Sorted { goal, _m: PhantomData }
+}
+
+/// Creates a goal that attempts to unify the typed variable and the value.
+pub fn unify_val<V>(var: &Var<V>, val: V) -> Sorted<V, goal::UnifyVal<V>> where V: Sort + Clone + Eq {
+    sort(goal::unify_val(&var.var, val))
+}
+
+/// Creates a goal that attempts to unify two typed variables of the same type.
+pub fn unify_vars<V>(v1: &Var<V>, v2: &Var<V>) -> Sorted<V, goal::UnifyVar<V>> where V: Sort {
+    sort(goal::unify_vars(&v1.var, &v2.var))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use goal::{self, Goal, conj, disj, fail, pred};
+    use term::Term;
+    use super::{State, MultiSorted, sort, unify_val, unify_vars};
+
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Colour { Red, Green }
+
+    impl ::state::Unif<Colour> for Colour {
+        fn unify(&self, other: &Colour, prev: &::state::State<Colour>) -> ::state::PossibleStates<Colour> {
+            if self == other { vec![prev.clone()] } else { Vec::new() }
+        }
+    }
+
+    #[test]
+    fn test_sorts() {
+        let s = State::empty();
+        let (n, s) = s.make_typed_var::<i64>();
+        let (c, s) = s.make_typed_var::<Colour>();
+        let (t, s) = s.make_typed_var::<Term>();
+        let (d, s) = s.make_typed_var::<Colour>();
+
+        // variables of different sorts belong to different single-sorted states
+        assert!(c.untyped() != d.untyped());
+        assert!(n.untyped() != c.untyped());
+
+        let g = conj(conj(unify_vars(&c, &d), unify_val(&n, 7)),
+                     disj(unify_val(&d, Colour::Red), conj(unify_val(&c, Colour::Green), unify_val(&t, Term::atom("go")))));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].get_typed(&c), Some(&Colour::Red));
+        assert!(results[0].get_typed(&t).is_none());
+        assert_eq!(results[1].get_typed(&d), Some(&Colour::Green));
+        assert_eq!(results[1].get_typed(&t).map(|t| t.to_string()), Some("go".to_string()));
+        assert!(results.iter().all(|s| s.get_typed(&n) == Some(&7)));
+
+        let is_red = |s: &State| s.get_typed(&c) == Some(&Colour::Red);
+        assert_eq!(conj(g, pred(&is_red)).eval(&s).len(), 1);
+        assert!(conj(unify_val(&n, 1), conj(fail(), unify_val(&n, 1))).eval(&s).is_empty());
+    }
+
+    #[test]
+    fn test_lifted_goals() {
+        let s = State::empty();
+        let (a, s) = s.make_typed_var::<i32>();
+        let (b, s) = s.make_typed_var::<i32>();
+        let (name, s) = s.make_typed_var::<String>();
+
+        let ints = sort::<i32, _>(goal::conj(goal::unify_vars(&a.untyped(), &b.untyped()), goal::disj(goal::unify_val(&a.untyped(), 1), goal::unify_val(&a.untyped(), 2))));
+        let g = conj(unify_val(&name, "n".to_string()), ints);
+        let results: Vec<(i32, i32)> = g.eval(&s).iter().map(|s| (*s.get_typed(&a).unwrap(), *s.get_typed(&b).unwrap())).collect();
+        assert_eq!(results, vec![(1, 1), (2, 2)]);
+
+        let (foreign, _) = ::state::State::<i32>::empty().make_var();
+        assert_eq!(sort::<i32, _>(goal::unify_val(&foreign, 1)).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(unify_val(&a, 1).try_eval(&s).map(|r| r.len()), Ok(1));
+    }
+}
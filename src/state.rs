@@ -3,7 +3,18 @@
 //! A logical state is a collection of variable bindings.
 //!
 
+use attr::{Attribute, Attr, Attrs};
+use chr::{self, ConstraintStore};
+use error::Error;
+use goal::Goal;
 use std::clone::Clone;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use stats::Stats;
+use store::{self, Store, Next};
 
 /// A collection of possible states.
 pub type PossibleStates<T> = Vec<State<T>>;
@@ -13,173 +24,1523 @@ pub type PossibleStates<T> = Vec<State<T>>;
 /// values may be bound in various combinations.
 pub trait Unif<T> where T : PartialEq + Unif<T> {
     fn unify(&self, other: &T, prev: &State<T>) -> PossibleStates<T>;
+
+    /// Like `unify`, but returns an error instead of panicking if a variable
+    /// in the values cannot be used with the state, or the state is
+    /// inconsistent.
+    ///
+    /// The default calls `unify`, which suits values that contain no variables.
+    fn try_unify(&self, other: &T, prev: &State<T>) -> Result<PossibleStates<T>, Error> {
+        Ok(self.unify(other, prev))
+    }
+
+    /// Unifies the values in a mutable `store::Store`, calling `next` with the
+    /// store as it is after each way in which they unify, until `next` returns
+    /// `true` to stop.  Returns `true` if it was stopped.  The store is left
+    /// as it was found.
+    ///
+    /// The default unifies the values in a snapshot of the store.
+    fn unify_in(&self, other: &T, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool {
+        store::each_state(self.unify(other, &store.snapshot()), store, next)
+    }
+
+    /// Calls `f` with each variable that the value contains, and returns
+    /// `true`; or returns `false` if the value cannot tell which variables it
+    /// contains.  `State::compact` keeps the bindings that the values depend
+    /// on, so it leaves alone a state with values that cannot tell.
+    ///
+    /// The default returns `false`.
+    fn each_var(&self, _f: &mut dyn FnMut(&Var)) -> bool {
+        false
+    }
+}
+
+/// Values that may contain variables can be reified: copied with the
+/// variables that are bound in a state replaced by their values.
+pub trait Reify<T> where T : PartialEq + Unif<T> {
+    fn reify(&self, state: &State<T>) -> T;
+}
+
+/// Values that may contain variables can have them renamed, so that states
+/// can be compared regardless of which variables their values happen to contain.
+pub trait Rename<T> where T : PartialEq + Unif<T> {
+    /// Returns a copy of the value with each variable replaced by the result of `f`.
+    fn rename(&self, f: &mut dyn FnMut(&Var) -> Var) -> T;
 }
 
 /// Represents a logical variable.  A variable must be created by calling
 /// `State::make_var()` before a goal is evaluated (by passing the
 /// resulting state to a goal).
-#[derive(Clone, Copy)]
+///
+/// A variable belongs to the lineage of the state that created it: that
+/// state, and all the states derived from it.  Using the variable with a
+/// state of another lineage is a mistake, and panics.  In debug builds,
+/// using it with a state of the same lineage that was derived before the
+/// variable was created also panics.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Var {
     index: usize,
+    lineage: u64,
 }
 
-use btmap::BtMap;
+/// The lineage of the next empty state to be created.
+static NEXT_LINEAGE: AtomicU64 = AtomicU64::new(0);
+
+/// The lineage of the variables that stand for unbound variables when states
+/// are compared.  No state has it.
+const CANONICAL_LINEAGE: u64 = u64::MAX;
+
+impl Var {
+    /// Creates the variable with the given index, in the lineage.
+    pub(crate) fn new(index: usize, lineage: u64) -> Var {
+        Var { index, lineage }
+    }
+
+    /// Returns the index of the variable in the state that created it.
+    pub(crate) fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the lineage of the state that created the variable.
+    pub(crate) fn lineage(&self) -> u64 {
+        self.lineage
+    }
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "_{}", self.index)
+    }
+}
+
+impl fmt::Debug for Var {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+use btmap::{BtMap, DuplicateKey};
+use radix::RadixMap;
+use std::ops::Index;
+
+/// An immutable map from the indices of variables or slots, in which a state
+/// keeps its bindings.  Variable indices are dense, so `RadixMap` suits them
+/// better than `BtMap`, which has to compare keys and rebalance its tree as
+/// they are added; both implement the trait so they can be compared.
+pub trait IndexMap<V>: Clone + Default {
+    /// Creates an empty map.
+    fn empty() -> Self;
+    /// Returns the number of items in the map.
+    fn len(&self) -> usize;
+    /// Returns `true` if the map has no items.
+    fn is_empty(&self) -> bool { self.len() == 0 }
+    /// Returns the item corresponding to the index, if any.
+    fn get(&self, index: &usize) -> Option<&V>;
+    /// Returns `true` if the map contains an item corresponding to the index.
+    fn contains_key(&self, index: &usize) -> bool { self.get(index).is_some() }
+    /// Returns a new map with the item added, or an error if there is
+    /// already an item corresponding to the index.
+    fn insert(&self, index: usize, val: V) -> Result<Self, DuplicateKey>;
+    /// Returns a new map with the item added or replaced.
+    fn replace(&self, index: usize, val: V) -> Self;
+    /// Returns a new map without the item corresponding to the index.
+    fn remove(&self, index: &usize) -> Self;
+    /// Returns `true` if the maps share their storage.
+    fn ptr_eq(&self, other: &Self) -> bool;
+}
+
+impl<V> IndexMap<V> for BtMap<usize, V> {
+    fn empty() -> Self { BtMap::empty() }
+    fn len(&self) -> usize { BtMap::len(self) }
+    fn get(&self, index: &usize) -> Option<&V> { BtMap::get(self, index) }
+    fn contains_key(&self, index: &usize) -> bool { BtMap::contains_key(self, index) }
+    fn insert(&self, index: usize, val: V) -> Result<Self, DuplicateKey> { BtMap::insert(self, index, val) }
+    fn replace(&self, index: usize, val: V) -> Self { BtMap::replace(self, index, val) }
+    fn remove(&self, index: &usize) -> Self { BtMap::remove(self, index) }
+    fn ptr_eq(&self, other: &Self) -> bool { BtMap::ptr_eq(self, other) }
+}
+
+impl<V> IndexMap<V> for RadixMap<V> {
+    fn empty() -> Self { RadixMap::empty() }
+    fn len(&self) -> usize { RadixMap::len(self) }
+    fn get(&self, index: &usize) -> Option<&V> { RadixMap::get(self, index) }
+    fn contains_key(&self, index: &usize) -> bool { RadixMap::contains_key(self, index) }
+    fn insert(&self, index: usize, val: V) -> Result<Self, DuplicateKey> { RadixMap::insert(self, index, val) }
+    fn replace(&self, index: usize, val: V) -> Self { RadixMap::replace(self, index, val) }
+    fn remove(&self, index: &usize) -> Self { RadixMap::remove(self, index) }
+    fn ptr_eq(&self, other: &Self) -> bool { RadixMap::ptr_eq(self, other) }
+}
+
+/// The kinds of `IndexMap` that a state can keep its maps in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Backing {
+    /// A `RadixMap`, which suits the dense indices of variables best.
+    #[default]
+    Radix,
+    /// A `BtMap`.
+    Tree,
+}
+
+/// The `IndexMap` that states use for everything they keep by the index of a
+/// variable or slot: bindings, values, attributes and names.  It is backed by
+/// whichever kind of map the state was created with; see `State::empty_in`.
+pub struct StateMap<V>(Map<V>);
+
+enum Map<V> {
+    Radix(RadixMap<V>),
+    Tree(BtMap<usize, V>),
+}
+
+impl<V> StateMap<V> {
+    /// Creates an empty map of the given kind.
+    pub fn empty_in(backing: Backing) -> StateMap<V> {
+        match backing {
+            Backing::Radix => StateMap(Map::Radix(RadixMap::empty())),
+            Backing::Tree => StateMap(Map::Tree(BtMap::empty())),
+        }
+    }
+
+    /// Returns the kind of map that backs this one.
+    pub fn backing(&self) -> Backing {
+        match self.0 {
+            Map::Radix(_) => Backing::Radix,
+            Map::Tree(_) => Backing::Tree,
+        }
+    }
+
+    /// Returns an iterator over the indices and items in the map, in order of
+    /// their indices.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (usize, &V)> + '_> {
+        match self.0 {
+            Map::Radix(ref map) => Box::new(map.iter()),
+            Map::Tree(ref map) => Box::new(map.iter().map(|(&index, val)| (index, val))),
+        }
+    }
+}
+
+impl<V> IndexMap<V> for StateMap<V> {
+    fn empty() -> Self {
+        StateMap::empty_in(Backing::default())
+    }
+
+    fn len(&self) -> usize {
+        match self.0 {
+            Map::Radix(ref map) => map.len(),
+            Map::Tree(ref map) => map.len(),
+        }
+    }
+
+    fn get(&self, index: &usize) -> Option<&V> {
+        match self.0 {
+            Map::Radix(ref map) => map.get(index),
+            Map::Tree(ref map) => map.get(index),
+        }
+    }
+
+    fn insert(&self, index: usize, val: V) -> Result<Self, DuplicateKey> {
+        Ok(StateMap(match self.0 {
+            Map::Radix(ref map) => Map::Radix(map.insert(index, val)?),
+            Map::Tree(ref map) => Map::Tree(map.insert(index, val)?),
+        }))
+    }
+
+    fn replace(&self, index: usize, val: V) -> Self {
+        StateMap(match self.0 {
+            Map::Radix(ref map) => Map::Radix(map.replace(index, val)),
+            Map::Tree(ref map) => Map::Tree(map.replace(index, val)),
+        })
+    }
+
+    fn remove(&self, index: &usize) -> Self {
+        StateMap(match self.0 {
+            Map::Radix(ref map) => Map::Radix(map.remove(index)),
+            Map::Tree(ref map) => Map::Tree(map.remove(index)),
+        })
+    }
+
+    fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Map::Radix(a), Map::Radix(b)) => a.ptr_eq(b),
+            (Map::Tree(a), Map::Tree(b)) => a.ptr_eq(b),
+            _ => false,
+        }
+    }
+}
+
+impl<V> Clone for StateMap<V> {
+    fn clone(&self) -> StateMap<V> {
+        StateMap(match self.0 {
+            Map::Radix(ref map) => Map::Radix(map.clone()),
+            Map::Tree(ref map) => Map::Tree(map.clone()),
+        })
+    }
+}
+
+impl<V> Default for StateMap<V> {
+    fn default() -> StateMap<V> {
+        StateMap::empty()
+    }
+}
+
+impl<V> Index<&usize> for StateMap<V> {
+    type Output = V;
+    fn index(&self, index: &usize) -> &V {
+        self.get(index).expect("no entry found for key")
+    }
+}
+
+impl<V> fmt::Debug for StateMap<V> where V: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
 
 /// A logical state, containing a collection of variable bindings.
 ///
 /// Variables are stored with one level of indirection, to indicate
 /// variables that have been unified before being bound.
 pub struct State<T> where T : PartialEq + Unif<T> {
-    bindings: BtMap<usize, usize>, // var index -> slot
-    slots: BtMap<usize, T>, // slot -> value
+    bindings: StateMap<usize>, // var index -> slot
+    slots: StateMap<T>, // slot -> value
+    attrs: StateMap<Attrs<T>>, // slot -> attributes
+    constraints: Arc<ConstraintStore>,
+    names: StateMap<String>, // var index -> name
     next_index: usize,
+    lineage: u64,
+    stats: Option<Arc<Stats>>,
 }
 
 impl<T> State<T> where T : PartialEq + Unif<T> {
     /// Creates an empty state.
     pub fn empty() -> State<T> {
+        State::empty_in(Backing::default())
+    }
+
+    /// Creates an empty state that keeps its bindings, values, attributes and
+    /// names in the given kind of map, as do all the states derived from it.
+    ///
+    /// ```
+    /// use rslogic::goal::{Goal, unify_val};
+    /// use rslogic::state::{Backing, State};
+    ///
+    /// let s = State::<i32>::empty_in(Backing::Tree);
+    /// let (a, s) = s.make_var();
+    /// assert_eq!(unify_val(&a, 1).eval(&s)[0].get(&a), Some(&1));
+    /// ```
+    pub fn empty_in(backing: Backing) -> State<T> {
+        State {
+            bindings: StateMap::empty_in(backing),
+            slots: StateMap::empty_in(backing),
+            attrs: StateMap::empty_in(backing),
+            constraints: Arc::new(ConstraintStore::new()),
+            names: StateMap::empty_in(backing),
+            next_index: 0,
+            lineage: NEXT_LINEAGE.fetch_add(1, Ordering::Relaxed),
+            stats: None,
+        }
+    }
+
+    /// Returns a copy of the state that records its work, and the work of
+    /// every state derived from it, in the collector.
+    pub fn with_stats(&self, stats: Arc<Stats>) -> State<T> {
+        stats.record_state();
+        State {
+            bindings: self.bindings.clone(),
+            slots: self.slots.clone(),
+            attrs: self.attrs.clone(),
+            constraints: self.constraints.clone(),
+            names: self.names.clone(),
+            next_index: self.next_index,
+            lineage: self.lineage,
+            stats: Some(stats),
+        }
+    }
+
+    /// Returns the statistics collector attached to the state, if any.
+    pub fn stats(&self) -> Option<&Arc<Stats>> {
+        self.stats.as_ref()
+    }
+
+    /// Creates a state with the same variables as this one, and the given bindings.
+    fn derive(&self, bindings: StateMap<usize>, slots: StateMap<T>) -> State<T> {
+        if let Some(ref stats) = self.stats {
+            stats.record_state();
+        }
         State {
-            bindings: BtMap::empty(),
-            slots: BtMap::empty(),
-            next_index: 0
+            bindings,
+            slots,
+            attrs: self.attrs.clone(),
+            constraints: self.constraints.clone(),
+            names: self.names.clone(),
+            next_index: self.next_index,
+            lineage: self.lineage,
+            stats: self.stats.clone(),
+        }
+    }
+
+    fn record_unification(&self, results: &PossibleStates<T>) {
+        if let Some(ref stats) = self.stats {
+            stats.record_unification(!results.is_empty());
         }
     }
 
     /// Returns `true` if the variable is bound in the state.
     pub fn binds_var(&self, var: &Var) -> bool {
-        match self.bindings.get(&var.index) {
-            Some(ref slot) => self.slots.contains_key(slot),
-            None => false
-        }
+        self.assert_var(var);
+        self.value_of(var).is_some()
     }
 
     /// Returns a reference to the value bound to the variable in the state,
-    /// or None if the var4iable is not bound.
+    /// or None if the variable is not bound.
     pub fn get<'a>(&'a self, var: &Var) -> Option<&'a T> {
-        match self.bindings.get(&var.index) {
-            Some(ref slot) => self.slots.get(slot),
-            None => None,
-        }
+        self.assert_var(var);
+        self.value_of(var)
+    }
+
+    /// Like `get`, but returns an error instead of panicking if the variable
+    /// cannot be used with the state.
+    pub fn try_get<'a>(&'a self, var: &Var) -> Result<Option<&'a T>, Error> {
+        self.check_var(var)?;
+        Ok(self.value_of(var))
+    }
+
+    fn value_of(&self, var: &Var) -> Option<&T> {
+        self.slots.get(&self.slot(var.index)?)
     }
 
     /// Attempts to unify a variable with a value.  If the variable is not bound,
     /// returns a new state containing a binding to the value.  If the variable is
     /// already bound, returns the unification of the two values.
     pub fn unify_val(&self, var: &Var, val: T) -> PossibleStates<T> {
-        match self.bindings.get(&var.index) {
+        self.assert_var(var);
+        let results = expect(self.bind_val(var, val));
+        self.record_unification(&results);
+        results
+    }
+
+    /// Like `unify_val`, but returns an error instead of panicking if a
+    /// variable cannot be used with the state, or the state is inconsistent.
+    pub fn try_unify_val(&self, var: &Var, val: T) -> Result<PossibleStates<T>, Error> {
+        self.check_var(var)?;
+        let results = self.bind_val(var, val)?;
+        self.record_unification(&results);
+        Ok(results)
+    }
+
+    fn bind_val(&self, var: &Var, val: T) -> Result<PossibleStates<T>, Error> {
+        let inconsistent = |DuplicateKey| Error::Inconsistent(vec![*var]);
+        match self.slot(var.index) {
             Some(slot) => {
                 // if the variable has a slot (could be bound or unified with another variable)
                 // see if it has a value.  if so, unify with the value, otherwise bind it to the value
-                match self.slots.get(slot) {
-                    Some(existing) => {
-                        existing.unify(&val, self)
-                    },
+                match self.slots.get(&slot) {
+                    Some(existing) => existing.try_unify(&val, self),
                     None => {
-                        vec![State {
-                            bindings: self.bindings.clone(),
-                            slots: self.slots.insert(*slot, val).unwrap(),
-                            .. *self
-                        }]
+                        let state = self.derive(self.bindings.clone(), self.slots.insert(slot, val).map_err(inconsistent)?);
+                        state.verify_attributes(var, self.attrs_of(slot))
                     }
                 }
             },
             None => {
                 // if this variable is not bound, make a new slot and binding for it
-                let index = &var.index;
-                vec![State {
-                    bindings: self.bindings.insert(*index, *index).unwrap(),
-                    slots: self.slots.insert(*index, val).unwrap(),
-                    .. *self
-                }]
+                let index = var.index;
+                let bindings = self.bindings.insert(index, index).map_err(inconsistent)?;
+                Ok(vec![self.derive(bindings, self.slots.insert(index, val).map_err(inconsistent)?)])
             }
         }
     }
 
     /// Attempts to unify two variables.
     pub fn unify_var(&self, v1: &Var, v2: &Var) -> PossibleStates<T> {
-        let b1 = self.bindings.get(&v1.index);
-        let b2 = self.bindings.get(&v2.index);
-
-        match b1 {
-            Some(s1) => { // v1 has a slot
-                match b2 {
-                    Some(s2) => { // both variables have slots
-                        let value1 = self.slots.get(s1);
-                        let value2 = self.slots.get(s2);
-
-                        match value1 {
-                            Some(vv1) => {
-                                match value2 {
-                                    Some(ref vv2) => vv1.unify(vv2, self), // both v1 and v2 are bound, unify values
-                                    None => PossibleStates::new()    // v2 is not bound, this is an error
-                                }
-                            },
-                            None => {
-                                match value2 {
-                                    Some(_vv2) => PossibleStates::new(), // v1 is not bound, this is an error
-                                    None => if s1.eq(s2) { vec![self.clone()] }
-                                            else { PossibleStates::new() } // neither slot is bound; slots must then be equal
-                                }
-                            }
-                        }
-                    },
-                    None => { // v1 has a slot, v2 does not
-                        vec![State {
-                            bindings: self.bindings.insert(v2.index, *s1).unwrap(),
-                            slots: self.slots.clone(),
-                            .. *self
-                        }]
-                    }
+        self.assert_var(v1);
+        self.assert_var(v2);
+        let results = expect(self.bind_var(v1, v2));
+        self.record_unification(&results);
+        results
+    }
+
+    /// Like `unify_var`, but returns an error instead of panicking if a
+    /// variable cannot be used with the state, or the state is inconsistent.
+    pub fn try_unify_var(&self, v1: &Var, v2: &Var) -> Result<PossibleStates<T>, Error> {
+        self.check_var(v1)?;
+        self.check_var(v2)?;
+        let results = self.bind_var(v1, v2)?;
+        self.record_unification(&results);
+        Ok(results)
+    }
+
+    fn bind_var(&self, v1: &Var, v2: &Var) -> Result<PossibleStates<T>, Error> {
+        let inconsistent = |DuplicateKey| Error::Inconsistent(vec![*v1, *v2]);
+        if v1.index == v2.index {
+            return Ok(vec![self.clone()]);
+        }
+        match (self.slot(v1.index), self.slot(v2.index)) {
+            (Some(s1), Some(s2)) if s1 == s2 => Ok(vec![self.clone()]),
+            (Some(s1), Some(s2)) => { // both variables have slots
+                match (self.slots.get(&s1), self.slots.get(&s2)) {
+                    (Some(vv1), Some(vv2)) => vv1.try_unify(vv2, self), // both are bound, unify values
+                    (_, None) => self.link(v2, s2, s1), // v2 is not bound, so it joins v1
+                    (None, Some(_)) => self.link(v1, s1, s2), // v1 is not bound, so it joins v2
                 }
             },
-            None => { // v1 does not have a slot
-                match b2 {
-                    Some(s2) => { // v1 does not have a slot, v2 does
-                        vec![State {
-                            bindings: self.bindings.insert(v1.index, *s2).unwrap(),
-                            slots: self.slots.clone(),
-                            .. *self
-                        }]
-                    },
-                    None => { // neither variable has a slot
-                        let slot = &v1.index;
-                        vec![State {
-                            bindings: self.bindings
-                                        .insert(v1.index, *slot).unwrap()
-                                        .insert(v2.index, *slot).unwrap(),
-                            slots: self.slots.clone(),
-                            .. *self
-                        }]
+            (Some(s1), None) => { // v1 has a slot, v2 does not
+                Ok(vec![self.derive(self.bindings.insert(v2.index, s1).map_err(inconsistent)?, self.slots.clone())])
+            },
+            (None, Some(s2)) => { // v1 does not have a slot, v2 does
+                Ok(vec![self.derive(self.bindings.insert(v1.index, s2).map_err(inconsistent)?, self.slots.clone())])
+            },
+            (None, None) => { // neither variable has a slot
+                let slot = v1.index;
+                let bindings = self.bindings
+                                .insert(v1.index, slot).map_err(inconsistent)?
+                                .insert(v2.index, slot).map_err(inconsistent)?;
+                Ok(vec![self.derive(bindings, self.slots.clone())])
+            }
+        }
+    }
+
+    /// Links the unbound slot `from`, which is the variable's, to the slot
+    /// `to`, so that the variables of both share `to`.  The attributes of
+    /// `from` are verified if `to` is bound.  Otherwise they are added to
+    /// those of `to`, and all of them are verified as unified.
+    fn link(&self, var: &Var, from: usize, to: usize) -> Result<PossibleStates<T>, Error> {
+        let state = self.derive(self.bindings.replace(from, to), self.slots.clone());
+        let attrs = self.attrs_of(from);
+        if self.slots.contains_key(&to) {
+            if attrs.is_empty() { Ok(vec![state]) } else { state.verify_attributes(var, attrs) }
+        } else {
+            let mut merged = self.attrs_of(to);
+            if attrs.is_empty() && merged.is_empty() {
+                return Ok(vec![state]);
+            }
+            merged.extend(attrs);
+            let state = State { attrs: state.attrs.remove(&from).replace(to, merged.clone()), .. state };
+            state.verify_attributes(var, merged)
+        }
+    }
+
+    /// Creates a state from its bindings, for converting from other
+    /// representations of states such as `store::Trail`.
+    pub(crate) fn from_parts(bindings: StateMap<usize>, slots: StateMap<T>, attrs: StateMap<Attrs<T>>,
+                             constraints: Arc<ConstraintStore>, next_index: usize, lineage: u64, stats: Option<Arc<Stats>>) -> State<T> {
+        if let Some(ref stats) = stats {
+            stats.record_state();
+        }
+        let names = StateMap::empty_in(bindings.backing());
+        State { bindings, slots, attrs, constraints, names, next_index, lineage, stats }
+    }
+
+    /// Returns `true` if the state has the very bindings of `other`, without
+    /// comparing them.  Unification always makes new bindings, so a state
+    /// derived from `other` that has them has not bound or unified anything.
+    pub(crate) fn same_bindings(&self, other: &State<T>) -> bool {
+        self.bindings.ptr_eq(&other.bindings) && self.slots.ptr_eq(&other.slots)
+    }
+
+    /// Returns the constraint handling rules' store.
+    pub(crate) fn constraints(&self) -> &Arc<ConstraintStore> {
+        &self.constraints
+    }
+
+    /// Returns a copy of the state with a different constraint store.
+    pub(crate) fn with_constraints(&self, constraints: Arc<ConstraintStore>) -> State<T> {
+        State { constraints, .. self.clone() }
+    }
+
+    /// Returns the names of the variables, by index.
+    pub(crate) fn names(&self) -> &StateMap<String> {
+        &self.names
+    }
+
+    /// Replaces the names of the state's variables.
+    pub(crate) fn with_names(self, names: StateMap<String>) -> State<T> {
+        State { names, .. self }
+    }
+
+    /// Returns the attributes of the variables, by slot.
+    pub(crate) fn attrs(&self) -> &StateMap<Attrs<T>> {
+        &self.attrs
+    }
+
+    /// Returns the number of variables that have been created for the state.
+    pub(crate) fn next_index(&self) -> usize {
+        self.next_index
+    }
+
+    /// Returns the lineage of the state.
+    pub(crate) fn lineage(&self) -> u64 {
+        self.lineage
+    }
+
+    /// Returns an error if the variable cannot be used with the state.
+    fn check_var(&self, var: &Var) -> Result<(), Error> {
+        if var.lineage != self.lineage {
+            Err(Error::ForeignVar(*var))
+        } else if var.index >= self.next_index {
+            Err(Error::UnknownVar(*var))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Panics if the variable cannot be used with the state.  Variables
+    /// created after the state are only detected in debug builds.
+    fn assert_var(&self, var: &Var) {
+        if var.lineage != self.lineage || cfg!(debug_assertions) {
+            expect(self.check_var(var));
+        }
+    }
+
+    /// Returns the slot of the variable with the given index, if it has one.
+    /// A slot that was unified with another slot while both were unbound is
+    /// bound to the other's variable, so the bindings are followed until a
+    /// slot that is bound to itself.
+    pub(crate) fn slot(&self, index: usize) -> Option<usize> {
+        let mut slot = *self.bindings.get(&index)?;
+        while let Some(&next) = self.bindings.get(&slot) {
+            if next == slot {
+                break;
+            }
+            slot = next;
+        }
+        Some(slot)
+    }
+
+    /// Returns the attributes in the slot.
+    fn attrs_of(&self, slot: usize) -> Attrs<T> {
+        self.attrs.get(&slot).cloned().unwrap_or_default()
+    }
+
+    /// Returns the value in the slot, if it is bound.
+    pub(crate) fn slot_value(&self, slot: usize) -> Option<&T> {
+        self.slots.get(&slot)
+    }
+
+    /// Returns a copy of the state in which the variable has the attribute,
+    /// replacing any attribute of the same type that it already had.  The
+    /// attribute is shared by the variables unified with this one.
+    ///
+    /// An attribute is verified when its variable is bound, so the attributes
+    /// of a variable that is already bound are never verified.
+    pub fn put_attr<A>(&self, var: &Var, attr: A) -> State<T> where A: Attribute<T> {
+        self.assert_var(var);
+        let (bindings, slot) = match self.slot(var.index) {
+            Some(slot) => (self.bindings.clone(), slot),
+            None => (self.bindings.replace(var.index, var.index), var.index),
+        };
+        let mut attrs = self.attrs_of(slot);
+        attrs.retain(|attr| !attr.is::<A>());
+        attrs.push(Attr::new(attr));
+        State { attrs: self.attrs.replace(slot, attrs), .. self.derive(bindings, self.slots.clone()) }
+    }
+
+    /// Returns the variable's attribute of type `A`, if it has one.
+    pub fn get_attr<A>(&self, var: &Var) -> Option<&A> where A: Attribute<T> {
+        self.assert_var(var);
+        let attrs = self.attrs.get(&self.slot(var.index)?)?;
+        attrs.iter().find(|attr| attr.is::<A>()).and_then(Attr::downcast)
+    }
+
+    /// Returns a copy of the state in which the variable does not have an attribute of type `A`.
+    pub fn del_attr<A>(&self, var: &Var) -> State<T> where A: Attribute<T> {
+        self.assert_var(var);
+        let slot = match self.slot(var.index) {
+            Some(slot) if self.attrs.contains_key(&slot) => slot,
+            _ => return self.clone(),
+        };
+        let mut attrs = self.attrs[&slot].clone();
+        attrs.retain(|attr| !attr.is::<A>());
+        State { attrs: self.attrs.replace(slot, attrs), .. self.clone() }
+    }
+
+    /// Calls the hooks of the attributes, which belong to the variable's
+    /// slot that has just been bound to a value or unified with another
+    /// unbound variable, and proves the goals they produce.
+    fn verify_attributes(self, var: &Var, attrs: Attrs<T>) -> Result<PossibleStates<T>, Error> {
+        let mut states = vec![self];
+        for attr in attrs {
+            let mut verified = PossibleStates::new();
+            for state in states {
+                let goals = match state.value_of(var) {
+                    Some(value) => attr.verify(var, value, &state),
+                    None => attr.verify_unified(var, &state),
+                };
+                let goals = match goals {
+                    Some(goals) => goals,
+                    None => continue,
+                };
+                let mut proven = vec![state];
+                for goal in goals {
+                    let mut next = PossibleStates::new();
+                    for state in proven {
+                        next.append(&mut goal.try_eval(&state)?);
                     }
+                    proven = next;
+                }
+                verified.append(&mut proven);
+            }
+            states = verified;
+        }
+        Ok(states)
+    }
+
+    /// Returns a copy of the state in which the variables created by `other`
+    /// may also be used, so that values taken from `other` can refer to them.
+    /// Variables that are new to this state are unbound in it.
+    pub fn with_vars_of(&self, other: &State<T>) -> State<T> {
+        assert!(self.lineage == other.lineage, "states belong to different lineages");
+        let names = other.names.iter()
+            .filter(|&(index, _)| !self.names.contains_key(&index))
+            .fold(self.names.clone(), |names, (index, name)| names.replace(index, name.clone()));
+        State { names, next_index: self.next_index.max(other.next_index), .. self.clone() }
+    }
+
+    /// Returns a copy of the state that keeps only the bindings reachable
+    /// from the root variables: those of the roots themselves, and of the
+    /// variables in their values, and so on.  The bindings of other variables,
+    /// such as those created for a step of a search that is long finished,
+    /// are forgotten, so they no longer take up memory.
+    ///
+    /// Variables with attributes, or in the arguments of constraints, are
+    /// always kept, as the attributes and constraints may depend on them.
+    /// A variable that is not kept may read as unbound in the compacted state,
+    /// so it should not be used with it.  If a value that is kept cannot tell
+    /// which variables it contains (see `Unif::each_var`), nothing is forgotten.
+    ///
+    /// ```
+    /// use rslogic::goal::{Goal, conj, unify_val, unify_vars};
+    /// use rslogic::state::State;
+    ///
+    /// let s = State::<i32>::empty();
+    /// let (a, s) = s.make_var();
+    /// let (tmp, s) = s.make_var();
+    /// let (b, s) = s.make_var();
+    /// let s = conj(unify_vars(&a, &tmp), conj(unify_val(&tmp, 1), unify_val(&b, 2))).eval(&s).remove(0);
+    ///
+    /// let c = s.compact(&[a]);
+    /// assert_eq!(c.get(&a), Some(&1));
+    /// assert_eq!(c.get(&b), None);
+    /// ```
+    pub fn compact(&self, roots: &[Var]) -> State<T> {
+        let mut reached = vec![false; self.next_index];
+        let mut pending: Vec<usize> = Vec::new();
+        let mut reach = |var: &Var| pending.push(var.index);
+        for var in roots {
+            self.assert_var(var);
+            reach(var);
+        }
+        for constraint in chr::constraints(self) {
+            constraint.args.iter().for_each(&mut reach);
+        }
+        for index in 0..self.next_index {
+            if self.slot(index).is_some_and(|slot| self.attrs.contains_key(&slot)) {
+                pending.push(index);
+            }
+        }
+
+        // the kept variables are bound straight to their slots, so the
+        // variables they were linked through can be forgotten
+        let mut slots = vec![false; self.next_index];
+        let mut bindings = self.bindings.clone();
+        while let Some(index) = pending.pop() {
+            if reached[index] {
+                continue;
+            }
+            reached[index] = true;
+            let slot = match self.slot(index) {
+                Some(slot) => slot,
+                None => continue,
+            };
+            slots[slot] = true;
+            if bindings.get(&index) != Some(&slot) {
+                bindings = bindings.replace(index, slot);
+            }
+            if let Some(val) = self.slots.get(&slot) {
+                if !val.each_var(&mut |var| pending.push(var.index)) {
+                    return self.clone();
                 }
             }
         }
+
+        let mut state = self.clone();
+        for index in 0..self.next_index {
+            if !reached[index] && !slots[index] {
+                bindings = bindings.remove(&index);
+            }
+            if !slots[index] {
+                state.slots = state.slots.remove(&index);
+                state.attrs = state.attrs.remove(&index);
+            }
+            if !reached[index] {
+                state.names = state.names.remove(&index);
+            }
+        }
+        State { bindings, .. state }
+    }
+
+    /// Returns a value that displays the state's bindings like its `Display`
+    /// implementation, but calls the variables by the given names rather
+    /// than the names they were created with.
+    ///
+    /// ```
+    /// use rslogic::state::State;
+    ///
+    /// let s = State::<i32>::empty();
+    /// let (a, s) = s.make_var();
+    /// let (b, s) = s.make_var();
+    /// let s = &s.unify_var(&a, &b)[0];
+    /// assert_eq!(s.to_string(), "_1 = _0");
+    /// let s = &s.unify_val(&b, 3)[0];
+    /// assert_eq!(s.named(&[(a, "A")]).to_string(), "A = 3, _1 = 3");
+    /// ```
+    pub fn named<'a>(&'a self, names: &'a [(Var, &'a str)]) -> Named<'a, T> {
+        Named { state: self, names }
+    }
+
+    /// Returns each variable that is bound or has been unified with another,
+    /// with its value or the first variable it was unified with.
+    fn bound(&self) -> Vec<(Var, Binding<&T>)> {
+        let mut first = vec![None; self.next_index];
+        let mut bound = Vec::new();
+        for index in 0..self.next_index {
+            let slot = match self.slot(index) {
+                Some(slot) => slot,
+                None => continue,
+            };
+            let var = Var { index, lineage: self.lineage };
+            match self.slots.get(&slot) {
+                Some(val) => bound.push((var, Binding::Value(val))),
+                None => {
+                    let other = *first[slot].get_or_insert(index);
+                    if other != index {
+                        bound.push((var, Binding::Var(Var { index: other, lineage: self.lineage })));
+                    }
+                },
+            }
+        }
+        bound
+    }
+
+    /// Writes each variable that is bound or has been unified with another,
+    /// with its value or the first variable it was unified with.
+    fn fmt_bindings<F>(&self, f: &mut fmt::Formatter, names: &[(Var, &str)], fmt_val: F) -> fmt::Result
+        where F: Fn(&T, &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = |f: &mut fmt::Formatter, var: &Var| {
+            match names.iter().find(|named| named.0 == *var) {
+                Some(named) => write!(f, "{}", named.1),
+                None => match self.names.get(&var.index) {
+                    Some(name) => write!(f, "{}", name),
+                    None => write!(f, "{}", var),
+                },
+            }
+        };
+        for (i, (var, binding)) in self.bound().into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            name(f, &var)?;
+            write!(f, " = ")?;
+            match binding {
+                Binding::Value(val) => fmt_val(val, f)?,
+                Binding::Var(other) => name(f, &other)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the bindings of the state, with their values reified.
+    fn reified(&self) -> Vec<(Var, Binding<T>)> where T: Reify<T> {
+        self.bound().into_iter().map(|(var, binding)| (var, match binding {
+            Binding::Value(val) => Binding::Value(val.reify(self)),
+            Binding::Var(other) => Binding::Var(other),
+        })).collect()
+    }
+
+    /// Returns the bindings of the state, with their values reified, and the
+    /// variables that are left unbound renamed in order of their first
+    /// appearance, so that states with the same answers have the same form
+    /// whatever lineage they belong to.
+    fn canonical(&self) -> Vec<(usize, Binding<T>)> where T: Reify<T> + Rename<T> {
+        let mut renamer = Renamer::new(self);
+        self.reified().into_iter().map(|(var, binding)| (var.index, match binding {
+            Binding::Value(val) => Binding::Value(val.rename(&mut |var| renamer.canon(var))),
+            Binding::Var(other) => Binding::Var(renamer.canon(&other)),
+        })).collect()
+    }
+
+    /// Returns the values of the variables, reified, with the variables that
+    /// are left unbound renamed in order of their first appearance.  Answers
+    /// with the same values for the variables, but different variables left
+    /// unbound in them, have the same form.
+    ///
+    /// ```
+    /// use rslogic::goal::{Goal, disj, unify_val};
+    /// use rslogic::state::State;
+    /// use rslogic::term::Term;
+    ///
+    /// let s = State::<Term>::empty();
+    /// let (a, s) = s.make_var();
+    /// let (x, s) = s.make_var();
+    /// let (y, s) = s.make_var();
+    /// let answers = disj(unify_val(&a, Term::Var(x)), unify_val(&a, Term::Var(y))).eval(&s);
+    /// assert!(answers[0].form(&[a]) == answers[1].form(&[a]));
+    /// assert!(answers[0].form(&[a, x]) != answers[1].form(&[a, x]));
+    /// ```
+    pub fn form(&self, vars: &[Var]) -> Vec<Binding<T>> where T: Reify<T> + Rename<T> {
+        self.form_counted(vars).0
+    }
+
+    /// Returns the form of the variables, and the number of canonical
+    /// variables in it, which are numbered from zero.
+    fn form_counted(&self, vars: &[Var]) -> (Vec<Binding<T>>, usize) where T: Reify<T> + Rename<T> {
+        let mut renamer = Renamer::new(self);
+        let form = vars.iter().map(|var| match self.get(var) {
+            Some(val) => Binding::Value(val.reify(self).rename(&mut |var| renamer.canon(var))),
+            None => Binding::Var(renamer.canon(var)),
+        }).collect();
+        (form, renamer.classes.len())
+    }
+
+    /// Returns `true` if the values of the variables in this state are at
+    /// least as general as in `other`, which must belong to the same lineage:
+    /// that is, if binding the variables left unbound in this state's values
+    /// could make them the same as `other`'s.
+    ///
+    /// ```
+    /// use rslogic::goal::{Goal, unify_val};
+    /// use rslogic::state::State;
+    /// use rslogic::term::Term;
+    ///
+    /// let s = State::<Term>::empty();
+    /// let (a, s) = s.make_var();
+    /// let (x, s) = s.make_var();
+    /// let general = &unify_val(&a, Term::compound("f", vec![Term::Var(x)])).eval(&s)[0];
+    /// let specific = &unify_val(&a, Term::compound("f", vec![Term::Int(1)])).eval(&s)[0];
+    /// assert!(general.subsumes(specific, &[a]));
+    /// assert!(!specific.subsumes(general, &[a]));
+    /// ```
+    pub fn subsumes(&self, other: &State<T>, vars: &[Var]) -> bool where T: Reify<T> + Rename<T> {
+        assert!(self.lineage == other.lineage, "states belong to different lineages");
+        let (general, count) = self.form_counted(vars);
+        let specific = other.form(vars);
+
+        // the variables left unbound in this state's values become fresh
+        // variables in `other`, which may be bound to parts of its values
+        let mut state = other.clone();
+        let mut fresh = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (var, next) = state.make_var();
+            fresh.push(var);
+            state = next;
+        }
+
+        let mut states = vec![state];
+        for (var, binding) in vars.iter().zip(general) {
+            states = states.iter().flat_map(|state| match binding {
+                Binding::Value(ref val) => state.unify_val(var, val.rename(&mut |canon| fresh[canon.index])),
+                Binding::Var(ref canon) => state.unify_var(var, &fresh[canon.index]),
+            }).collect();
+        }
+        // binding any of `other`'s own variables would change its form
+        states.iter().any(|state| state.form(vars) == specific)
+    }
+
+    /// Returns the changes to the bindings of the variables from this state
+    /// to `other`, which must belong to the same lineage, in order of the
+    /// variables.  Values are compared once reified.
+    ///
+    /// ```
+    /// use rslogic::state::{Binding, State};
+    ///
+    /// let s = State::<i32>::empty();
+    /// let (a, s) = s.make_var();
+    /// let (b, s) = s.make_var();
+    /// let t = &s.unify_val(&a, 1)[0];
+    /// let u = &t.unify_var(&a, &b)[0];
+    ///
+    /// let changes = t.diff(u);
+    /// assert_eq!(changes.len(), 1);
+    /// assert!(changes[0].var == b && changes[0].old.is_none() && changes[0].new == Some(Binding::Value(1)));
+    /// assert_eq!(changes[0].to_string(), "_1 = 1 (added)");
+    /// assert!(u.diff(u).is_empty());
+    /// ```
+    pub fn diff(&self, other: &State<T>) -> Vec<Change<T>> where T: Reify<T> {
+        assert!(self.lineage == other.lineage, "states belong to different lineages");
+        let mut old = self.reified().into_iter().peekable();
+        let mut new = other.reified().into_iter().peekable();
+        let mut changes = Vec::new();
+        loop {
+            let change = match (old.peek(), new.peek()) {
+                (Some(a), Some(b)) if a.0 == b.0 => {
+                    let (a, b) = (old.next().unwrap(), new.next().unwrap());
+                    if a.1 == b.1 {
+                        continue;
+                    }
+                    Change { var: a.0, old: Some(a.1), new: Some(b.1) }
+                },
+                (Some(a), Some(b)) if a.0 > b.0 => new.next().map(|(var, b)| Change { var, old: None, new: Some(b) }).unwrap(),
+                (Some(_), _) => old.next().map(|(var, a)| Change { var, old: Some(a), new: None }).unwrap(),
+                (None, Some(_)) => new.next().map(|(var, b)| Change { var, old: None, new: Some(b) }).unwrap(),
+                (None, None) => break,
+            };
+            changes.push(change);
+        }
+        changes
+    }
+
+    /// Unifies the bindings of `other`, which must belong to the same
+    /// lineage, with those of this state, returning the states in which both
+    /// sets of bindings hold.  There are none if they conflict.  This combines
+    /// partial solutions that were found independently from a common state.
+    ///
+    /// Only bindings are merged; the attributes and constraints are those of
+    /// this state.
+    ///
+    /// ```
+    /// use rslogic::state::State;
+    ///
+    /// let s = State::<i32>::empty();
+    /// let (a, s) = s.make_var();
+    /// let (b, s) = s.make_var();
+    /// let left = &s.unify_val(&a, 1)[0];
+    /// let right = &s.unify_val(&b, 2)[0];
+    ///
+    /// let merged = left.merge(right);
+    /// assert_eq!(merged.len(), 1);
+    /// assert_eq!(merged[0].to_string(), "_0 = 1, _1 = 2");
+    /// assert!(left.merge(&s.unify_val(&a, 3)[0]).is_empty());
+    /// ```
+    pub fn merge(&self, other: &State<T>) -> PossibleStates<T> where T: Clone {
+        let mut states = vec![self.with_vars_of(other)];
+        for (var, binding) in other.bound() {
+            states = states.iter().flat_map(|state| match binding {
+                Binding::Value(val) => state.unify_val(&var, val.clone()),
+                Binding::Var(ref other) => state.unify_var(&var, other),
+            }).collect();
+            if states.is_empty() {
+                break;
+            }
+        }
+        states
     }
 
     /// Creates a new variable and a new state with which it is usable.
     pub fn make_var(&self) -> (Var, State<T>) {
-        let var = Var { index: self.next_index };
-        let state = State {
-            bindings: self.bindings.clone(),
-            slots: self.slots.clone(),
-            next_index: self.next_index + 1
-        };
+        let var = Var { index: self.next_index, lineage: self.lineage };
+        let state = State { next_index: self.next_index + 1, .. self.derive(self.bindings.clone(), self.slots.clone()) };
         (var, state)
     }
+
+    /// Creates a new variable with a name, and a new state with which it is
+    /// usable.  The name is used when displaying the state, and as the key of
+    /// the variable's value in an `answer`.  Names should be unique.
+    pub fn make_named_var(&self, name: &str) -> (Var, State<T>) {
+        let (var, state) = self.make_var();
+        let names = state.names.replace(var.index, name.to_string());
+        (var, State { names, .. state })
+    }
+
+    /// Returns the name of the variable, if it was created with one.
+    pub fn var_name(&self, var: &Var) -> Option<&str> {
+        self.assert_var(var);
+        self.names.get(&var.index).map(String::as_str)
+    }
+
+    /// Returns the variable with the name, if there is one.
+    pub fn var_named(&self, name: &str) -> Option<Var> {
+        (0..self.next_index)
+            .find(|index| self.names.get(index).is_some_and(|named| named == name))
+            .map(|index| Var { index, lineage: self.lineage })
+    }
+
+    /// Returns the values of the named variables that are bound in the state,
+    /// by name, reified so that the variables they contain are replaced by
+    /// their values.
+    ///
+    /// ```
+    /// use rslogic::goal::{Goal, conj, unify_val, unify_vars};
+    /// use rslogic::state::State;
+    ///
+    /// let s = State::<i32>::empty();
+    /// let (x, s) = s.make_named_var("X");
+    /// let (y, s) = s.make_named_var("Y");
+    /// let (z, s) = s.make_var();
+    /// let (_, s) = s.make_named_var("W");
+    ///
+    /// let results = conj(unify_vars(&x, &z), conj(unify_val(&z, 1), unify_val(&y, 2))).eval(&s);
+    /// let answer = results[0].answer();
+    /// assert_eq!(answer.len(), 2);
+    /// assert_eq!(answer["X"], 1);
+    /// assert_eq!(answer["Y"], 2);
+    /// ```
+    pub fn answer(&self) -> BTreeMap<String, T> where T: Reify<T> {
+        (0..self.next_index)
+            .filter_map(|index| {
+                let name = self.names.get(&index)?;
+                let val = self.slots.get(&self.slot(index)?)?;
+                Some((name.clone(), val.reify(self)))
+            })
+            .collect()
+    }
+}
+
+/// Renames the variables left unbound in a state's values to canonical
+/// variables, numbered in order of their first appearance.  Variables that
+/// have been unified share a slot, and are renamed alike.
+struct Renamer<'a, T> where T: PartialEq + Unif<T> + 'a {
+    state: &'a State<T>,
+    classes: BTreeMap<usize, usize>, // slot -> canonical index
+}
+
+impl<'a, T> Renamer<'a, T> where T: PartialEq + Unif<T> {
+    fn new(state: &'a State<T>) -> Renamer<'a, T> {
+        Renamer { state, classes: BTreeMap::new() }
+    }
+
+    fn canon(&mut self, var: &Var) -> Var {
+        let class = self.state.slot(var.index).unwrap_or(var.index);
+        let count = self.classes.len();
+        Var { index: *self.classes.entry(class).or_insert(count), lineage: CANONICAL_LINEAGE }
+    }
+}
+
+/// What a variable is bound to in a state: a value, or another variable with
+/// which it has been unified while both were unbound.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Binding<T> {
+    Value(T),
+    Var(Var),
+}
+
+impl<T> fmt::Display for Binding<T> where T: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Value(val) => fmt::Display::fmt(val, f),
+            Binding::Var(var) => fmt::Display::fmt(var, f),
+        }
+    }
+}
+
+/// A change to the binding of a variable between two states; see `State::diff`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Change<T> {
+    pub var: Var,
+    /// The binding in the first state, if the variable was bound in it.
+    pub old: Option<Binding<T>>,
+    /// The binding in the second state, if the variable is bound in it.
+    pub new: Option<Binding<T>>,
+}
+
+impl<T> fmt::Display for Change<T> where T: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, "{} = {} (was {})", self.var, new, old),
+            (None, Some(new)) => write!(f, "{} = {} (added)", self.var, new),
+            (Some(old), None) => write!(f, "{} = {} (removed)", self.var, old),
+            (None, None) => write!(f, "{} unchanged", self.var),
+        }
+    }
+}
+
+/// Displays a state with names for some of its variables; see `State::named`.
+pub struct Named<'a, T> where T: PartialEq + Unif<T> + 'a {
+    state: &'a State<T>,
+    names: &'a [(Var, &'a str)],
+}
+
+impl<'a, T> fmt::Display for Named<'a, T> where T: PartialEq + Unif<T> + fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.state.fmt_bindings(f, self.names, |val, f| write!(f, "{}", val))
+    }
+}
+
+/// Displays the value of each variable that is bound or has been unified
+/// with another, as in `_0 = 123, _1 = _0`.  A variable that has only been
+/// unified with others is shown equal to the first of them.
+impl<T> fmt::Display for State<T> where T: PartialEq + Unif<T> + fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.named(&[]), f)
+    }
+}
+
+impl<T> fmt::Debug for State<T> where T: PartialEq + Unif<T> + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "State(")?;
+        self.fmt_bindings(f, &[], |val, f| write!(f, "{:?}", val))?;
+        write!(f, ")")
+    }
+}
+
+/// States are equal if their variables have the same values, once reified,
+/// and the same variables are unified, regardless of which variables are
+/// left unbound in the values.  Variables are compared by the order in which
+/// they were created, so states of different lineages can be equal, and the
+/// variables that are neither bound nor unified with others, like those
+/// forgotten by `compact`, do not matter.  Attributes, constraints and names
+/// are not compared.
+///
+/// ```
+/// use rslogic::goal::{Goal, unify_val};
+/// use rslogic::state::State;
+/// use rslogic::term::Term;
+///
+/// let answer = || {
+///     let s = State::<Term>::empty();
+///     let (a, s) = s.make_var();
+///     let (b, s) = s.make_var();
+///     unify_val(&a, Term::compound("f", vec![Term::Var(b)])).eval(&s).remove(0)
+/// };
+/// assert!(answer() == answer());
+/// ```
+impl<T> PartialEq for State<T> where T: PartialEq + Unif<T> + Reify<T> + Rename<T> {
+    fn eq(&self, other: &State<T>) -> bool {
+        (self.lineage == other.lineage && self.same_bindings(other)) || self.canonical() == other.canonical()
+    }
+}
+
+impl<T> Eq for State<T> where T: Eq + Unif<T> + Reify<T> + Rename<T> {}
+
+impl<T> Hash for State<T> where T: PartialEq + Unif<T> + Reify<T> + Rename<T> + Hash {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical().hash(state);
+    }
+}
+
+/// Unwraps the result of an operation whose errors are bugs in the caller.
+pub(crate) fn expect<R>(result: Result<R, Error>) -> R {
+    result.unwrap_or_else(|error| panic!("{}", error))
 }
 
 impl<T> Clone for State<T> where T : PartialEq + Unif<T> {
     fn clone(&self) -> State<T> {
-        State {
-            bindings: self.bindings.clone(),
-            slots: self.slots.clone(),
-            next_index: self.next_index
-        }
+        self.derive(self.bindings.clone(), self.slots.clone())
     }
 
     fn clone_from(&mut self, source: &State<T>) {
+        if let Some(ref stats) = source.stats {
+            stats.record_state();
+        }
         self.bindings = source.bindings.clone();
         self.slots = source.slots.clone();
+        self.attrs = source.attrs.clone();
+        self.constraints = source.constraints.clone();
+        self.names = source.names.clone();
         self.next_index = source.next_index;
+        self.lineage = source.lineage;
+        self.stats = source.stats.clone();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use attr::{Attribute, Goals};
+    use error::Error;
+    use goal::{Goal, conj, unify_val, unify_vars};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use term::Term;
+    use super::{Backing, Binding, PossibleStates, Reify, Rename, State, Unif, Var};
+
+    #[test]
+    #[should_panic(expected = "different state lineage")]
+    fn test_foreign_var() {
+        let (a, _) = State::<i32>::empty().make_var();
+        let (_, s) = State::<i32>::empty().make_var();
+        s.unify_val(&a, 1);
+    }
+
+    #[test]
+    fn test_lineage() {
+        let s = State::<i32>::empty();
+        let (a, s1) = s.make_var();
+        let (b, s2) = s.make_var();
+        // states derived from the same state share its lineage
+        assert_eq!(a, b);
+        assert_eq!(s1.unify_val(&a, 1).len(), 1);
+        assert_eq!(s2.unify_val(&b, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_unify_unbound_slots() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let (c, s) = s.make_var();
+        let (d, s) = s.make_var();
+
+        // a bound variable and a variable unified with another unbound one
+        let g = conj(conj(unify_vars(&b, &c), unify_val(&a, 1)), unify_vars(&a, &b));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get(&c), Some(&1));
+
+        // two pairs of unified, unbound variables
+        let g = conj(conj(unify_vars(&a, &b), unify_vars(&c, &d)), conj(unify_vars(&b, &c), unify_val(&d, 2)));
+        let results = g.eval(&s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get(&a), Some(&2));
+        assert_eq!(unify_vars(&a, &a).eval(&s).len(), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (a, s) = State::<i32>::empty().make_var();
+        let (b, _) = s.make_var();
+        let (c, _) = State::<i32>::empty().make_var();
+
+        assert_eq!(s.try_get(&a), Ok(None));
+        assert_eq!(s.try_unify_val(&b, 1).err(), Some(Error::UnknownVar(b)));
+        assert_eq!(s.try_unify_var(&a, &c).err(), Some(Error::ForeignVar(c)));
+        assert_eq!(conj(unify_val(&a, 1), unify_vars(&a, &c)).try_eval(&s).err(), Some(Error::ForeignVar(c)));
+        // logical failure is not an error
+        assert_eq!(conj(unify_val(&a, 1), unify_val(&a, 2)).try_eval(&s).map(|r| r.len()), Ok(0));
+    }
+
+    #[test]
+    fn test_display() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let (c, s) = s.make_var();
+        let (d, s) = s.make_var();
+        let g = conj(conj(unify_vars(&b, &d), unify_vars(&d, &c)), unify_val(&a, 123));
+        let s = &g.eval(&s)[0];
+        assert_eq!(s.to_string(), "_0 = 123, _2 = _1, _3 = _1");
+        assert_eq!(s.named(&[(a, "A"), (b, "B")]).to_string(), "A = 123, _2 = B, _3 = B");
+        assert_eq!(format!("{:?}", s.unify_val(&c, 4)[0]), "State(_0 = 123, _1 = 4, _2 = 4, _3 = 4)");
+        assert_eq!(format!("{:?}", State::<i32>::empty()), "State()");
+    }
+
+    #[test]
+    fn test_named_vars() {
+        let s = State::<i32>::empty();
+        let (x, s) = s.make_named_var("X");
+        let (y, s) = s.make_var();
+        assert_eq!(s.var_name(&x), Some("X"));
+        assert_eq!(s.var_name(&y), None);
+        assert_eq!(s.var_named("X"), Some(x));
+        assert_eq!(s.var_named("Y"), None);
+
+        let s = &conj(unify_vars(&y, &x), unify_val(&y, 5)).eval(&s)[0];
+        assert_eq!(s.to_string(), "X = 5, _1 = 5");
+        assert_eq!(s.answer().into_iter().collect::<Vec<_>>(), vec![("X".to_string(), 5)]);
+    }
+
+    #[test]
+    fn test_backing() {
+        for &backing in [Backing::Radix, Backing::Tree].iter() {
+            let s = State::<i32>::empty_in(backing);
+            let (x, s) = s.make_named_var("X");
+            let (y, s) = s.make_var();
+            let (z, s) = s.make_var();
+            let s = &conj(unify_vars(&y, &x), conj(unify_val(&y, 5), unify_val(&z, 6))).eval(&s)[0];
+            assert_eq!(s.to_string(), "X = 5, _1 = 5, _2 = 6");
+
+            let c = s.compact(&[x]);
+            assert_eq!(c.get(&x), Some(&5));
+            assert_eq!(c.get(&z), None);
+            for state in [s, &c].iter() {
+                assert_eq!(state.bindings.backing(), backing);
+                assert_eq!(state.slots.backing(), backing);
+                assert_eq!(state.attrs.backing(), backing);
+                assert_eq!(state.names.backing(), backing);
+            }
+        }
+    }
+
+    #[test]
+    fn test_many_named_vars() {
+        struct Mark;
+        impl Attribute<i32> for Mark {
+            fn verify_attributes(&self, _: &Var, _: &i32, _: &State<i32>) -> Option<Goals<i32>> {
+                Some(Vec::new())
+            }
+        }
+
+        // names and attributes are added in order of their variables' indices
+        let s = (0..100_000).fold(State::<i32>::empty(), |s, i| {
+            let (v, s) = s.make_named_var(&format!("V{}", i));
+            s.put_attr(&v, Mark)
+        });
+        let v = s.var_named("V99999").unwrap();
+        assert_eq!(s.var_name(&v), Some("V99999"));
+        assert!(s.get_attr::<Mark>(&v).is_some());
+    }
+
+    #[test]
+    fn test_compact() {
+        struct Mark;
+        impl Attribute<Term> for Mark {
+            fn verify_attributes(&self, _: &Var, _: &Term, _: &State<Term>) -> Option<Goals<Term>> {
+                Some(Vec::new())
+            }
+        }
+
+        let s = State::<Term>::empty();
+        let (x, s) = s.make_named_var("X");
+        let (y, s) = s.make_var();
+        let (z, s) = s.make_var();
+        let (w, s) = s.make_named_var("W");
+        let (u, s) = s.make_var();
+        let (m, s) = s.make_var();
+        let s = s.put_attr(&m, Mark);
+        let g = conj(conj(unify_vars(&z, &y), unify_val(&x, Term::compound("f", vec![Term::Var(y)]))),
+                     conj(unify_val(&w, Term::Int(1)), unify_val(&u, Term::Int(2))));
+        let s = &g.eval(&s)[0];
+
+        let c = s.compact(&[x]);
+        assert_eq!(c.get(&x), s.get(&x));
+        assert_eq!(c.get(&w), None);
+        assert_eq!(c.get(&u), None);
+        assert_eq!(c.var_named("W"), None);
+        assert_eq!(c.var_named("X"), Some(x));
+        assert!(c.get_attr::<Mark>(&m).is_some());
+
+        // the variable reached through the value can still be bound
+        let c = &c.unify_val(&y, Term::Int(3))[0];
+        assert_eq!(c.answer()["X"].to_string(), "f(3)");
+        assert_eq!(c.compact(&[]).to_string(), "");
+    }
+
+    #[test]
+    fn test_compact_unknown_vars() {
+        // values that cannot tell which variables they contain
+        #[derive(Clone, PartialEq, Eq, Debug)]
+        struct Opaque(i32);
+        impl Unif<Opaque> for Opaque {
+            fn unify(&self, other: &Opaque, prev: &State<Opaque>) -> PossibleStates<Opaque> {
+                if self == other { vec![prev.clone()] } else { Vec::new() }
+            }
+        }
+
+        let s = State::<Opaque>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let s = &conj(unify_val(&a, Opaque(1)), unify_val(&b, Opaque(2))).eval(&s)[0];
+        let c = s.compact(&[a]);
+        assert_eq!(c.get(&a), Some(&Opaque(1)));
+        assert_eq!(c.get(&b), Some(&Opaque(2)));
+    }
+
+    #[test]
+    fn test_subsumes() {
+        let s = State::<Term>::empty();
+        let (a, s) = s.make_var();
+        let (x, s) = s.make_var();
+        let (y, s) = s.make_var();
+        let f = |args| Term::compound("f", args);
+        let state = |val| unify_val(&a, val).eval(&s).remove(0);
+
+        // the same variable appears twice in the compound value
+        let general = state(f(vec![Term::Var(x), Term::Var(x), Term::Var(y)]));
+        assert!(general.subsumes(&state(f(vec![Term::Int(1), Term::Int(1), Term::Int(2)])), &[a]));
+        assert!(general.subsumes(&state(f(vec![Term::Var(y), Term::Var(y), Term::Var(x)])), &[a]));
+        assert!(!general.subsumes(&state(f(vec![Term::Int(1), Term::Int(2), Term::Int(2)])), &[a]));
+        assert!(!general.subsumes(&state(Term::Int(1)), &[a]));
+        assert!(!state(f(vec![Term::Int(1), Term::Int(1), Term::Var(y)])).subsumes(&general, &[a]));
+    }
+
+    #[test]
+    fn test_subsumes_unknown_vars() {
+        // values that contain variables but cannot tell which
+        #[derive(Clone, PartialEq, Eq, Debug)]
+        enum Val { Var(Var), Int(i32) }
+        impl Unif<Val> for Val {
+            fn unify(&self, other: &Val, prev: &State<Val>) -> PossibleStates<Val> {
+                match (self, other) {
+                    (Val::Var(a), Val::Var(b)) => prev.unify_var(a, b),
+                    (Val::Var(a), val) | (val, Val::Var(a)) => prev.unify_val(a, val.clone()),
+                    (a, b) => if a == b { vec![prev.clone()] } else { Vec::new() },
+                }
+            }
+        }
+        impl Reify<Val> for Val {
+            fn reify(&self, state: &State<Val>) -> Val {
+                match self {
+                    Val::Var(var) => state.get(var).map_or(Val::Var(*var), |val| val.reify(state)),
+                    val => val.clone(),
+                }
+            }
+        }
+        impl Rename<Val> for Val {
+            fn rename(&self, f: &mut dyn FnMut(&Var) -> Var) -> Val {
+                match self {
+                    Val::Var(var) => Val::Var(f(var)),
+                    val => val.clone(),
+                }
+            }
+        }
+
+        let s = State::<Val>::empty();
+        let (a, s) = s.make_var();
+        let (x, s) = s.make_var();
+        let general = &unify_val(&a, Val::Var(x)).eval(&s)[0];
+        let specific = &unify_val(&a, Val::Int(1)).eval(&s)[0];
+        assert!(general.subsumes(specific, &[a]));
+        assert!(!specific.subsumes(general, &[a]));
+    }
+
+    #[test]
+    fn test_eq_hash() {
+        // f(X, Y) with Y unified with a third variable, built in two orders
+        let answer = |swap: bool| {
+            let s = State::<Term>::empty();
+            let (a, s) = s.make_var();
+            let (x, s) = s.make_var();
+            let (y, s) = s.make_var();
+            let (z, s) = s.make_var();
+            let (tmp, s) = s.make_var();
+            let f = Term::compound("f", vec![Term::Var(if swap { y } else { x }), Term::Var(if swap { x } else { y })]);
+            let g = conj(conj(unify_val(&tmp, Term::Int(0)), unify_vars(&y, &z)), unify_val(&a, f));
+            (g.eval(&s).remove(0), a, tmp)
+        };
+        let (s1, a, tmp) = answer(false);
+        let (s2, _, _) = answer(true);
+        assert!(s1 == s1.clone() && s1 == answer(false).0);
+        assert!(s1 != s2);
+        let (s3, a3, _) = answer(false);
+        assert_eq!(s1.compact(&[a]), s3.compact(&[a3]));
+        assert!(s1.compact(&[a]) != s1 && s1.compact(&[a]) != s1.compact(&[a, tmp]));
+
+        let hash = |s: &State<Term>| {
+            let mut hasher = DefaultHasher::new();
+            s.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&s1), hash(&answer(false).0));
+        assert!(hash(&s1) != hash(&s2));
+    }
+
+    #[test]
+    fn test_diff_merge() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let (c, s) = s.make_var();
+        let t = &conj(unify_val(&a, 1), unify_vars(&b, &c)).eval(&s)[0];
+        let u = &t.unify_val(&c, 2)[0];
+        assert_eq!(t.diff(u).iter().map(|change| change.to_string()).collect::<Vec<_>>(),
+                   vec!["_1 = 2 (added)", "_2 = 2 (was _1)"]);
+        assert_eq!(u.diff(&s).iter().map(|change| change.var).collect::<Vec<_>>(), vec![a, b, c]);
+        assert_eq!(u.diff(&s)[0].new, None);
+        assert_eq!(u.diff(&s)[0].old, Some(Binding::Value(1)));
+
+        let v = &s.unify_val(&b, 2)[0];
+        let merged = v.merge(t);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].to_string(), "_0 = 1, _1 = 2, _2 = 2");
+        assert!(merged[0] == *u && u.merge(v)[0] == *u);
+        assert!(s.unify_val(&c, 3)[0].merge(v).len() == 1 && s.unify_val(&c, 3)[0].merge(u).is_empty());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "created after the state")]
+    fn test_var_after_state() {
+        let s = State::<i32>::empty();
+        let (a, _) = s.make_var();
+        s.get(&a);
     }
 }
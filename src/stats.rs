@@ -0,0 +1,166 @@
+//! # Search Statistics
+//!
+//! A `Stats` collector counts the work done while evaluating a goal.  It is
+//! opt-in: attach one to a state with `State::with_stats`, and every state
+//! derived from that one (by unification, creating variables, or cloning)
+//! records into the same collector.  The counters are atomic, so states on
+//! different threads may share a collector.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use rslogic::state::State;
+//! use rslogic::goal::{Goal, conj, disj, unify_val};
+//! use rslogic::stats::Stats;
+//!
+//! let stats = Arc::new(Stats::new());
+//! let s = State::<i32>::empty().with_stats(stats.clone());
+//! let (a, s) = s.make_var();
+//! let g = conj(disj(unify_val(&a, 1), unify_val(&a, 2)), unify_val(&a, 2));
+//!
+//! let results = stats.time(|| g.eval(&s));
+//! assert_eq!(results.len(), 1);
+//! assert_eq!(stats.unifications(), 4);
+//! assert_eq!(stats.failures(), 1);
+//! assert_eq!(stats.branches(), 2);
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counters for the work done while evaluating goals.
+#[derive(Debug, Default)]
+pub struct Stats {
+    unifications: AtomicU64,
+    successes: AtomicU64,
+    failures: AtomicU64,
+    states: AtomicU64,
+    branches: AtomicU64,
+    max_depth: AtomicUsize,
+    elapsed_nanos: AtomicU64,
+}
+
+impl Stats {
+    /// Creates a collector with all its counters at zero.
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    /// The number of times a variable was unified with a value or another variable.
+    pub fn unifications(&self) -> u64 {
+        self.unifications.load(Ordering::Relaxed)
+    }
+
+    /// The number of unifications that produced at least one state.
+    pub fn successes(&self) -> u64 {
+        self.successes.load(Ordering::Relaxed)
+    }
+
+    /// The number of unifications that produced no states.
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// The number of states created.
+    pub fn states(&self) -> u64 {
+        self.states.load(Ordering::Relaxed)
+    }
+
+    /// The number of alternative branches explored at disjunctions and other choice points.
+    pub fn branches(&self) -> u64 {
+        self.branches.load(Ordering::Relaxed)
+    }
+
+    /// The greatest number of goal expansions on any one path of a `search::Search`.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth.load(Ordering::Relaxed)
+    }
+
+    /// The total wall time spent inside `time`, including the time spent by
+    /// searches looking for answers.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Calls `f`, adding the time it takes to the elapsed time.
+    pub fn time<F, R>(&self, f: F) -> R where F: FnOnce() -> R {
+        let start = Instant::now();
+        let result = f();
+        let nanos = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.elapsed_nanos.fetch_add(nanos, Ordering::Relaxed);
+        result
+    }
+
+    pub(crate) fn record_unification(&self, succeeded: bool) {
+        self.unifications.fetch_add(1, Ordering::Relaxed);
+        let outcome = if succeeded { &self.successes } else { &self.failures };
+        outcome.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_state(&self) {
+        self.states.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_branches(&self, count: usize) {
+        self.branches.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_depth(&self, depth: usize) {
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} unifications ({} succeeded, {} failed), {} states, {} branches, max depth {}, {:?}",
+               self.unifications(), self.successes(), self.failures(), self.states(),
+               self.branches(), self.max_depth(), self.elapsed())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use state::State;
+    use goal::{Goal, conj, disj, unify_val, unify_vars};
+    use search::Search;
+    use std::sync::Arc;
+    use super::Stats;
+
+    #[test]
+    fn test_unifications() {
+        let stats = Arc::new(Stats::new());
+        let s = State::<i32>::empty().with_stats(stats.clone());
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        assert_eq!(stats.states(), 3);
+
+        let g = conj(unify_vars(&a, &b), conj(unify_val(&a, 1), unify_val(&b, 2)));
+        assert!(g.eval(&s).is_empty());
+        assert_eq!((stats.unifications(), stats.successes(), stats.failures()), (3, 2, 1));
+        assert_eq!(stats.branches(), 0);
+
+        // states derived without a collector record nothing
+        let plain = State::<i32>::empty();
+        let (c, plain) = plain.make_var();
+        let before = stats.states();
+        assert_eq!(unify_val(&c, 1).eval(&plain).len(), 1);
+        assert_eq!(stats.states(), before);
+        assert!(plain.stats().is_none());
+    }
+
+    #[test]
+    fn test_search() {
+        let stats = Arc::new(Stats::new());
+        let s = State::<i32>::empty().with_stats(stats.clone());
+        let (a, s) = s.make_var();
+        let g = disj(unify_val(&a, 1), disj(unify_val(&a, 2), unify_val(&a, 3)));
+
+        assert_eq!(Search::new(&g, &s).count(), 3);
+        assert_eq!(stats.branches(), 4);
+        assert_eq!(stats.max_depth(), 3);
+        assert_eq!(stats.unifications(), 3);
+        assert!(stats.to_string().starts_with("3 unifications (3 succeeded, 0 failed)"));
+    }
+}
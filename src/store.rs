@@ -0,0 +1,466 @@
+//! # Binding Stores
+//!
+//! A `State` is persistent: unifying a variable creates a new state, and the
+//! old one is unchanged, so that any number of alternative states can be
+//! explored at once.  That is what interleaving and breadth-first searches
+//! need, but a depth-first search only ever explores one alternative at a
+//! time, and pays for persistence it does not use.
+//!
+//! A `Store` is a mutable collection of variable bindings that a depth-first
+//! search can explore in place.  Binding a variable changes the store, and
+//! backtracking to a `Mark` undoes the changes made since it was taken.
+//! Goals are proven in a store with `Goal::solve`, which calls a continuation
+//! with the store as it is in each solution.
+//!
+//! There are two kinds of store:
+//!
+//! - `Trail` keeps its bindings in an array, and records the changes made to
+//!   it on a trail so that they can be undone, in the manner of the Warren
+//!   Abstract Machine.  It is the faster store for deep depth-first searches.
+//! - `Persistent` keeps a `State`, and saves the states it replaces.
+//!
+//! Goals that do not know how to use a store are evaluated in a snapshot of
+//! it, so any goal can be proven in either kind of store.
+//!
+//! ```
+//! use rslogic::state::State;
+//! use rslogic::goal::{Goal, conj, disj, unify_val, unify_vars};
+//! use rslogic::store::{Store, Trail};
+//!
+//! let s = State::<i32>::empty();
+//! let (a, s) = s.make_var();
+//! let (b, s) = s.make_var();
+//! let g = conj(unify_vars(&a, &b), disj(unify_val(&a, 1), unify_val(&b, 2)));
+//!
+//! let mut store = Trail::from_state(&s);
+//! let mut found = Vec::new();
+//! g.solve(&mut store, &mut |store: &mut dyn Store<i32>| {
+//!     found.push(*store.get(&b).unwrap());
+//!     false
+//! });
+//! assert_eq!(found, vec![1, 2]);
+//! assert!(store.get(&a).is_none());
+//! ```
+
+use attr::Attrs;
+use chr::ConstraintStore;
+use goal::Goal;
+use state::{IndexMap, StateMap, Unif, Var, State, PossibleStates};
+use std::mem;
+use std::sync::Arc;
+use stats::Stats;
+
+/// A continuation called with the store as it is in a solution.  It returns
+/// `true` to stop looking for more solutions.
+pub type Next<'n, T> = dyn FnMut(&mut dyn Store<T>) -> bool + 'n;
+
+/// A point in the history of a store, to which it can backtrack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mark {
+    trail: usize,
+    vars: usize,
+}
+
+/// A mutable collection of variable bindings, explored depth-first.
+pub trait Store<T> where T: PartialEq + Unif<T> {
+    /// Creates a new variable, which is unbound in the store.
+    fn make_var(&mut self) -> Var;
+
+    /// Returns a reference to the value bound to the variable, or `None` if it is not bound.
+    fn get(&self, var: &Var) -> Option<&T>;
+
+    /// Unifies a variable with a value, calling `next` with the store as it
+    /// is after each way in which they unify, until `next` returns `true` to
+    /// stop.  Returns `true` if it was stopped.  The store is left as it was found.
+    fn unify_val(&mut self, var: &Var, val: T, next: &mut Next<T>) -> bool;
+
+    /// Unifies two variables, in the same way as `unify_val`.
+    fn unify_vars(&mut self, v1: &Var, v2: &Var, next: &mut Next<T>) -> bool;
+
+    /// Returns the current point in the store's history.
+    fn mark(&self) -> Mark;
+
+    /// Undoes all the changes made to the store since the mark was taken.
+    fn undo(&mut self, mark: Mark);
+
+    /// Returns a persistent copy of the store's bindings.
+    fn snapshot(&self) -> State<T>;
+
+    /// Replaces the store's bindings with those of the state, which must have
+    /// been derived from a snapshot of the store.  The change can be undone.
+    fn load(&mut self, state: &State<T>);
+}
+
+/// Calls `next` with the store holding the bindings of each of the states in
+/// turn, until `next` returns `true` to stop.  Returns `true` if it was stopped.
+/// The store is left as it was found.
+pub fn each_state<T>(states: PossibleStates<T>, store: &mut dyn Store<T>, next: &mut Next<T>) -> bool where T: PartialEq + Unif<T> {
+    for state in states {
+        let mark = store.mark();
+        store.load(&state);
+        let stop = next(store);
+        store.undo(mark);
+        if stop {
+            return true;
+        }
+    }
+    false
+}
+
+/// Proves the goal in the store, returning a snapshot of each solution.
+pub fn solve_all<T, G>(goal: &G, store: &mut dyn Store<T>) -> PossibleStates<T> where T: PartialEq + Unif<T>, G: Goal<T> + ?Sized {
+    let mut states = PossibleStates::new();
+    goal.solve(store, &mut |store: &mut dyn Store<T>| {
+        states.push(store.snapshot());
+        false
+    });
+    states
+}
+
+
+/// A store that keeps a persistent `State`, and saves the states it replaces
+/// so that it can backtrack to them.
+pub struct Persistent<T> where T: PartialEq + Unif<T> {
+    state: State<T>,
+    saved: Vec<State<T>>,
+}
+
+impl<T> Persistent<T> where T: PartialEq + Unif<T> {
+    /// Creates a store with the bindings of the state.
+    pub fn new(state: &State<T>) -> Persistent<T> {
+        Persistent { state: state.clone(), saved: Vec::new() }
+    }
+
+    /// Returns the store's current state.
+    pub fn state(&self) -> &State<T> {
+        &self.state
+    }
+
+    fn replace(&mut self, state: State<T>) {
+        let old = mem::replace(&mut self.state, state);
+        self.saved.push(old);
+    }
+}
+
+impl<T> Store<T> for Persistent<T> where T: PartialEq + Unif<T> {
+    fn make_var(&mut self) -> Var {
+        let (var, state) = self.state.make_var();
+        self.replace(state);
+        var
+    }
+
+    fn get(&self, var: &Var) -> Option<&T> {
+        self.state.get(var)
+    }
+
+    fn unify_val(&mut self, var: &Var, val: T, next: &mut Next<T>) -> bool {
+        let states = self.state.unify_val(var, val);
+        each_state(states, self, next)
+    }
+
+    fn unify_vars(&mut self, v1: &Var, v2: &Var, next: &mut Next<T>) -> bool {
+        let states = self.state.unify_var(v1, v2);
+        each_state(states, self, next)
+    }
+
+    fn mark(&self) -> Mark {
+        Mark { trail: self.saved.len(), vars: self.state.next_index() }
+    }
+
+    fn undo(&mut self, mark: Mark) {
+        if mark.trail < self.saved.len() {
+            self.state = self.saved.swap_remove(mark.trail);
+            self.saved.truncate(mark.trail);
+        }
+    }
+
+    fn snapshot(&self) -> State<T> {
+        self.state.clone()
+    }
+
+    fn load(&mut self, state: &State<T>) {
+        self.replace(state.clone());
+    }
+}
+
+
+/// The contents of a variable's cell in a `Trail`.
+#[derive(Clone)]
+enum Cell<T> {
+    Unbound,
+    Bound(T),
+    /// The variable has been unified with the variable in another cell.
+    Ref(usize),
+}
+
+/// A change to a `Trail` that can be undone.
+enum Entry<T> where T: PartialEq + Unif<T> {
+    /// The cell was unbound.
+    Reset(usize),
+    /// The cells, attributes, constraints and names were replaced by loading a state.
+    Restore(Vec<Cell<T>>, StateMap<Attrs<T>>, Arc<ConstraintStore>, StateMap<String>),
+}
+
+/// A store that keeps one cell per variable in an array, and records the
+/// changes made to the cells on a trail so that they can be undone.
+///
+/// Unifications that bind variables with attributes are made in a snapshot
+/// of the store, so that the attributes are verified.
+pub struct Trail<T> where T: PartialEq + Unif<T> {
+    cells: Vec<Cell<T>>,
+    attrs: StateMap<Attrs<T>>, // cell -> attributes
+    constraints: Arc<ConstraintStore>,
+    names: StateMap<String>, // cell -> name of its variable
+    lineage: u64,
+    trail: Vec<Entry<T>>,
+    stats: Option<Arc<Stats>>,
+}
+
+impl<T> Trail<T> where T: PartialEq + Unif<T> + Clone {
+    /// Creates an empty store.
+    pub fn new() -> Trail<T> {
+        Trail::from_state(&State::empty())
+    }
+
+    /// Creates a store with the variables and bindings of the state.  The
+    /// state's statistics collector, if any, is passed on to snapshots.
+    pub fn from_state(state: &State<T>) -> Trail<T> {
+        Trail {
+            cells: Trail::cells_of(state),
+            attrs: state.attrs().clone(),
+            constraints: state.constraints().clone(),
+            names: state.names().clone(),
+            lineage: state.lineage(),
+            trail: Vec::new(),
+            stats: state.stats().cloned(),
+        }
+    }
+
+    fn cells_of(state: &State<T>) -> Vec<Cell<T>> {
+        (0..state.next_index()).map(|index| match state.slot(index) {
+            None => Cell::Unbound,
+            Some(slot) if slot != index => Cell::Ref(slot),
+            Some(slot) => match state.slot_value(slot) {
+                Some(val) => Cell::Bound(val.clone()),
+                None => Cell::Unbound,
+            },
+        }).collect()
+    }
+
+    /// Returns the index of the variable's cell, panicking if the variable
+    /// cannot be used with the store, as `State` does.
+    fn cell(&self, var: &Var) -> usize {
+        assert!(var.lineage() == self.lineage, "variable {} belongs to a different state lineage", var.index());
+        debug_assert!(var.index() < self.cells.len(), "variable {} was created after the store's state", var.index());
+        var.index()
+    }
+
+    /// Returns the index of the last cell in the chain of references from the variable's cell.
+    fn deref(&self, mut index: usize) -> usize {
+        while let Cell::Ref(next) = self.cells[index] {
+            index = next;
+        }
+        index
+    }
+
+    /// Makes the unbound cell `from` refer to the cell `to`, as `unify_vars` does.
+    fn link(&mut self, from: usize, to: usize, v1: &Var, v2: &Var, next: &mut Next<T>) -> bool {
+        let unbound = !matches!(self.cells[to], Cell::Bound(_));
+        if self.attrs.contains_key(&from) || unbound && self.attrs.contains_key(&to) {
+            let states = self.snapshot().unify_var(v1, v2);
+            each_state(states, self, next)
+        } else {
+            self.bind(from, Cell::Ref(to), next)
+        }
+    }
+
+    /// Sets an unbound cell, calls `next`, then unbinds the cell again.
+    fn bind(&mut self, index: usize, cell: Cell<T>, next: &mut Next<T>) -> bool {
+        let mark = self.mark();
+        self.cells[index] = cell;
+        self.trail.push(Entry::Reset(index));
+        let stop = next(self);
+        self.undo(mark);
+        stop
+    }
+}
+
+impl<T> Default for Trail<T> where T: PartialEq + Unif<T> + Clone {
+    fn default() -> Trail<T> {
+        Trail::new()
+    }
+}
+
+impl<T> Store<T> for Trail<T> where T: PartialEq + Unif<T> + Clone {
+    fn make_var(&mut self) -> Var {
+        self.cells.push(Cell::Unbound);
+        Var::new(self.cells.len() - 1, self.lineage)
+    }
+
+    fn get(&self, var: &Var) -> Option<&T> {
+        match self.cells[self.deref(self.cell(var))] {
+            Cell::Bound(ref val) => Some(val),
+            _ => None,
+        }
+    }
+
+    fn unify_val(&mut self, var: &Var, val: T, next: &mut Next<T>) -> bool {
+        let index = self.deref(self.cell(var));
+        match self.cells[index] {
+            Cell::Bound(ref existing) => existing.clone().unify_in(&val, self, next),
+            _ if self.attrs.contains_key(&index) => {
+                let states = self.snapshot().unify_val(var, val);
+                each_state(states, self, next)
+            },
+            _ => self.bind(index, Cell::Bound(val), next),
+        }
+    }
+
+    fn unify_vars(&mut self, v1: &Var, v2: &Var, next: &mut Next<T>) -> bool {
+        let i1 = self.deref(self.cell(v1));
+        let i2 = self.deref(self.cell(v2));
+        if i1 == i2 {
+            return next(self);
+        }
+        match (&self.cells[i1], &self.cells[i2]) {
+            (Cell::Bound(a), Cell::Bound(b)) => {
+                let (a, b) = (a.clone(), b.clone());
+                a.unify_in(&b, self, next)
+            },
+            (Cell::Bound(_), _) => self.link(i2, i1, v1, v2, next),
+            (_, Cell::Bound(_)) => self.link(i1, i2, v1, v2, next),
+            _ if self.attrs.contains_key(&i1) => self.link(i2, i1, v1, v2, next),
+            _ => self.link(i1, i2, v1, v2, next),
+        }
+    }
+
+    fn mark(&self) -> Mark {
+        Mark { trail: self.trail.len(), vars: self.cells.len() }
+    }
+
+    fn undo(&mut self, mark: Mark) {
+        while self.trail.len() > mark.trail {
+            match self.trail.pop() {
+                Some(Entry::Reset(index)) => self.cells[index] = Cell::Unbound,
+                Some(Entry::Restore(cells, attrs, constraints, names)) => {
+                    self.cells = cells;
+                    self.attrs = attrs;
+                    self.constraints = constraints;
+                    self.names = names;
+                },
+                None => break,
+            }
+        }
+        self.cells.truncate(mark.vars);
+    }
+
+    fn snapshot(&self) -> State<T> {
+        let mut bindings = StateMap::empty_in(self.attrs.backing());
+        let mut slots = StateMap::empty_in(self.attrs.backing());
+        for index in 0..self.cells.len() {
+            let root = self.deref(index);
+            if root != index {
+                bindings = bind_slot(bindings, index, root);
+                bindings = bind_slot(bindings, root, root);
+            }
+            if let Cell::Bound(ref val) = self.cells[root] {
+                bindings = bind_slot(bindings, root, root);
+                slots = bind_slot(slots, root, val.clone());
+            }
+            if self.attrs.contains_key(&index) {
+                bindings = bind_slot(bindings, index, index);
+            }
+        }
+        State::from_parts(bindings, slots, self.attrs.clone(), self.constraints.clone(), self.cells.len(), self.lineage, self.stats.clone())
+            .with_names(self.names.clone())
+    }
+
+    fn load(&mut self, state: &State<T>) {
+        let cells = mem::replace(&mut self.cells, Trail::cells_of(state));
+        let attrs = mem::replace(&mut self.attrs, state.attrs().clone());
+        let constraints = mem::replace(&mut self.constraints, state.constraints().clone());
+        let names = mem::replace(&mut self.names, state.names().clone());
+        self.trail.push(Entry::Restore(cells, attrs, constraints, names));
+    }
+}
+
+/// Inserts the item into the map, unless the key is already present.
+fn bind_slot<V, M>(map: M, key: usize, val: V) -> M where M: IndexMap<V> {
+    if map.contains_key(&key) { map } else { map.replace(key, val) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use goal::{Goal, conj, disj, unify_val, unify_vars};
+    use state::State;
+    use term::Term;
+    use super::{Store, Trail, Persistent, solve_all};
+
+    fn answers(states: Vec<State<i32>>, vars: &[::state::Var]) -> Vec<Vec<Option<i32>>> {
+        let mut answers: Vec<Vec<Option<i32>>> = states.iter()
+            .map(|s| vars.iter().map(|v| s.get(v).cloned()).collect())
+            .collect();
+        answers.sort();
+        answers
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let s = State::<i32>::empty();
+        let (a, s) = s.make_var();
+        let (b, s) = s.make_var();
+        let (c, s) = s.make_var();
+        let g = conj(
+            disj(unify_vars(&a, &b), unify_val(&a, 1)),
+            conj(disj(unify_val(&b, 1), unify_val(&b, 2)), disj(unify_vars(&b, &c), unify_val(&c, 3))));
+
+        let vars = [a, b, c];
+        let expected = answers(g.eval(&s), &vars);
+        assert_eq!(expected.len(), 8);
+        assert_eq!(answers(solve_all(&g, &mut Trail::from_state(&s)), &vars), expected);
+        assert_eq!(answers(solve_all(&g, &mut Persistent::new(&s)), &vars), expected);
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut store = Trail::<i32>::new();
+        let a = store.make_var();
+        let b = store.make_var();
+        let mark = store.mark();
+        let stopped = unify_vars(&a, &b).solve(&mut store, &mut |store: &mut dyn Store<i32>| {
+            store.unify_val(&b, 5, &mut |store: &mut dyn Store<i32>| store.get(&a) == Some(&5))
+        });
+        assert!(stopped);
+        assert_eq!(store.mark(), mark);
+        assert!(store.get(&a).is_none() && store.get(&b).is_none());
+
+        // a state loaded into the store is replaced by the original on backtracking
+        let (c, loaded) = store.snapshot().make_named_var("C");
+        store.load(&unify_val(&a, 1).eval(&loaded)[0]);
+        assert_eq!(store.get(&a), Some(&1));
+        assert_eq!(store.snapshot().var_name(&c), Some("C"));
+        store.undo(mark);
+        assert!(store.get(&a).is_none());
+        assert_eq!(store.snapshot().var_named("C"), None);
+        assert_eq!(store.mark(), mark);
+    }
+
+    #[test]
+    fn test_terms() {
+        let s = State::<Term>::empty();
+        let (x, s) = s.make_var();
+        let (y, s) = s.make_var();
+        let g = conj(
+            unify_val(&x, Term::list(vec![Term::Var(y), Term::Int(2)], None)),
+            unify_val(&x, Term::list(vec![Term::Int(1), Term::Var(y)], None)));
+        assert!(solve_all(&g, &mut Trail::from_state(&s)).is_empty());
+
+        let g = conj(
+            unify_val(&x, Term::list(vec![Term::Var(y), Term::Int(2)], None)),
+            unify_val(&x, Term::list(vec![Term::Int(1), Term::Int(2)], None)));
+        let states = solve_all(&g, &mut Trail::from_state(&s));
+        assert_eq!(states.len(), 1);
+        assert_eq!(Term::Var(x).reify(&states[0]).to_string(), "[1, 2]");
+    }
+}
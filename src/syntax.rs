@@ -0,0 +1,1427 @@
+//! # Text Syntax
+//!
+//! A parser for a Prolog-like language, and a `Program` that proves goals
+//! written in it against a collection of clauses.
+//!
+//! The language has atoms (`foo`, `'Hello'`, `[]`), numbers (`12`, `-3.5e2`),
+//! strings (`"text"`), variables (`X`, `_Tail`, `_`), compound terms
+//! (`point(1, 2)`), lists (`[a, b|T]`), and the usual operators.  A text is a
+//! sequence of clauses (`head :- body.` or `fact.`) and queries (`?- goal.`).
+//! A cut (`!`) in a clause body commits to the clause and to the first proof
+//! of the goals before the cut; cutting a clause from inside `;` or `->` is
+//! not supported, and is reported as a parse error.
+//!
+//! ```
+//! use rslogic::state::State;
+//! use rslogic::goal::Goal;
+//! use rslogic::syntax::{Program, parse_query};
+//!
+//! let mut p = Program::new();
+//! p.consult("
+//!     parent(alice, bob).
+//!     parent(bob, carol).
+//!     grandparent(X, Z) :- parent(X, Y), parent(Y, Z).
+//! ").unwrap();
+//!
+//! let q = parse_query("?- grandparent(alice, Who).").unwrap();
+//! let (goal, vars, s) = q.instantiate(&State::empty());
+//! let results = p.goal(goal).eval(&s);
+//! assert_eq!(results.len(), 1);
+//! assert_eq!(vars[0].0, "Who");
+//! assert_eq!(rslogic::term::Term::Var(vars[0].1).reify(&results[0]).to_string(), "carol");
+//! ```
+
+use aggregate::Numeric;
+use error::Error;
+use goal::{Goal, Branch, Subgoal, Port, Tracer};
+use search::Search;
+use state::{Unif, Var, State, PossibleStates};
+use term::{Term, NIL, is_symbol_char};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::rc::Rc;
+
+/// A position in a text, counting lines and columns from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// An error encountered while parsing a text, with the position at which it was found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    message: String,
+    span: Span,
+}
+
+impl ParseError {
+    fn new(message: String, span: Span) -> ParseError {
+        ParseError { message, span }
+    }
+
+    /// Returns a description of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the position at which the error was found.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl error::Error for ParseError {}
+
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Name(String),
+    Var(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Punct(char),
+    End,
+    Eof,
+}
+
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Tok::Name(ref s) | Tok::Var(ref s) => write!(f, "`{}`", s),
+            Tok::Int(n) => write!(f, "`{}`", n),
+            Tok::Float(x) => write!(f, "`{}`", x),
+            Tok::Str(ref s) => write!(f, "{:?}", s),
+            Tok::Punct(c) => write!(f, "`{}`", c),
+            Tok::End => write!(f, "`.`"),
+            Tok::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+struct Token {
+    tok: Tok,
+    span: Span,
+    layout_before: bool, // whitespace or a comment precedes the token
+}
+
+struct Lexer<'t> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'t>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'t> Lexer<'t> {
+    fn new(text: &'t str) -> Lexer<'t> {
+        Lexer { chars: text.chars().peekable(), line: 1, column: 1 }
+    }
+
+    fn span(&self) -> Span {
+        Span { line: self.line, column: self.column }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().cloned()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        match c {
+            Some('\n') => { self.line += 1; self.column = 1; },
+            Some(_) => self.column += 1,
+            None => {},
+        }
+        c
+    }
+
+    fn tokens(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            let layout_before = self.skip_layout()?;
+            let span = self.span();
+            let tok = self.token()?;
+            let eof = tok == Tok::Eof;
+            tokens.push(Token { tok, span, layout_before });
+            if eof {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    /// Skips whitespace and comments, returning `true` if there were any.
+    fn skip_layout(&mut self) -> Result<bool, ParseError> {
+        let mut skipped = false;
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => { self.bump(); },
+                Some('%') => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                },
+                Some('/') if self.chars.clone().nth(1) == Some('*') => {
+                    let span = self.span();
+                    self.bump();
+                    self.bump();
+                    loop {
+                        match self.bump() {
+                            Some('*') if self.peek() == Some('/') => { self.bump(); break; },
+                            Some(_) => {},
+                            None => return Err(ParseError::new("unterminated block comment".to_string(), span)),
+                        }
+                    }
+                },
+                _ => return Ok(skipped),
+            }
+            skipped = true;
+        }
+    }
+
+    fn token(&mut self) -> Result<Tok, ParseError> {
+        let span = self.span();
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Ok(Tok::Eof),
+        };
+
+        if c.is_ascii_digit() {
+            self.number()
+        } else if c == '_' || c.is_uppercase() {
+            Ok(Tok::Var(self.word()))
+        } else if c.is_alphabetic() {
+            Ok(Tok::Name(self.word()))
+        } else if c == '\'' {
+            Ok(Tok::Name(self.quoted('\'')?))
+        } else if c == '"' {
+            Ok(Tok::Str(self.quoted('"')?))
+        } else if "()[]{},|".contains(c) {
+            self.bump();
+            Ok(Tok::Punct(c))
+        } else if c == '!' || c == ';' {
+            self.bump();
+            Ok(Tok::Name(c.to_string()))
+        } else if is_symbol_char(c) {
+            let mut name = String::new();
+            while let Some(c) = self.peek().filter(|&c| is_symbol_char(c)) {
+                self.bump();
+                name.push(c);
+                if name == "." && self.peek().is_none_or(|c| c.is_whitespace() || c == '%') {
+                    return Ok(Tok::End);
+                }
+            }
+            Ok(Tok::Name(name))
+        } else {
+            Err(ParseError::new(format!("unexpected character `{}`", c), span))
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek().filter(|&c| c.is_alphanumeric() || c == '_') {
+            self.bump();
+            word.push(c);
+        }
+        word
+    }
+
+    fn digits(&mut self, text: &mut String) {
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_digit()) {
+            self.bump();
+            text.push(c);
+        }
+    }
+
+    fn number(&mut self) -> Result<Tok, ParseError> {
+        let span = self.span();
+        let mut text = String::new();
+        self.digits(&mut text);
+
+        let mut float = false;
+        let mut ahead = self.chars.clone();
+        if ahead.next() == Some('.') && ahead.next().is_some_and(|c| c.is_ascii_digit()) {
+            float = true;
+            text.push('.');
+            self.bump();
+            self.digits(&mut text);
+        }
+        if let Some(e) = self.peek().filter(|&c| c == 'e' || c == 'E') {
+            let mut ahead = self.chars.clone();
+            ahead.next();
+            let sign = ahead.peek().cloned().filter(|&c| c == '+' || c == '-');
+            if sign.is_some() {
+                ahead.next();
+            }
+            if ahead.next().is_some_and(|c| c.is_ascii_digit()) {
+                float = true;
+                self.bump();
+                text.push(e);
+                if let Some(sign) = sign {
+                    self.bump();
+                    text.push(sign);
+                }
+                self.digits(&mut text);
+            }
+        }
+
+        if float {
+            text.parse().map(Tok::Float).map_err(|_| ParseError::new(format!("invalid number `{}`", text), span))
+        } else {
+            text.parse().map(Tok::Int).map_err(|_| ParseError::new(format!("integer `{}` is too large", text), span))
+        }
+    }
+
+    fn quoted(&mut self, quote: char) -> Result<String, ParseError> {
+        let span = self.span();
+        self.bump();
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => {
+                    if self.peek() == Some(quote) {
+                        self.bump();
+                        text.push(quote);
+                    } else {
+                        return Ok(text);
+                    }
+                },
+                Some('\\') => {
+                    let escape = self.span();
+                    match self.bump() {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some('r') => text.push('\r'),
+                        Some('0') => text.push('\0'),
+                        Some(c) if c == '\\' || c == '\'' || c == '"' || c == '`' => text.push(c),
+                        Some('\n') => {},
+                        Some(c) => return Err(ParseError::new(format!("unknown escape sequence `\\{}`", c), escape)),
+                        None => return Err(ParseError::new("unterminated quoted text".to_string(), span)),
+                    }
+                },
+                Some(c) => text.push(c),
+                None => return Err(ParseError::new("unterminated quoted text".to_string(), span)),
+            }
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum Fix {
+    Xfx,
+    Xfy,
+    Yfx,
+    Fy,
+    Fx,
+}
+
+fn infix_op(name: &str) -> Option<(u32, Fix)> {
+    match name {
+        ":-" => Some((1200, Fix::Xfx)),
+        ";" => Some((1100, Fix::Xfy)),
+        "->" => Some((1050, Fix::Xfy)),
+        "," => Some((1000, Fix::Xfy)),
+        "=" | "\\=" | "==" | "\\==" | "is" | "<" | ">" | "=<" | ">=" | "=:=" | "=\\=" => Some((700, Fix::Xfx)),
+        "+" | "-" => Some((500, Fix::Yfx)),
+        "*" | "/" | "//" | "mod" => Some((400, Fix::Yfx)),
+        "**" => Some((200, Fix::Xfx)),
+        "^" => Some((200, Fix::Xfy)),
+        _ => None,
+    }
+}
+
+fn prefix_op(name: &str) -> Option<(u32, Fix)> {
+    match name {
+        ":-" | "?-" => Some((1200, Fix::Fx)),
+        "\\+" => Some((900, Fix::Fy)),
+        "-" | "+" => Some((200, Fix::Fy)),
+        _ => None,
+    }
+}
+
+/// A parsed clause.  The head and body share variables, which are renamed to
+/// fresh variables each time the clause is used.
+#[derive(Clone)]
+pub struct Clause {
+    head: Term,
+    body: Term,
+    vars: Vec<Var>,
+}
+
+impl Clause {
+    /// Returns the head of the clause.
+    pub fn head(&self) -> &Term {
+        &self.head
+    }
+
+    /// Returns the body of the clause (`true` for a fact).
+    pub fn body(&self) -> &Term {
+        &self.body
+    }
+
+    /// Returns copies of the head and body whose variables are fresh variables
+    /// in the state.
+    fn rename(&self, state: &State<Term>) -> (Term, Term, State<Term>) {
+        let (map, state) = fresh_vars(&self.vars, &[], state);
+        let mut rename = |var| Term::Var(map[&var]);
+        (self.head.map_vars(&mut rename), self.body.map_vars(&mut rename), state)
+    }
+}
+
+/// A parsed query, with the names of its variables.
+#[derive(Clone)]
+pub struct Query {
+    goal: Term,
+    vars: Vec<Var>,
+    names: Vec<(String, Var)>,
+}
+
+impl Query {
+    /// Returns the goal of the query.
+    pub fn goal(&self) -> &Term {
+        &self.goal
+    }
+
+    /// Creates fresh variables in the state for the query's variables, named
+    /// as in the query.  Returns the goal in terms of the fresh variables, the
+    /// fresh variables that have names, in order of their first appearance,
+    /// and the resulting state.
+    pub fn instantiate(&self, state: &State<Term>) -> (Term, Vec<(String, Var)>, State<Term>) {
+        let (map, state) = fresh_vars(&self.vars, &self.names, state);
+        let goal = self.goal.map_vars(&mut |var| Term::Var(map[&var]));
+        let names = self.names.iter().map(|&(ref name, var)| (name.clone(), map[&var])).collect();
+        (goal, names, state)
+    }
+}
+
+fn fresh_vars(vars: &[Var], names: &[(String, Var)], state: &State<Term>) -> (HashMap<Var, Var>, State<Term>) {
+    let mut map = HashMap::new();
+    let mut state = state.clone();
+    for var in vars {
+        let (fresh, next) = match names.iter().find(|named| named.1 == *var) {
+            Some(named) => state.make_named_var(&named.0),
+            None => state.make_var(),
+        };
+        map.insert(*var, fresh);
+        state = next;
+    }
+    (map, state)
+}
+
+/// A sentence of the text syntax.
+#[derive(Clone)]
+pub enum Sentence {
+    Clause(Clause),
+    Query(Query),
+}
+
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    state: State<Term>,
+    vars: Vec<Var>,
+    names: Vec<(String, Var)>,
+}
+
+impl Parser {
+    fn new(text: &str) -> Result<Parser, ParseError> {
+        Ok(Parser {
+            tokens: Lexer::new(text).tokens()?,
+            pos: 0,
+            state: State::empty(),
+            vars: Vec::new(),
+            names: Vec::new(),
+        })
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        &self.tokens[(self.pos + offset).min(self.tokens.len() - 1)]
+    }
+
+    fn advance(&mut self) -> &Token {
+        let pos = self.pos;
+        if pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        &self.tokens[pos]
+    }
+
+    fn unexpected<A>(&self, expected: &str) -> Result<A, ParseError> {
+        let token = self.peek();
+        Err(ParseError::new(format!("expected {}, found {}", expected, token.tok), token.span))
+    }
+
+    /// Reports an operator that could not be used where it was found.
+    fn clash<A>(&self) -> Result<A, ParseError> {
+        match self.infix() {
+            Some((name, _, _)) => Err(ParseError::new(format!("operator priority clash at `{}`; try using parentheses", name), self.peek().span)),
+            None => self.unexpected("an operator or `.`"),
+        }
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), ParseError> {
+        if self.peek().tok == Tok::Punct(punct) {
+            self.advance();
+            Ok(())
+        } else {
+            self.unexpected(&format!("`{}`", punct))
+        }
+    }
+
+    fn variable(&mut self, name: &str) -> Term {
+        if name != "_" {
+            if let Some(&(_, var)) = self.names.iter().find(|&(n, _)| n == name) {
+                return Term::Var(var);
+            }
+        }
+        let (var, state) = self.state.make_var();
+        self.state = state;
+        self.vars.push(var);
+        if name != "_" {
+            self.names.push((name.to_string(), var));
+        }
+        Term::Var(var)
+    }
+
+    /// Returns the name of the operator at the current position, if it is an infix operator.
+    fn infix(&self) -> Option<(String, u32, Fix)> {
+        let name = match self.peek().tok {
+            Tok::Name(ref name) => name.clone(),
+            Tok::Punct(',') => ",".to_string(),
+            Tok::Punct('|') => ";".to_string(),
+            _ => return None,
+        };
+        infix_op(&name).map(|(prec, fix)| (name, prec, fix))
+    }
+
+    /// Returns `true` if the token at the offset can begin a term.
+    fn starts_term(&self, offset: usize) -> bool {
+        match self.peek_at(offset).tok {
+            Tok::Name(ref name) => infix_op(name).is_none() || prefix_op(name).is_some()
+                                   || self.peek_at(offset + 1).tok == Tok::Punct('('),
+            Tok::Var(_) | Tok::Int(_) | Tok::Float(_) | Tok::Str(_) => true,
+            Tok::Punct(c) => c == '(' || c == '[' || c == '{',
+            Tok::End | Tok::Eof => false,
+        }
+    }
+
+    /// Parses a term whose precedence is at most `max`.
+    fn term(&mut self, max: u32) -> Result<Term, ParseError> {
+        let (mut left, mut left_prec) = self.primary(max)?;
+        while let Some((name, prec, fix)) = self.infix() {
+            let (left_max, right_max) = match fix {
+                Fix::Xfx => (prec - 1, prec - 1),
+                Fix::Xfy => (prec - 1, prec),
+                _ => (prec, prec - 1),
+            };
+            if prec > max || left_prec > left_max {
+                break;
+            }
+            self.advance();
+            let right = self.term(right_max)?;
+            left = Term::compound(&name, vec![left, right]);
+            left_prec = prec;
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self, max: u32) -> Result<(Term, u32), ParseError> {
+        let span = self.peek().span;
+        let tok = self.peek().tok.clone();
+        match tok {
+            Tok::Int(n) => { self.advance(); Ok((Term::Int(n), 0)) },
+            Tok::Float(x) => { self.advance(); Ok((Term::Float(x), 0)) },
+            Tok::Str(s) => { self.advance(); Ok((Term::Str(s), 0)) },
+            Tok::Var(name) => { self.advance(); Ok((self.variable(&name), 0)) },
+            Tok::Punct('(') => {
+                self.advance();
+                let term = self.term(1200)?;
+                self.expect(')')?;
+                Ok((term, 0))
+            },
+            Tok::Punct('[') => {
+                self.advance();
+                if self.peek().tok == Tok::Punct(']') {
+                    self.advance();
+                    return Ok((Term::atom(NIL), 0));
+                }
+                let mut items = vec![self.term(999)?];
+                while self.peek().tok == Tok::Punct(',') {
+                    self.advance();
+                    items.push(self.term(999)?);
+                }
+                let tail = if self.peek().tok == Tok::Punct('|') {
+                    self.advance();
+                    Some(self.term(999)?)
+                } else {
+                    None
+                };
+                self.expect(']')?;
+                Ok((Term::list(items, tail), 0))
+            },
+            Tok::Name(name) => {
+                self.advance();
+                if self.peek().tok == Tok::Punct('(') && !self.peek().layout_before {
+                    self.advance();
+                    let mut args = vec![self.term(999)?];
+                    while self.peek().tok == Tok::Punct(',') {
+                        self.advance();
+                        args.push(self.term(999)?);
+                    }
+                    self.expect(')')?;
+                    return Ok((Term::Compound(name, args), 0));
+                }
+
+                if name == "-" && !self.peek().layout_before {
+                    match self.peek().tok {
+                        Tok::Int(n) => { self.advance(); return Ok((Term::Int(-n), 0)) },
+                        Tok::Float(x) => { self.advance(); return Ok((Term::Float(-x), 0)) },
+                        _ => {},
+                    }
+                }
+
+                match prefix_op(&name) {
+                    Some((prec, fix)) if self.starts_term(0) => {
+                        if prec > max {
+                            return Err(ParseError::new(format!("operator `{}` has too high a precedence here; try using parentheses", name), span));
+                        }
+                        let arg_max = if fix == Fix::Fy { prec } else { prec - 1 };
+                        let arg = self.term(arg_max)?;
+                        Ok((Term::compound(&name, vec![arg]), prec))
+                    },
+                    _ => {
+                        let prec = infix_op(&name).or_else(|| prefix_op(&name)).map_or(0, |(prec, _)| prec);
+                        Ok((Term::Atom(name), if prec > max { 0 } else { prec }))
+                    },
+                }
+            },
+            Tok::Punct(_) | Tok::End | Tok::Eof => self.unexpected("a term"),
+        }
+    }
+
+    /// Parses a sentence ending with `.`, or returns `None` at the end of the text.
+    fn sentence(&mut self) -> Result<Option<Sentence>, ParseError> {
+        if self.peek().tok == Tok::Eof {
+            return Ok(None);
+        }
+
+        self.state = State::empty();
+        self.vars.clear();
+        self.names.clear();
+
+        let span = self.peek().span;
+        let term = self.term(1200)?;
+        if self.peek().tok != Tok::End {
+            return self.clash();
+        }
+        self.advance();
+
+        let vars = self.vars.clone();
+        let (head, body) = match term {
+            Term::Compound(ref name, ref args) if (name == "?-" || name == ":-") && args.len() == 1 => {
+                check_cuts(&args[0], false, span)?;
+                let names = self.names.clone();
+                return Ok(Some(Sentence::Query(Query { goal: args[0].clone(), vars, names })));
+            },
+            Term::Compound(ref name, ref args) if name == ":-" && args.len() == 2 => (args[0].clone(), args[1].clone()),
+            term => (term, Term::atom("true")),
+        };
+        check_cuts(&body, false, span)?;
+        match head {
+            Term::Atom(_) | Term::Compound(_, _) => Ok(Some(Sentence::Clause(Clause { head, body, vars }))),
+            _ => Err(ParseError::new("the head of a clause must be an atom or compound term".to_string(), span)),
+        }
+    }
+}
+
+/// Returns an error if a cut is in a branch of `;` or `->` in the goal.  Such
+/// a cut would cut the clause, which is not supported; a cut elsewhere in a
+/// conjunction, or in the condition of `->` or the goal of a built-in such as
+/// `\+` or `findall/3`, is.
+fn check_cuts(goal: &Term, in_branch: bool, span: Span) -> Result<(), ParseError> {
+    match *goal {
+        Term::Atom(ref name) if name == "!" && in_branch =>
+            Err(ParseError::new("a cut in a branch of `;` or `->` is not supported".to_string(), span)),
+        Term::Compound(ref name, ref args) if args.len() == 2 => match name.as_str() {
+            "," => {
+                check_cuts(&args[0], in_branch, span)?;
+                check_cuts(&args[1], in_branch, span)
+            },
+            ";" => {
+                match args[0] {
+                    Term::Compound(ref name, ref cond) if name == "->" && cond.len() == 2 => check_cuts(&cond[1], true, span)?,
+                    ref goal => check_cuts(goal, true, span)?,
+                }
+                check_cuts(&args[1], true, span)
+            },
+            "->" => check_cuts(&args[1], true, span),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Parses a text containing clauses and queries.
+pub fn parse(text: &str) -> Result<Vec<Sentence>, ParseError> {
+    let mut parser = Parser::new(text)?;
+    let mut sentences = Vec::new();
+    while let Some(sentence) = parser.sentence()? {
+        sentences.push(sentence);
+    }
+    Ok(sentences)
+}
+
+/// Parses a single query.  The leading `?-` and the final `.` are optional.
+pub fn parse_query(text: &str) -> Result<Query, ParseError> {
+    let mut parser = Parser::new(text)?;
+    if parser.peek().tok == Tok::Name("?-".to_string()) && parser.starts_term(1) {
+        parser.advance();
+    }
+    let span = parser.peek().span;
+    let goal = parser.term(1200)?;
+    check_cuts(&goal, false, span)?;
+    if parser.peek().tok == Tok::End {
+        parser.advance();
+    }
+    if parser.peek().tok != Tok::Eof {
+        return parser.clash();
+    }
+    Ok(Query { goal, vars: parser.vars, names: parser.names })
+}
+
+
+/// A collection of clauses, against which goals can be proven.
+#[derive(Default)]
+pub struct Program {
+    clauses: HashMap<(String, usize), Vec<Clause>>,
+    tracer: Option<Rc<dyn Tracer<Term>>>,
+}
+
+impl Program {
+    /// Creates an empty program.
+    pub fn new() -> Program {
+        Program { clauses: HashMap::new(), tracer: None }
+    }
+
+    /// Adds a clause to the end of the clauses for its head's functor.
+    pub fn add(&mut self, clause: Clause) {
+        let key = match clause.head.functor() {
+            Some((name, arity)) => (name.to_string(), arity),
+            None => return,
+        };
+        self.clauses.entry(key).or_default().push(clause);
+    }
+
+    /// Parses the text and adds its clauses to the program.  Returns the
+    /// queries in the text, in order.
+    pub fn consult(&mut self, text: &str) -> Result<Vec<Query>, ParseError> {
+        let mut queries = Vec::new();
+        for sentence in parse(text)? {
+            match sentence {
+                Sentence::Clause(clause) => self.add(clause),
+                Sentence::Query(query) => queries.push(query),
+            }
+        }
+        Ok(queries)
+    }
+
+    /// Returns the clauses whose heads have the given name and arity.
+    pub fn clauses(&self, name: &str, arity: usize) -> &[Clause] {
+        match self.clauses.get(&(name.to_string(), arity)) {
+            Some(clauses) => clauses,
+            None => &[],
+        }
+    }
+
+    /// Creates a goal that proves the term using the program's clauses.
+    pub fn goal(&self, term: Term) -> Box<dyn Goal<Term> + '_> {
+        Box::new(Solve { program: self, term, depth: 0 })
+    }
+
+    /// Sets the tracer to which the ports of goals are reported, or turns
+    /// tracing off if `tracer` is `None`.
+    ///
+    /// Every goal is traced except the control constructs `,`, `;` and `->`,
+    /// whose sub-goals are traced at the same depth as the construct itself.
+    pub fn set_tracer(&mut self, tracer: Option<Rc<dyn Tracer<Term>>>) {
+        self.tracer = tracer;
+    }
+
+    /// Returns the first state in which the term is proven, if any.
+    fn first(&self, term: &Term, depth: usize, state: &State<Term>) -> Option<State<Term>> {
+        let goal = Solve { program: self, term: term.clone(), depth };
+        let mut search = Search::new(&goal, state);
+        search.next()
+    }
+
+    /// Proves the term in every possible way, and returns a copy of the template
+    /// and of the witness for each solution, and a state in which any
+    /// variables they contain may be used.
+    fn collect(&self, template: &Term, witness: &Term, goal: &Term, depth: usize, state: &State<Term>)
+               -> (Vec<(Term, Term)>, State<Term>) {
+        let goal = Solve { program: self, term: goal.clone(), depth };
+        let mut vars = state.clone();
+        let mut found = Vec::new();
+        for s in Search::new(&goal, state) {
+            found.push((witness.reify(&s), template.reify(&s)));
+            vars = vars.with_vars_of(&s);
+        }
+        (found, vars)
+    }
+
+    /// Implements `bagof/3` and `setof/3`, which group the solutions of the
+    /// goal by the values of its free variables: those that are not in the
+    /// template, nor bound with `^` as in `Var^Goal`.
+    fn bagof<'a>(&self, args: &[Term], sorted: bool, depth: usize, state: &State<Term>) -> Vec<Branch<'a, Term>> {
+        let template = args[0].reify(state);
+        let mut goal = args[1].reify(state);
+        let mut bound = template.vars();
+        while let Term::Compound(ref name, ref args) = goal.clone() {
+            if name != "^" || args.len() != 2 {
+                break;
+            }
+            bound.extend(args[0].vars());
+            goal = args[1].clone();
+        }
+        let free: Vec<Term> = goal.vars().into_iter().filter(|var| !bound.contains(var)).map(Term::Var).collect();
+        let witness = Term::compound("v", free);
+
+        let (mut found, vars) = self.collect(&template, &witness, &goal, depth + 1, state);
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut branches = Vec::new();
+        let mut found = found.into_iter().peekable();
+        while let Some((key, item)) = found.next() {
+            let mut items = vec![item];
+            while let Some((_, item)) = found.next_if(|next| next.0 == key) {
+                items.push(item);
+            }
+            if sorted {
+                items.sort();
+                items.dedup();
+            }
+            for s in witness.unify(&key, &vars) {
+                branches.extend(args[2].unify(&Term::list(items.clone(), None), &s).into_iter().map(Branch::done));
+            }
+        }
+        branches
+    }
+
+    /// Implements `aggregate_all/3`, for the aggregates `count`, `sum(Expr)`,
+    /// `max(Expr)`, `min(Expr)`, `bag(Template)` and `set(Template)`.
+    fn aggregate_all<'a>(&self, args: &[Term], depth: usize, state: &State<Term>) -> Vec<Branch<'a, Term>> {
+        let (kind, template) = match *args[0].walk(state) {
+            Term::Atom(ref name) if name == "count" => ("count", Term::atom("true")),
+            Term::Compound(ref name, ref args) if args.len() == 1 => (name.as_str(), args[0].clone()),
+            _ => return Vec::new(),
+        };
+        let (found, vars) = self.collect(&template, &Term::atom("v"), &args[1], depth + 1, state);
+        let mut items = found.into_iter().map(|(_, item)| item);
+        let value = match kind {
+            "count" => Term::from_count(items.count()),
+            "sum" => items.try_fold(Term::Int(0), |total, item| total.add(&eval_arith(&item, &vars)?)),
+            "max" | "min" => {
+                let which = if kind == "max" { Ordering::Greater } else { Ordering::Less };
+                let mut best: Option<Term> = None;
+                for item in items {
+                    let item = match eval_arith(&item, &vars) {
+                        Some(item) => item,
+                        None => return Vec::new(),
+                    };
+                    best = match best {
+                        Some(best) if item.compare(&best) != Some(which) => Some(best),
+                        _ => Some(item),
+                    };
+                }
+                best
+            },
+            "bag" => Some(Term::list(items.collect(), None)),
+            "set" => {
+                let mut items: Vec<Term> = items.collect();
+                items.sort();
+                items.dedup();
+                Some(Term::list(items, None))
+            },
+            _ => None,
+        };
+        match value {
+            Some(value) => args[2].unify(&value, &vars).into_iter().map(Branch::done).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn expand<'a>(&'a self, goal: &Term, depth: usize, state: &State<Term>) -> Vec<Branch<'a, Term>> {
+        let tracer = match self.tracer {
+            Some(ref tracer) => tracer,
+            None => return self.resolve(goal, depth, state),
+        };
+        let goal = goal.walk(state);
+        match goal.functor() {
+            Some((",", 2)) | Some((";", 2)) | Some(("->", 2)) => return self.resolve(goal, depth, state),
+            _ => {},
+        }
+
+        // surround each branch with markers that report its ports when the search reaches them
+        tracer.port(Port::Call, depth, &goal.reify(state).to_string(), state);
+        let marker = |port| -> Subgoal<'a, Term> {
+            Subgoal::Shared(Rc::new(TracePort { tracer: tracer.clone(), port, depth, term: goal.clone(), call: state.clone() }))
+        };
+        let branches = self.resolve(goal, depth, state);
+        let mut traced = Vec::with_capacity(branches.len() + 1);
+        let redo = !branches.is_empty();
+        for (i, branch) in branches.into_iter().enumerate() {
+            let cost = branch.cost;
+            let mut goals = Vec::with_capacity(branch.goals.len() + 2);
+            if i > 0 {
+                goals.push(marker(Port::Redo));
+            }
+            goals.extend(branch.goals);
+            goals.push(marker(Port::Exit));
+            traced.push(Branch::new(branch.state, goals).with_cost(cost));
+        }
+        let mut fail = vec![marker(Port::Fail)];
+        if redo {
+            fail.insert(0, marker(Port::Redo));
+        }
+        traced.push(Branch::new(state.clone(), fail));
+        traced
+    }
+
+    fn resolve<'a>(&'a self, goal: &Term, depth: usize, state: &State<Term>) -> Vec<Branch<'a, Term>> {
+        let goal = goal.walk(state);
+        let (name, args): (&str, &[Term]) = match *goal {
+            Term::Atom(ref name) => (name, &[]),
+            Term::Compound(ref name, ref args) => (name, args),
+            _ => return Vec::new(),
+        };
+
+        let sub = |term: &Term, depth| -> Subgoal<'a, Term> {
+            Subgoal::Shared(Rc::new(Solve { program: self, term: term.clone(), depth }))
+        };
+        let done = |states: PossibleStates<Term>| states.into_iter().map(Branch::done).collect();
+        let succeed_if = |cond: bool| if cond { vec![Branch::done(state.clone())] } else { Vec::new() };
+
+        match (name, args.len()) {
+            ("true", 0) | ("!", 0) => succeed_if(true),
+            ("fail", 0) | ("false", 0) => Vec::new(),
+            (",", 2) => match split_cut(goal) {
+                // outside the top of a clause body, as in a query or the goal
+                // of `\+`, a cut commits to the first proof of the goals before it
+                Some((before, after)) => match self.first(&before, depth, state) {
+                    Some(s) => vec![Branch::new(s, vec![sub(&after, depth)])],
+                    None => Vec::new(),
+                },
+                None => vec![Branch::new(state.clone(), vec![sub(&args[0], depth), sub(&args[1], depth)])],
+            },
+            (";", 2) => match *args[0].walk(state) {
+                Term::Compound(ref name, ref cond) if name == "->" && cond.len() == 2 => {
+                    match self.first(&cond[0], depth, state) {
+                        Some(s) => vec![Branch::new(s, vec![sub(&cond[1], depth)])],
+                        None => vec![Branch::new(state.clone(), vec![sub(&args[1], depth)])],
+                    }
+                },
+                _ => vec![
+                    Branch::new(state.clone(), vec![sub(&args[0], depth)]),
+                    Branch::new(state.clone(), vec![sub(&args[1], depth)]),
+                ],
+            },
+            ("->", 2) => match self.first(&args[0], depth, state) {
+                Some(s) => vec![Branch::new(s, vec![sub(&args[1], depth)])],
+                None => Vec::new(),
+            },
+            ("\\+", 1) => succeed_if(self.first(&args[0], depth + 1, state).is_none()),
+            ("findall", 3) => {
+                let (found, vars) = self.collect(&args[0], &Term::atom("v"), &args[1], depth + 1, state);
+                let list = Term::list(found.into_iter().map(|(_, item)| item).collect(), None);
+                done(args[2].unify(&list, &vars))
+            },
+            ("bagof", 3) => self.bagof(args, false, depth, state),
+            ("setof", 3) => self.bagof(args, true, depth, state),
+            ("aggregate_all", 3) => self.aggregate_all(args, depth, state),
+            ("=", 2) => done(args[0].unify(&args[1], state)),
+            ("\\=", 2) => succeed_if(args[0].unify(&args[1], state).is_empty()),
+            ("==", 2) => succeed_if(args[0].reify(state) == args[1].reify(state)),
+            ("\\==", 2) => succeed_if(args[0].reify(state) != args[1].reify(state)),
+            ("is", 2) => match eval_arith(&args[1], state) {
+                Some(n) => done(args[0].unify(&n, state)),
+                None => Vec::new(),
+            },
+            ("<", 2) | (">", 2) | ("=<", 2) | (">=", 2) | ("=:=", 2) | ("=\\=", 2) => {
+                match compare_arith(&args[0], &args[1], state) {
+                    Some(ord) => succeed_if(match name {
+                        "<" => ord.is_lt(),
+                        ">" => ord.is_gt(),
+                        "=<" => ord.is_le(),
+                        ">=" => ord.is_ge(),
+                        "=:=" => ord.is_eq(),
+                        _ => ord.is_ne(),
+                    }),
+                    None => Vec::new(),
+                }
+            },
+            _ => {
+                let mut branches = Vec::new();
+                for clause in self.clauses(name, args.len()) {
+                    let (head, body, s) = clause.rename(state);
+                    let fact = body == Term::atom("true");
+                    let cut = split_cut(&body);
+                    for s in head.unify(goal, &s) {
+                        match cut {
+                            // once the goals before the cut are proven, the later
+                            // clauses and the other proofs of those goals are cut off
+                            Some((ref before, ref after)) => if let Some(s) = self.first(before, depth + 1, &s) {
+                                branches.push(Branch::new(s, vec![sub(after, depth + 1)]));
+                                return branches;
+                            },
+                            None => branches.push(if fact { Branch::done(s) } else { Branch::new(s, vec![sub(&body, depth + 1)]) }),
+                        }
+                    }
+                }
+                branches
+            },
+        }
+    }
+}
+
+/// Splits a conjunction at its first cut into the goals before the cut and
+/// the goals after it, or returns `None` if there is no cut at its top level.
+/// A variable is not a cut, even if it is bound to one, as it is called like
+/// the goal of `call/1`.
+fn split_cut(goal: &Term) -> Option<(Term, Term)> {
+    match *goal {
+        Term::Atom(ref name) if name == "!" => Some((Term::atom("true"), Term::atom("true"))),
+        Term::Compound(ref name, ref args) if name == "," && args.len() == 2 => {
+            match split_cut(&args[0]) {
+                Some((before, after)) => Some((before, Term::compound(",", vec![after, args[1].clone()]))),
+                None => split_cut(&args[1]).map(|(before, after)| (Term::compound(",", vec![args[0].clone(), before]), after)),
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Evaluating a `Solve` goal proves a term using the clauses of a program.
+pub struct Solve<'a> {
+    program: &'a Program,
+    term: Term,
+    depth: usize,
+}
+
+impl<'a> Goal<Term> for Solve<'a> {
+    fn eval(&self, state: &State<Term>) -> PossibleStates<Term> {
+        Search::new(self, state).collect()
+    }
+
+    /// The variables that the search creates come from the state, so only
+    /// those of the term need to be checked before searching.
+    fn try_eval(&self, state: &State<Term>) -> Result<PossibleStates<Term>, Error> {
+        let mut vars = Vec::new();
+        self.term.each_var(&mut |var| vars.push(*var));
+        for var in &vars {
+            state.try_get(var)?;
+        }
+        Ok(self.eval(state))
+    }
+
+    fn expand<'b>(&'b self, state: &State<Term>) -> Vec<Branch<'b, Term>> {
+        self.program.expand(&self.term, self.depth, state)
+    }
+
+    fn expand_shared<'b>(self: Rc<Self>, state: &State<Term>) -> Vec<Branch<'b, Term>> where Self: 'b {
+        self.program.expand(&self.term, self.depth, state)
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        self.term.each_var(f)
+    }
+}
+
+/// A marker that reports a port of a traced goal when the search reaches it.
+/// Reaching a `Fail` port ends the path; reaching any other continues it.
+struct TracePort {
+    tracer: Rc<dyn Tracer<Term>>,
+    port: Port,
+    depth: usize,
+    term: Term,
+    call: State<Term>,
+}
+
+impl Goal<Term> for TracePort {
+    fn eval(&self, state: &State<Term>) -> PossibleStates<Term> {
+        // `Exit` shows the goal as it was proven; the other ports show it as it was called
+        let shown = if self.port == Port::Exit { state } else { &self.call };
+        self.tracer.port(self.port, self.depth, &self.term.reify(shown).to_string(), shown);
+        if self.port == Port::Fail { Vec::new() } else { vec![state.clone()] }
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        self.term.each_var(f)
+    }
+}
+
+
+/// Evaluates an arithmetic expression, returning `None` if it contains unbound
+/// variables or non-numbers, or divides by zero.
+fn eval_arith(term: &Term, state: &State<Term>) -> Option<Term> {
+    let (name, args) = match *term.walk(state) {
+        Term::Int(n) => return Some(Term::Int(n)),
+        Term::Float(x) => return Some(Term::Float(x)),
+        Term::Compound(ref name, ref args) => (name.as_str(), args),
+        _ => return None,
+    };
+    let args = args.iter().map(|arg| eval_arith(arg, state)).collect::<Option<Vec<Term>>>()?;
+
+    match (name, &args[..]) {
+        ("-", &[Term::Int(a)]) => a.checked_neg().map(Term::Int),
+        ("-", &[Term::Float(a)]) => Some(Term::Float(-a)),
+        ("+", [a]) => Some(a.clone()),
+        ("abs", &[Term::Int(a)]) => a.checked_abs().map(Term::Int),
+        ("abs", &[Term::Float(a)]) => Some(Term::Float(a.abs())),
+        (_, &[Term::Int(a), Term::Int(b)]) => match name {
+            "+" => a.checked_add(b).map(Term::Int),
+            "-" => a.checked_sub(b).map(Term::Int),
+            "*" => a.checked_mul(b).map(Term::Int),
+            "/" => match a.checked_rem(b)? {
+                0 => a.checked_div(b).map(Term::Int),
+                _ => Some(Term::Float(a as f64 / b as f64)),
+            },
+            "//" => a.checked_div(b).map(Term::Int),
+            // the result takes the sign of the divisor
+            "mod" if b != 0 => match a.wrapping_rem(b) {
+                r if r != 0 && (r < 0) != (b < 0) => Some(Term::Int(r + b)),
+                r => Some(Term::Int(r)),
+            },
+            "min" => Some(Term::Int(a.min(b))),
+            "max" => Some(Term::Int(a.max(b))),
+            "**" | "^" => match u32::try_from(b) {
+                Ok(b) => a.checked_pow(b).map(Term::Int),
+                // only `**` has a fractional result
+                Err(_) if name == "**" => Some(Term::Float((a as f64).powf(b as f64))),
+                Err(_) => None,
+            },
+            _ => None,
+        },
+        (_, [a, b]) => {
+            let (a, b) = (as_float(a)?, as_float(b)?);
+            match name {
+                "+" => Some(Term::Float(a + b)),
+                "-" => Some(Term::Float(a - b)),
+                "*" => Some(Term::Float(a * b)),
+                "/" if b != 0.0 => Some(Term::Float(a / b)),
+                "min" => Some(Term::Float(a.min(b))),
+                "max" => Some(Term::Float(a.max(b))),
+                "**" | "^" => Some(Term::Float(a.powf(b))),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+fn as_float(term: &Term) -> Option<f64> {
+    match *term {
+        Term::Int(n) => Some(n as f64),
+        Term::Float(x) => Some(x),
+        _ => None,
+    }
+}
+
+fn compare_arith(a: &Term, b: &Term, state: &State<Term>) -> Option<Ordering> {
+    match (eval_arith(a, state)?, eval_arith(b, state)?) {
+        (Term::Int(a), Term::Int(b)) => Some(a.cmp(&b)),
+        (a, b) => as_float(&a)?.partial_cmp(&as_float(&b)?),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use goal::{Goal, PrettyTracer};
+    use search::Search;
+    use state::State;
+    use std::rc::Rc;
+    use term::Term;
+    use super::{Program, Sentence, Span, ParseError, parse, parse_query};
+
+    fn answers(program: &Program, query: &str) -> Vec<String> {
+        let q = parse_query(query).unwrap();
+        let (goal, vars, s) = q.instantiate(&State::empty());
+        program.goal(goal).eval(&s).iter().map(|s| {
+            vars.iter().map(|&(ref name, var)| format!("{} = {}", name, Term::Var(var).reify(s)))
+                .collect::<Vec<_>>().join(", ")
+        }).collect()
+    }
+
+    fn parse_error(text: &str) -> ParseError {
+        match parse(text) {
+            Ok(_) => panic!("expected a parse error"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn test_named_answers() {
+        let mut p = Program::new();
+        p.consult("pair(X, f(X)).").unwrap();
+        let q = parse_query("?- pair(1, Y), Z = Y.").unwrap();
+        let (goal, _, s) = q.instantiate(&State::empty());
+        let results = p.goal(goal).eval(&s);
+        assert_eq!(results.len(), 1);
+        let answer = results[0].answer();
+        assert_eq!(answer["Y"].to_string(), "f(1)");
+        assert_eq!(answer["Z"].to_string(), "f(1)");
+        assert_eq!(results[0].var_name(&s.var_named("Z").unwrap()), Some("Z"));
+    }
+
+    #[test]
+    fn test_compacting_search() {
+        let mut p = Program::new();
+        p.consult("count(N, N).  count(I, N) :- I < N, J is I + 1, count(J, N).").unwrap();
+        let q = parse_query("?- count(0, 500), X = done.").unwrap();
+        let (goal, vars, s) = q.instantiate(&State::empty());
+        let goal = p.goal(goal);
+        let roots: Vec<_> = vars.iter().map(|named| named.1).collect();
+
+        let plain = Search::new(&*goal, &s).next().unwrap();
+        let compacted = Search::new(&*goal, &s).compact_every(10, &roots).next().unwrap();
+        assert_eq!(compacted.answer()["X"], Term::atom("done"));
+        // the variables of the clauses used along the way are forgotten
+        let bound = |s: &State<Term>| s.to_string().matches(" = ").count();
+        assert!(bound(&plain) > 1000);
+        assert!(bound(&compacted) < 10, "{} bindings", bound(&compacted));
+    }
+
+    #[test]
+    fn test_parse_terms() {
+        let sentences = parse("f(X, 'A b', \"str\", [1, 2.5|T], -3, - 3, a + b * c, (a :- b, c ; d)).").unwrap();
+        assert_eq!(sentences.len(), 1);
+        match sentences[0] {
+            Sentence::Clause(ref clause) => {
+                assert_eq!(clause.head().to_string(),
+                           "f(_0, 'A b', \"str\", [1, 2.5|_1], -3, -(3), +(a, *(b, c)), :-(a, ;(','(b, c), d)))");
+                assert_eq!(clause.body(), &Term::atom("true"));
+            },
+            Sentence::Query(_) => panic!("expected a clause"),
+        }
+    }
+
+    #[test]
+    fn test_parse_sentences() {
+        let sentences = parse("% a comment\nfoo. bar(X) :- foo, X = 1.\n/* block */ ?- bar(Y).").unwrap();
+        assert_eq!(sentences.len(), 3);
+        match sentences[2] {
+            Sentence::Query(ref query) => assert_eq!(query.goal().to_string(), "bar(_0)"),
+            Sentence::Clause(_) => panic!("expected a query"),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse_error("foo(a, b.\n");
+        assert_eq!(err.span(), Span { line: 1, column: 9 });
+        assert_eq!(err.to_string(), "line 1, column 9: expected `)`, found `.`");
+
+        let err = parse_error("foo.\nbar :- 'baz");
+        assert_eq!(err.span(), Span { line: 2, column: 8 });
+        assert_eq!(err.message(), "unterminated quoted text");
+
+        let err = parse_error("foo.\n  bar baz.");
+        assert_eq!(err.span(), Span { line: 2, column: 7 });
+
+        let err = parse_error("X :- foo.");
+        assert_eq!(err.message(), "the head of a clause must be an atom or compound term");
+
+        let err = parse_error("a = b = c.");
+        assert_eq!(err.to_string(), "line 1, column 7: operator priority clash at `=`; try using parentheses");
+    }
+
+    #[test]
+    fn test_program() {
+        let mut p = Program::new();
+        p.consult("
+            parent(alice, bob).
+            parent(bob, carol).
+            parent(bob, dave).
+            ancestor(X, Y) :- parent(X, Y).
+            ancestor(X, Z) :- parent(X, Y), ancestor(Y, Z).
+            childless(X) :- parent(_, X), \\+ parent(X, _).
+        ").unwrap();
+
+        assert_eq!(answers(&p, "ancestor(alice, X)"), vec!["X = bob", "X = carol", "X = dave"]);
+        assert_eq!(answers(&p, "?- ancestor(X, dave)."), vec!["X = bob", "X = alice"]);
+        assert_eq!(answers(&p, "childless(X)"), vec!["X = carol", "X = dave"]);
+        assert!(answers(&p, "ancestor(carol, _)").is_empty());
+
+        let s = State::empty();
+        let (x, s) = s.make_var();
+        let (foreign, _) = State::<Term>::empty().make_var();
+        let query = |arg| Term::compound("ancestor", vec![Term::atom("alice"), arg]);
+        assert_eq!(p.goal(query(Term::Var(foreign))).try_eval(&s).err(), Some(Error::ForeignVar(foreign)));
+        assert_eq!(p.goal(query(Term::Var(x))).try_eval(&s).map(|r| r.len()), Ok(3));
+    }
+
+    #[test]
+    fn test_lists_and_arithmetic() {
+        let mut p = Program::new();
+        p.consult("
+            append([], L, L).
+            append([H|T], L, [H|R]) :- append(T, L, R).
+            length([], 0).
+            length([_|T], N) :- length(T, M), N is M + 1.
+            max(X, Y, Z) :- ( X >= Y -> Z = X ; Z = Y ).
+        ").unwrap();
+
+        assert_eq!(answers(&p, "append(X, Y, [1, 2])"),
+                   vec!["X = [], Y = [1, 2]", "X = [1], Y = [2]", "X = [1, 2], Y = []"]);
+        assert_eq!(answers(&p, "length([a, b, c], N)"), vec!["N = 3"]);
+        assert_eq!(answers(&p, "max(3, 7, M)"), vec!["M = 7"]);
+        assert_eq!(answers(&p, "X is 7 / 2, Y is 6 / 2, Z is 7 mod 3"), vec!["X = 3.5, Y = 3, Z = 1"]);
+        assert!(answers(&p, "1 + 1 =:= 3").is_empty());
+        assert!(answers(&p, "X is -9223372036854775807 - 1, Y is X / -1").is_empty());
+        assert!(answers(&p, "X is -9223372036854775807 - 1, Y is X // -1").is_empty());
+        assert!(answers(&p, "X is 9223372036854775807 * 2").is_empty());
+        assert_eq!(answers(&p, "X is 7 mod -3, Y is -7 mod 3, Z is -7 mod -3"), vec!["X = -2, Y = 2, Z = -1"]);
+        assert_eq!(answers(&p, "X is -9223372036854775807 - 1, Y is X mod -1"), vec!["X = -9223372036854775808, Y = 0"]);
+        assert!(answers(&p, "X is 1 mod 0").is_empty());
+        assert_eq!(answers(&p, "X is 2 ** -1, Y is 2 ** 3"), vec!["X = 0.5, Y = 8"]);
+        assert!(answers(&p, "X is 2 ^ -1").is_empty());
+    }
+
+    #[test]
+    fn test_cut() {
+        let mut p = Program::new();
+        p.consult("
+            p(1). p(2). p(3).
+            first(X) :- p(X), !.
+            max(X, Y, X) :- X >= Y, !.
+            max(_, Y, Y).
+            pairs(X, Y) :- p(X), !, p(Y).
+            none(X) :- p(X), X > 1, !, fail.
+            none(9).
+            some(0).
+            some(X) :- p(X), !.
+            some(4).
+            always :- !.
+            always :- fail.
+        ").unwrap();
+
+        assert_eq!(answers(&p, "first(X)"), vec!["X = 1"]);
+        assert_eq!(answers(&p, "max(3, 7, M)"), vec!["M = 7"]);
+        assert_eq!(answers(&p, "max(7, 3, M)"), vec!["M = 7"]);
+        assert_eq!(answers(&p, "pairs(X, Y)"), vec!["X = 1, Y = 1", "X = 1, Y = 2", "X = 1, Y = 3"]);
+        // once the cut is reached, failing afterwards does not try the next clause
+        assert!(answers(&p, "none(X)").is_empty());
+        // the cut does not affect the clauses before it
+        assert_eq!(answers(&p, "some(X)"), vec!["X = 0", "X = 1"]);
+        assert_eq!(answers(&p, "always"), vec![""]);
+
+        // outside a clause, a cut is local to the goal it is in
+        assert_eq!(answers(&p, "p(X), !"), vec!["X = 1"]);
+        assert_eq!(answers(&p, "findall(X, (p(X), !), L)"), vec!["X = _0, L = [1]"]);
+        assert_eq!(answers(&p, "\\+ (p(X), !, X > 1), p(Y), Y > 2"), vec!["X = _0, Y = 3"]);
+        assert_eq!(answers(&p, "G = !, (p(X), G)"), vec!["G = !, X = 1", "G = !, X = 2", "G = !, X = 3"]);
+        assert_eq!(answers(&p, "( p(X), ! -> Y = X ; Y = 0 )"), vec!["X = 1, Y = 1"]);
+
+        // cutting a clause from inside `;` or `->` is not supported
+        let err = parse_error("a :- ( b, ! ; c ).");
+        assert_eq!(err.message(), "a cut in a branch of `;` or `->` is not supported");
+        assert!(parse_error("a :- ( b -> ! ; c ).").message().contains("cut"));
+        assert!(parse_query("( p(X), ! ; true )").is_err());
+    }
+
+    #[test]
+    fn test_infinite_relation() {
+        let mut p = Program::new();
+        p.consult("nat(z). nat(s(X)) :- nat(X).").unwrap();
+
+        let q = parse_query("nat(N)").unwrap();
+        let (goal, vars, s) = q.instantiate(&State::empty());
+        let goal = p.goal(goal);
+        let found: Vec<String> = Search::new(&goal, &s).take(3).map(|s| Term::Var(vars[0].1).reify(&s).to_string()).collect();
+        assert_eq!(found, vec!["z", "s(z)", "s(s(z))"]);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut p = Program::new();
+        p.consult("
+            color(red).
+            color(green).
+            warm(X) :- color(X), X \\= green.
+        ").unwrap();
+        let tracer = Rc::new(PrettyTracer::new());
+        p.set_tracer(Some(tracer.clone()));
+
+        assert_eq!(answers(&p, "warm(X), X \\= red"), Vec::<String>::new());
+        assert_eq!(tracer.lines(), vec![
+            "Call: warm(_0)",
+            "  Call: color(_0)",
+            "  Exit: color(red)",
+            "  Call: \\=(red, green)",
+            "  Exit: \\=(red, green)",
+            "Exit: warm(red)",
+            "Call: \\=(red, red)",
+            "Fail: \\=(red, red)",
+            "  Redo: \\=(red, green)",
+            "  Fail: \\=(red, green)",
+            "  Redo: color(_0)",
+            "  Exit: color(green)",
+            "  Call: \\=(green, green)",
+            "  Fail: \\=(green, green)",
+            "  Redo: color(_0)",
+            "  Fail: color(_0)",
+            "Redo: warm(_0)",
+            "Fail: warm(_0)",
+        ]);
+    }
+
+    #[test]
+    fn test_aggregation() {
+        let mut p = Program::new();
+        p.consult("
+            age(peter, 7).
+            age(ann, 11).
+            age(pat, 8).
+            age(tom, 5).
+            age(mike, 11).
+            class(peter, a).
+            class(ann, b).
+            class(pat, a).
+            class(tom, b).
+            class(mike, b).
+        ").unwrap();
+
+        assert_eq!(answers(&p, "findall(N-A, age(N, A), L)"),
+                   vec!["N = _0, A = _1, L = [-(peter, 7), -(ann, 11), -(pat, 8), -(tom, 5), -(mike, 11)]"]);
+        assert_eq!(answers(&p, "findall(X, fail, L)"), vec!["X = _0, L = []"]);
+        assert_eq!(answers(&p, "setof(A, N^age(N, A), L)"), vec!["A = _0, N = _1, L = [5, 7, 8, 11]"]);
+        assert_eq!(answers(&p, "bagof(N, A^(class(N, C), age(N, A), A > 6), L)"),
+                   vec!["N = _0, A = _1, C = a, L = [peter, pat]", "N = _0, A = _1, C = b, L = [ann, mike]"]);
+        assert!(answers(&p, "bagof(N, age(N, 3), L)").is_empty());
+        assert_eq!(answers(&p, "aggregate_all(count, class(_, b), N)"), vec!["N = 3"]);
+        assert_eq!(answers(&p, "aggregate_all(sum(A), age(_, A), S), aggregate_all(max(A), age(_, A), M)"),
+                   vec!["A = _0, S = 42, M = 11"]);
+        assert_eq!(answers(&p, "aggregate_all(set(C), class(_, C), Cs)"), vec!["C = _0, Cs = [a, b]"]);
+        assert!(answers(&p, "aggregate_all(min(A), age(_, A), 3)").is_empty());
+    }
+}
@@ -0,0 +1,420 @@
+//! # Terms
+//!
+//! Structured values in the style of Prolog: atoms, numbers, strings and
+//! compound terms, any of which may contain logical variables.  Unifying two
+//! terms unifies their arguments, binding variables as needed.
+//!
+//! Lists are represented in the traditional way, as nested `'.'(Head, Tail)`
+//! compound terms ending in the atom `[]`.
+
+use error::Error;
+use state::{Unif, Reify, Rename, Var, State, PossibleStates};
+use store::{Store, Next};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// The name of the list constructor.
+pub const CONS: &str = ".";
+
+/// The name of the empty list.
+pub const NIL: &str = "[]";
+
+/// A Prolog-style term.
+#[derive(Clone)]
+pub enum Term {
+    Var(Var),
+    Atom(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Compound(String, Vec<Term>),
+}
+
+impl Term {
+    /// Creates an atom.
+    pub fn atom(name: &str) -> Term {
+        Term::Atom(name.to_string())
+    }
+
+    /// Creates a compound term, or an atom if there are no arguments.
+    pub fn compound(name: &str, args: Vec<Term>) -> Term {
+        if args.is_empty() {
+            Term::Atom(name.to_string())
+        } else {
+            Term::Compound(name.to_string(), args)
+        }
+    }
+
+    /// Creates a list of the items, ending with `tail` (or `[]` if `tail` is `None`).
+    pub fn list(items: Vec<Term>, tail: Option<Term>) -> Term {
+        items.into_iter().rev().fold(tail.unwrap_or_else(|| Term::atom(NIL)), |tail, item| {
+            Term::Compound(CONS.to_string(), vec![item, tail])
+        })
+    }
+
+    /// Returns the name and arity of an atom or compound term.
+    pub fn functor(&self) -> Option<(&str, usize)> {
+        match *self {
+            Term::Atom(ref name) => Some((name, 0)),
+            Term::Compound(ref name, ref args) => Some((name, args.len())),
+            _ => None,
+        }
+    }
+
+    /// Follows variable bindings in the state until reaching a term that is
+    /// not a bound variable.
+    pub fn walk<'a>(&'a self, state: &'a State<Term>) -> &'a Term {
+        let mut term = self;
+        while let Term::Var(ref var) = *term {
+            match state.get(var) {
+                Some(bound) => term = bound,
+                None => break,
+            }
+        }
+        term
+    }
+
+    /// Like `walk`, but returns an error instead of panicking if a variable
+    /// cannot be used with the state.
+    fn try_walk<'a>(&'a self, state: &'a State<Term>) -> Result<&'a Term, Error> {
+        let mut term = self;
+        while let Term::Var(ref var) = *term {
+            match state.try_get(var)? {
+                Some(bound) => term = bound,
+                None => break,
+            }
+        }
+        Ok(term)
+    }
+
+    /// Returns a copy of the term with all the bound variables it contains
+    /// replaced by their values in the state.
+    pub fn reify(&self, state: &State<Term>) -> Term {
+        match *self.walk(state) {
+            Term::Compound(ref name, ref args) =>
+                Term::Compound(name.clone(), args.iter().map(|arg| arg.reify(state)).collect()),
+            ref term => term.clone(),
+        }
+    }
+
+    /// Returns the variables in the term, in order of their first appearance.
+    pub fn vars(&self) -> Vec<Var> {
+        let mut vars = Vec::new();
+        self.map_vars(&mut |var| {
+            if !vars.contains(&var) {
+                vars.push(var);
+            }
+            Term::Var(var)
+        });
+        vars
+    }
+
+    /// Returns a copy of the term with each variable replaced by the result of `f`.
+    pub fn map_vars<F>(&self, f: &mut F) -> Term where F: FnMut(Var) -> Term {
+        match *self {
+            Term::Var(var) => f(var),
+            Term::Compound(ref name, ref args) =>
+                Term::Compound(name.clone(), args.iter().map(|arg| arg.map_vars(f)).collect()),
+            ref term => term.clone(),
+        }
+    }
+}
+
+impl PartialEq for Term {
+    fn eq(&self, other: &Term) -> bool {
+        match (self, other) {
+            (Term::Var(a), Term::Var(b)) => a == b,
+            (Term::Atom(a), Term::Atom(b)) => a == b,
+            (Term::Int(a), Term::Int(b)) => a == b,
+            (Term::Float(a), Term::Float(b)) => a.to_bits() == b.to_bits(),
+            (Term::Str(a), Term::Str(b)) => a == b,
+            (Term::Compound(f, a), Term::Compound(g, b)) => f == g && a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Term {}
+
+impl Hash for Term {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Term::Var(ref var) => { 0.hash(state); var.hash(state) },
+            Term::Atom(ref name) => { 1.hash(state); name.hash(state) },
+            Term::Int(n) => { 2.hash(state); n.hash(state) },
+            Term::Float(x) => { 3.hash(state); x.to_bits().hash(state) },
+            Term::Str(ref s) => { 4.hash(state); s.hash(state) },
+            Term::Compound(ref name, ref args) => { 5.hash(state); name.hash(state); args.hash(state) },
+        }
+    }
+}
+
+/// Terms are ordered in the standard order of Prolog: variables, then numbers
+/// (by value, with a float before an equal integer), then atoms, strings, and
+/// compound terms (by arity, then name, then arguments).
+impl Ord for Term {
+    fn cmp(&self, other: &Term) -> Ordering {
+        fn rank(term: &Term) -> u8 {
+            match *term {
+                Term::Var(_) => 0,
+                Term::Int(_) | Term::Float(_) => 1,
+                Term::Atom(_) => 2,
+                Term::Str(_) => 3,
+                Term::Compound(..) => 4,
+            }
+        }
+        match (self, other) {
+            (Term::Var(a), Term::Var(b)) => a.cmp(b),
+            (Term::Int(a), Term::Int(b)) => a.cmp(b),
+            (Term::Float(a), Term::Float(b)) => a.total_cmp(b),
+            (Term::Int(a), Term::Float(b)) => (*a as f64).total_cmp(b).then(Ordering::Greater),
+            (Term::Float(a), Term::Int(b)) => a.total_cmp(&(*b as f64)).then(Ordering::Less),
+            (Term::Atom(a), Term::Atom(b)) => a.cmp(b),
+            (Term::Str(a), Term::Str(b)) => a.cmp(b),
+            (Term::Compound(f, a), Term::Compound(g, b)) =>
+                a.len().cmp(&b.len()).then_with(|| f.cmp(g)).then_with(|| a.cmp(b)),
+            (a, b) => rank(a).cmp(&rank(b)),
+        }
+    }
+}
+
+impl PartialOrd for Term {
+    fn partial_cmp(&self, other: &Term) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Reify<Term> for Term {
+    fn reify(&self, state: &State<Term>) -> Term {
+        Term::reify(self, state)
+    }
+}
+
+impl Rename<Term> for Term {
+    fn rename(&self, f: &mut dyn FnMut(&Var) -> Var) -> Term {
+        self.map_vars(&mut |var| Term::Var(f(&var)))
+    }
+}
+
+impl Unif<Term> for Term {
+    fn unify(&self, other: &Term, prev: &State<Term>) -> PossibleStates<Term> {
+        match (self.walk(prev), other.walk(prev)) {
+            (Term::Var(a), Term::Var(b)) if a == b => vec![prev.clone()],
+            (Term::Var(a), b) => prev.unify_val(a, b.clone()),
+            (a, Term::Var(b)) => prev.unify_val(b, a.clone()),
+            (Term::Compound(f, a), Term::Compound(g, b)) => {
+                if f != g || a.len() != b.len() {
+                    return PossibleStates::new();
+                }
+                let mut states = vec![prev.clone()];
+                for (a, b) in a.iter().zip(b.iter()) {
+                    states = states.iter().flat_map(|s| a.unify(b, s)).collect();
+                }
+                states
+            },
+            (a, b) => if a == b { vec![prev.clone()] } else { PossibleStates::new() },
+        }
+    }
+
+    fn try_unify(&self, other: &Term, prev: &State<Term>) -> Result<PossibleStates<Term>, Error> {
+        match (self.try_walk(prev)?, other.try_walk(prev)?) {
+            (Term::Var(a), Term::Var(b)) if a == b => Ok(vec![prev.clone()]),
+            (Term::Var(a), b) => prev.try_unify_val(a, b.clone()),
+            (a, Term::Var(b)) => prev.try_unify_val(b, a.clone()),
+            (Term::Compound(f, a), Term::Compound(g, b)) => {
+                if f != g || a.len() != b.len() {
+                    return Ok(PossibleStates::new());
+                }
+                let mut states = vec![prev.clone()];
+                for (a, b) in a.iter().zip(b.iter()) {
+                    let mut next = PossibleStates::new();
+                    for s in &states {
+                        next.append(&mut a.try_unify(b, s)?);
+                    }
+                    states = next;
+                }
+                Ok(states)
+            },
+            (a, b) => Ok(if a == b { vec![prev.clone()] } else { PossibleStates::new() }),
+        }
+    }
+
+    fn unify_in(&self, other: &Term, store: &mut dyn Store<Term>, next: &mut Next<Term>) -> bool {
+        if let Some(bound) = value_in(self, store) {
+            return bound.unify_in(other, store, next);
+        }
+        if let Some(bound) = value_in(other, store) {
+            return self.unify_in(&bound, store, next);
+        }
+        match (self, other) {
+            (Term::Var(a), Term::Var(b)) => store.unify_vars(a, b, next),
+            (Term::Var(a), b) | (b, Term::Var(a)) => store.unify_val(a, b.clone(), next),
+            (Term::Compound(f, a), Term::Compound(g, b)) =>
+                f == g && a.len() == b.len() && unify_args(a, b, store, next),
+            (a, b) => a == b && next(store),
+        }
+    }
+
+    fn each_var(&self, f: &mut dyn FnMut(&Var)) -> bool {
+        match *self {
+            Term::Var(ref var) => f(var),
+            Term::Compound(_, ref args) => args.iter().for_each(|arg| { arg.each_var(f); }),
+            _ => {},
+        }
+        true
+    }
+}
+
+/// Returns a copy of the value of the term in the store, if it is a bound
+/// variable.  Copying the value lets the store be changed while unifying it.
+fn value_in(term: &Term, store: &dyn Store<Term>) -> Option<Term> {
+    match *term {
+        Term::Var(ref var) => store.get(var).cloned(),
+        _ => None,
+    }
+}
+
+/// Unifies the arguments of two compound terms in turn, in the store.
+fn unify_args(a: &[Term], b: &[Term], store: &mut dyn Store<Term>, next: &mut Next<Term>) -> bool {
+    match (a.split_first(), b.split_first()) {
+        (Some((x, a)), Some((y, b))) => x.unify_in(y, store, &mut |store: &mut dyn Store<Term>| unify_args(a, b, store, next)),
+        _ => next(store),
+    }
+}
+
+impl From<i64> for Term {
+    fn from(n: i64) -> Term {
+        Term::Int(n)
+    }
+}
+
+impl From<f64> for Term {
+    fn from(x: f64) -> Term {
+        Term::Float(x)
+    }
+}
+
+impl From<Var> for Term {
+    fn from(var: Var) -> Term {
+        Term::Var(var)
+    }
+}
+
+/// Returns `true` if the atom can be written without quotes.
+fn is_plain_atom(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_lowercase() => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        Some(_) => name == NIL || name == "!" || name == ";" || name.chars().all(is_symbol_char),
+        None => false,
+    }
+}
+
+/// Returns `true` for the characters that make up symbolic atoms such as `:-` or `\=`.
+pub(crate) fn is_symbol_char(c: char) -> bool {
+    "+-*/\\^<>=~:.?@#&$".contains(c)
+}
+
+fn write_atom(f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    if is_plain_atom(name) {
+        write!(f, "{}", name)
+    } else {
+        write!(f, "'{}'", name.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Term::Var(ref var) => write!(f, "{}", var),
+            Term::Atom(ref name) => write_atom(f, name),
+            Term::Int(n) => write!(f, "{}", n),
+            Term::Float(x) => write!(f, "{:?}", x),
+            Term::Str(ref s) => write!(f, "{:?}", s),
+            Term::Compound(ref name, ref args) if name == CONS && args.len() == 2 => {
+                write!(f, "[{}", args[0])?;
+                let mut tail = &args[1];
+                while let Term::Compound(ref name, ref args) = *tail {
+                    if name != CONS || args.len() != 2 {
+                        break;
+                    }
+                    write!(f, ", {}", args[0])?;
+                    tail = &args[1];
+                }
+                match *tail {
+                    Term::Atom(ref name) if name == NIL => write!(f, "]"),
+                    ref tail => write!(f, "|{}]", tail),
+                }
+            },
+            Term::Compound(ref name, ref args) => {
+                write_atom(f, name)?;
+                write!(f, "(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use state::{State, Unif};
+    use super::Term;
+
+    #[test]
+    fn test_unify_compound() {
+        let s = State::<Term>::empty();
+        let (x, s) = s.make_var();
+        let (y, s) = s.make_var();
+
+        let a = Term::compound("f", vec![Term::Var(x), Term::atom("b")]);
+        let b = Term::compound("f", vec![Term::atom("a"), Term::Var(y)]);
+        let results = a.unify(&b, &s);
+        assert_eq!(results.len(), 1);
+        assert_eq!(Term::Var(x).reify(&results[0]), Term::atom("a"));
+        assert_eq!(Term::Var(y).reify(&results[0]), Term::atom("b"));
+
+        let c = Term::compound("g", vec![Term::atom("a"), Term::Var(y)]);
+        assert_eq!(a.unify(&c, &s).len(), 0);
+    }
+
+    #[test]
+    fn test_unify_chain() {
+        let s = State::<Term>::empty();
+        let (x, s) = s.make_var();
+        let (y, s) = s.make_var();
+        let (z, s) = s.make_var();
+
+        let s = Term::Var(x).unify(&Term::Var(y), &s).pop().unwrap();
+        let s = Term::Var(z).unify(&Term::Var(y), &s).pop().unwrap();
+        let s = Term::Var(z).unify(&Term::Int(7), &s).pop().unwrap();
+        assert_eq!(Term::Var(x).reify(&s), Term::Int(7));
+        assert_eq!(Term::Var(x).unify(&Term::Int(8), &s).len(), 0);
+    }
+
+    #[test]
+    fn test_display() {
+        let t = Term::compound("point", vec![Term::Int(1), Term::Float(2.5), Term::atom("Hello world")]);
+        assert_eq!(t.to_string(), "point(1, 2.5, 'Hello world')");
+
+        let l = Term::list(vec![Term::Int(1), Term::Int(2)], None);
+        assert_eq!(l.to_string(), "[1, 2]");
+        let l = Term::list(vec![Term::Int(1)], Some(Term::atom("t")));
+        assert_eq!(l.to_string(), "[1|t]");
+        assert_eq!(Term::Str("hi".to_string()).to_string(), "\"hi\"");
+        assert_eq!(Term::atom("[]").to_string(), "[]");
+    }
+}
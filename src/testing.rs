@@ -0,0 +1,17 @@
+//! Goals shared by the tests of several modules.
+
+use goal::{Goal, Branch, Subgoal};
+use state::{State, PossibleStates};
+
+/// Never succeeds, and never fails either.
+pub struct Never;
+
+impl Goal<i32> for Never {
+    fn eval(&self, _state: &State<i32>) -> PossibleStates<i32> {
+        panic!("never ends")
+    }
+
+    fn expand<'a>(&'a self, state: &State<i32>) -> Vec<Branch<'a, i32>> {
+        vec![Branch::new(state.clone(), vec![Subgoal::Borrowed(self)])]
+    }
+}